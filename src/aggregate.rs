@@ -0,0 +1,302 @@
+//! Windowed summaries of readings: count, min, max, mean, stddev and p95.
+//!
+//! Windows are aligned to multiples of the slide on the readings' own clock,
+//! so every instance in a fleet closes its windows at the same wall time and
+//! replayed traces are summarized by their recorded timestamps. A window is
+//! summarized by the first reading past its end, and partial windows are
+//! flushed when the run ends or a channel's clock changes, e.g. when the
+//! wall clock gets set mid-run. NaN and infinite values are left out.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::clock::Timestamp;
+use crate::error::{Error, Result};
+use crate::output::Record;
+use crate::reading::{Quantity, Reading, Tags};
+
+/// Statistics over one window of one sensor channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub sensor: String,
+    pub quantity: Quantity,
+    pub unit: String,
+    pub tags: Tags,
+    /// Window bounds, on the same clock as the readings.
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Population standard deviation.
+    pub stddev: f64,
+    /// Nearest-rank 95th percentile.
+    pub p95: f64,
+}
+
+/// Tumbling (`slide == window`) or sliding windows over every channel.
+pub struct Aggregator {
+    window: Duration,
+    slide: Duration,
+    /// Time base for readings without a clock.
+    started: Instant,
+    series: Vec<Series>,
+}
+
+struct Series {
+    /// Metadata of the channel, from its first reading.
+    channel: Reading,
+    /// Shape of the channel's timestamps, reused for the window bounds.
+    clock: Timestamp,
+    next_end: Duration,
+    samples: VecDeque<(Duration, f64)>,
+}
+
+impl Aggregator {
+    /// Windows of `window`, one closing every `slide` (default: `window`).
+    /// A slide longer than the window would leave readings out of every
+    /// window, so it is rejected.
+    pub fn new(window: Duration, slide: Option<Duration>) -> Result<Self> {
+        let slide = slide.unwrap_or(window);
+        if slide > window {
+            let reason = format!("the slide of {} ms is longer than the window of {} ms", slide.as_millis(), window.as_millis());
+            return Err(Error::Usage(reason));
+        }
+        Ok(Aggregator { window, slide, started: Instant::now(), series: Vec::new() })
+    }
+
+    /// Add one iteration's records; returns the summaries of windows they closed.
+    pub fn push(&mut self, records: &[Record]) -> Vec<Summary> {
+        let mut summaries = Vec::new();
+        for record in records.iter().filter(|record| record.reading.value.is_finite()) {
+            let time = match record.timestamp {
+                Timestamp::Wall(time) | Timestamp::Monotonic(time) => time,
+                Timestamp::Unsynced => self.started.elapsed(),
+            };
+            let reading = record.reading;
+            let index = match self.series.iter().position(|series| series.is(reading)) {
+                Some(index) if mem::discriminant(&self.series[index].clock) == mem::discriminant(&record.timestamp) => index,
+                found => {
+                    // Times on another clock cannot share a window.
+                    if let Some(index) = found {
+                        summaries.extend(self.series.remove(index).summarize(self.window));
+                    }
+                    self.series.push(Series {
+                        channel: reading.clone(),
+                        clock: record.timestamp,
+                        next_end: align(time, self.slide) + self.slide,
+                        samples: VecDeque::new(),
+                    });
+                    self.series.len() - 1
+                }
+            };
+            let series = &mut self.series[index];
+            while time >= series.next_end {
+                summaries.extend(series.summarize(self.window));
+                series.next_end += self.slide;
+                let start = series.next_end.saturating_sub(self.window);
+                series.samples.retain(|&(at, _)| at >= start);
+                if series.samples.is_empty() && time >= series.next_end {
+                    // Skip the empty windows of a gap.
+                    series.next_end = align(time, self.slide) + self.slide;
+                }
+            }
+            series.samples.push_back((time, reading.value));
+        }
+        summaries
+    }
+
+    /// Summaries of the windows still open, e.g. when the run ends.
+    pub fn finish(&mut self) -> Vec<Summary> {
+        let summaries = self.series.iter().filter_map(|series| series.summarize(self.window)).collect();
+        self.series.clear();
+        summaries
+    }
+}
+
+impl Series {
+    fn is(&self, reading: &Reading) -> bool {
+        self.channel.sensor == reading.sensor && self.channel.unit == reading.unit && self.channel.tags == reading.tags
+    }
+
+    /// Summary of the window ending at `next_end`, if it has any samples.
+    fn summarize(&self, window: Duration) -> Option<Summary> {
+        let start = self.next_end.saturating_sub(window);
+        let mut values: Vec<f64> = self.samples.iter()
+            .filter(|&&(at, _)| at >= start && at < self.next_end)
+            .map(|&(_, value)| value)
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / count as f64;
+        let rank = (count as f64 * 0.95).ceil() as usize;
+        Some(Summary {
+            sensor: self.channel.sensor.clone(),
+            quantity: self.channel.quantity,
+            unit: self.channel.unit.clone(),
+            tags: self.channel.tags.clone(),
+            start: at(self.clock, start),
+            end: at(self.clock, self.next_end),
+            count,
+            min: values[0],
+            max: values[count - 1],
+            mean,
+            stddev: variance.sqrt(),
+            p95: values[rank.max(1) - 1],
+        })
+    }
+}
+
+/// Start of the slide containing `time`.
+fn align(time: Duration, slide: Duration) -> Duration {
+    let slide = slide.as_nanos().max(1);
+    Duration::from_nanos((time.as_nanos() / slide * slide) as u64)
+}
+
+/// `time` on the same clock as `like`.
+fn at(like: Timestamp, time: Duration) -> Timestamp {
+    match like {
+        Timestamp::Wall(_) => Timestamp::Wall(time),
+        Timestamp::Monotonic(_) => Timestamp::Monotonic(time),
+        Timestamp::Unsynced => Timestamp::Unsynced,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::Channel;
+
+    const START: u64 = 1_770_656_082;
+
+    /// The demo run: 23, 26, 29, 32, 35 °C every 2 s.
+    fn demo() -> Vec<(Reading, Timestamp)> {
+        let channel = Channel::new("temperature", "°C");
+        (0..5u64).map(|i| {
+            (channel.reading(23.0 + 3.0 * i as f64), Timestamp::Wall(Duration::from_secs(START + 2 * i)))
+        }).collect()
+    }
+
+    fn run(aggregator: &mut Aggregator, readings: &[(Reading, Timestamp)]) -> Vec<Summary> {
+        let mut summaries = Vec::new();
+        for (reading, timestamp) in readings {
+            summaries.extend(aggregator.push(&[Record { seq: 1, reading, timestamp: *timestamp }]));
+        }
+        summaries.extend(aggregator.finish());
+        summaries
+    }
+
+    fn wall(secs: u64) -> Timestamp {
+        Timestamp::Wall(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn computes_statistics() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60), None).unwrap();
+        let summaries = run(&mut aggregator, &demo());
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!((summary.count, summary.min, summary.max, summary.mean, summary.p95), (5, 23.0, 35.0, 29.0, 35.0));
+        assert!((summary.stddev - 18f64.sqrt()).abs() < 1e-12);
+        assert_eq!((summary.start, summary.end), (wall(1_770_656_040), wall(1_770_656_100)));
+    }
+
+    #[test]
+    fn tumbling_windows_are_aligned_and_disjoint() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(4), None).unwrap();
+        let summaries: Vec<_> = run(&mut aggregator, &demo()).iter()
+            .map(|summary| (summary.start, summary.count, summary.mean))
+            .collect();
+        // 082 and 086 start new windows at 080 and 084; 090 is flushed by finish.
+        assert_eq!(summaries, [
+            (wall(START - 2), 1, 23.0),
+            (wall(START + 2), 2, 27.5),
+            (wall(START + 6), 2, 33.5),
+        ]);
+    }
+
+    #[test]
+    fn sliding_windows_overlap() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(6), Some(Duration::from_secs(2))).unwrap();
+        let summaries: Vec<_> = run(&mut aggregator, &demo()).iter()
+            .map(|summary| (summary.end, summary.count, summary.max))
+            .collect();
+        assert_eq!(summaries, [
+            (wall(START + 2), 1, 23.0),
+            (wall(START + 4), 2, 26.0),
+            (wall(START + 6), 3, 29.0),
+            (wall(START + 8), 3, 32.0),
+            (wall(START + 10), 3, 35.0),
+        ]);
+    }
+
+    #[test]
+    fn p95_uses_nearest_rank() {
+        let channel = Channel::new("temperature", "°C");
+        let readings: Vec<_> = (1..=40).map(|i| (channel.reading(i as f64), wall(START))).collect();
+        let mut aggregator = Aggregator::new(Duration::from_secs(60), None).unwrap();
+        assert_eq!(run(&mut aggregator, &readings)[0].p95, 38.0);
+    }
+
+    #[test]
+    fn rejects_a_slide_longer_than_the_window() {
+        let err = Aggregator::new(Duration::from_secs(10), Some(Duration::from_secs(15))).err().unwrap();
+        assert!(matches!(err, Error::Usage(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "the slide of 15000 ms is longer than the window of 10000 ms");
+        assert!(Aggregator::new(Duration::from_secs(10), Some(Duration::from_secs(10))).is_ok());
+    }
+
+    #[test]
+    fn skips_gaps_and_keeps_channels_apart() {
+        let temperature = Channel::new("temperature", "°C");
+        let humidity = Channel::new("humidity", "%");
+        let readings = [
+            (temperature.reading(20.0), wall(1_000)),
+            (humidity.reading(40.0), wall(1_000)),
+            (temperature.reading(22.0), wall(5_000)),
+        ];
+        let mut aggregator = Aggregator::new(Duration::from_secs(10), None).unwrap();
+        let summaries: Vec<_> = run(&mut aggregator, &readings).iter()
+            .map(|summary| (summary.sensor.clone(), summary.start, summary.mean))
+            .collect();
+        assert_eq!(summaries, [
+            ("temperature".to_string(), wall(1_000), 20.0),
+            ("temperature".to_string(), wall(5_000), 22.0),
+            ("humidity".to_string(), wall(1_000), 40.0),
+        ]);
+    }
+
+    #[test]
+    fn skips_non_finite_values() {
+        let channel = Channel::new("temperature", "°C");
+        let readings: Vec<_> = [20.0, f64::NAN, f64::INFINITY, 22.0].iter().map(|&value| (channel.reading(value), wall(START))).collect();
+        let mut aggregator = Aggregator::new(Duration::from_secs(60), None).unwrap();
+        let summaries = run(&mut aggregator, &readings);
+        assert_eq!((summaries[0].count, summaries[0].mean, summaries[0].max), (2, 21.0, 22.0));
+    }
+
+    #[test]
+    fn flushes_a_channel_when_its_clock_changes() {
+        let channel = Channel::new("temperature", "°C");
+        let readings = [
+            (channel.reading(20.0), Timestamp::Monotonic(Duration::from_secs(2))),
+            (channel.reading(21.0), Timestamp::Monotonic(Duration::from_secs(4))),
+            (channel.reading(30.0), wall(START)),
+            (channel.reading(32.0), wall(START + 2)),
+        ];
+        let mut aggregator = Aggregator::new(Duration::from_secs(60), None).unwrap();
+        let summaries: Vec<_> = run(&mut aggregator, &readings).iter()
+            .map(|summary| (summary.start, summary.count, summary.mean))
+            .collect();
+        assert_eq!(summaries, [
+            (Timestamp::Monotonic(Duration::ZERO), 2, 20.5),
+            (wall(1_770_656_040), 2, 31.0),
+        ]);
+    }
+}
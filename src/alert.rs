@@ -0,0 +1,309 @@
+//! Threshold alert rules with hysteresis, evaluated on every reading.
+//!
+//! A rule is raised once its threshold is crossed for `samples` consecutive
+//! readings and cleared once the value is back past `clear` for
+//! `clear_samples` readings. Each transition produces one [`AlertEvent`].
+
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::clock::Timestamp;
+use crate::output::Record;
+use crate::reading::Quality;
+
+/// One `[[alerts]]` entry in the configuration file.
+///
+/// ```toml
+/// [[alerts]]
+/// name = "overheat"
+/// sensor = "temperature"
+/// above = 30.0
+/// clear = 28.0
+/// samples = 3
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RuleConfig")]
+pub struct AlertRule {
+    pub name: String,
+    pub sensor: String,
+    pub condition: Condition,
+    pub threshold: f64,
+    /// Level the value must return past to clear; the threshold by default.
+    pub clear: f64,
+    /// Consecutive readings past the threshold that raise the alert.
+    pub samples: u32,
+    /// Consecutive readings past `clear` that clear it.
+    pub clear_samples: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Above,
+    Below,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    name: String,
+    sensor: String,
+    above: Option<f64>,
+    below: Option<f64>,
+    clear: Option<f64>,
+    #[serde(default = "one")]
+    samples: u32,
+    #[serde(default = "one")]
+    clear_samples: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl TryFrom<RuleConfig> for AlertRule {
+    type Error = String;
+
+    fn try_from(config: RuleConfig) -> Result<Self, Self::Error> {
+        let (condition, threshold) = match (config.above, config.below) {
+            (Some(above), None) => (Condition::Above, above),
+            (None, Some(below)) => (Condition::Below, below),
+            _ => return Err(format!("alert '{}' needs exactly one of 'above' or 'below'", config.name)),
+        };
+        let clear = config.clear.unwrap_or(threshold);
+        let backwards = match condition {
+            Condition::Above => clear > threshold,
+            Condition::Below => clear < threshold,
+        };
+        if backwards {
+            return Err(format!("alert '{}' clears at {} on the wrong side of its threshold {}", config.name, clear, threshold));
+        }
+        if config.samples == 0 || config.clear_samples == 0 {
+            return Err(format!("alert '{}' needs at least one sample to change state", config.name));
+        }
+        Ok(AlertRule {
+            name: config.name,
+            sensor: config.sensor,
+            condition,
+            threshold,
+            clear,
+            samples: config.samples,
+            clear_samples: config.clear_samples,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Condition::Above => "above",
+            Condition::Below => "below",
+        })
+    }
+}
+
+impl AlertRule {
+    fn crossed(&self, value: f64) -> bool {
+        match self.condition {
+            Condition::Above => value > self.threshold,
+            Condition::Below => value < self.threshold,
+        }
+    }
+
+    fn recovered(&self, value: f64) -> bool {
+        match self.condition {
+            Condition::Above => value <= self.clear,
+            Condition::Below => value >= self.clear,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Raised,
+    Cleared,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transition::Raised => "raised",
+            Transition::Cleared => "cleared",
+        })
+    }
+}
+
+/// An alert changing state on a reading.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    pub transition: Transition,
+    pub alert: String,
+    pub sensor: String,
+    pub condition: Condition,
+    pub threshold: f64,
+    /// The reading that caused the transition.
+    pub value: f64,
+    pub unit: String,
+    pub seq: u64,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct State {
+    active: bool,
+    /// Consecutive readings towards the opposite state.
+    streak: u32,
+}
+
+/// Alert state for every rule.
+pub struct Alerts {
+    rules: Vec<(AlertRule, State)>,
+}
+
+impl Alerts {
+    pub fn new(rules: &[AlertRule]) -> Self {
+        Alerts { rules: rules.iter().map(|rule| (rule.clone(), State::default())).collect() }
+    }
+
+    /// Feed one iteration's records; returns the alerts that changed state.
+    /// Readings of bad quality are ignored.
+    pub fn evaluate(&mut self, records: &[Record]) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        for record in records {
+            let reading = record.reading;
+            if reading.quality == Quality::Bad {
+                continue;
+            }
+            for (rule, state) in self.rules.iter_mut().filter(|(rule, _)| rule.sensor == reading.sensor) {
+                let (towards, needed) = if state.active {
+                    (rule.recovered(reading.value), rule.clear_samples)
+                } else {
+                    (rule.crossed(reading.value), rule.samples)
+                };
+                state.streak = if towards { state.streak + 1 } else { 0 };
+                if state.streak < needed {
+                    continue;
+                }
+                state.active = !state.active;
+                state.streak = 0;
+                events.push(AlertEvent {
+                    transition: if state.active { Transition::Raised } else { Transition::Cleared },
+                    alert: rule.name.clone(),
+                    sensor: reading.sensor.clone(),
+                    condition: rule.condition,
+                    threshold: rule.threshold,
+                    value: reading.value,
+                    unit: reading.unit.clone(),
+                    seq: record.seq,
+                    timestamp: record.timestamp,
+                });
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::{Channel, Reading};
+
+    fn rule(toml: &str) -> AlertRule {
+        #[derive(Deserialize)]
+        struct File {
+            alerts: Vec<AlertRule>,
+        }
+        let file: File = toml::from_str(&format!("[[alerts]]\nname = \"overheat\"\nsensor = \"temperature\"\n{}", toml)).unwrap();
+        file.alerts.into_iter().next().unwrap()
+    }
+
+    /// Feed `values` one per iteration; returns `(seq, transition, value)` per event.
+    fn run(rule: AlertRule, values: &[f64]) -> Vec<(u64, Transition, f64)> {
+        let channel = Channel::new("temperature", "°C");
+        let mut alerts = Alerts::new(&[rule]);
+        let mut events = Vec::new();
+        for (seq, &value) in (1..).zip(values) {
+            let reading = channel.reading(value);
+            let record = Record { seq, reading: &reading, timestamp: Timestamp::Unsynced };
+            events.extend(alerts.evaluate(&[record]).into_iter().map(|event| (event.seq, event.transition, event.value)));
+        }
+        events
+    }
+
+    const DEMO: [f64; 5] = [23.0, 26.0, 29.0, 32.0, 35.0];
+
+    #[test]
+    fn demo_run_raises_once_the_streak_is_long_enough() {
+        assert_eq!(run(rule("above = 30.0"), &DEMO), [(4, Transition::Raised, 32.0)]);
+        assert_eq!(run(rule("above = 30.0\nsamples = 2"), &DEMO), [(5, Transition::Raised, 35.0)]);
+        assert_eq!(run(rule("above = 30.0\nsamples = 3"), &DEMO), []);
+        assert_eq!(run(rule("above = 25.0\nsamples = 3"), &DEMO), [(4, Transition::Raised, 32.0)]);
+    }
+
+    #[test]
+    fn a_dip_restarts_the_streak() {
+        let values = [31.0, 32.0, 29.0, 31.0, 32.0, 33.0];
+        assert_eq!(run(rule("above = 30.0\nsamples = 3"), &values), [(6, Transition::Raised, 33.0)]);
+    }
+
+    #[test]
+    fn hysteresis_holds_the_alert_until_clear() {
+        let values = [32.0, 29.5, 31.0, 28.5, 27.5, 31.0];
+        assert_eq!(run(rule("above = 30.0\nclear = 28.0"), &values), [
+            (1, Transition::Raised, 32.0),
+            (5, Transition::Cleared, 27.5),
+            (6, Transition::Raised, 31.0),
+        ]);
+        // Without `clear` the threshold itself clears it.
+        assert_eq!(run(rule("above = 30.0"), &values), [
+            (1, Transition::Raised, 32.0),
+            (2, Transition::Cleared, 29.5),
+            (3, Transition::Raised, 31.0),
+            (4, Transition::Cleared, 28.5),
+            (6, Transition::Raised, 31.0),
+        ]);
+    }
+
+    #[test]
+    fn clear_samples_debounce_clearing() {
+        let values = [32.0, 27.0, 31.0, 27.0, 26.0];
+        assert_eq!(run(rule("above = 30.0\nclear = 28.0\nclear_samples = 2"), &values), [
+            (1, Transition::Raised, 32.0),
+            (5, Transition::Cleared, 26.0),
+        ]);
+    }
+
+    #[test]
+    fn below_rules_mirror_above_rules() {
+        let values = [5.0, 1.0, -1.0, 1.5, 3.0];
+        assert_eq!(run(rule("below = 2.0\nclear = 2.5"), &values), [
+            (2, Transition::Raised, 1.0),
+            (5, Transition::Cleared, 3.0),
+        ]);
+    }
+
+    #[test]
+    fn ignores_other_sensors_and_bad_readings() {
+        let mut alerts = Alerts::new(&[rule("above = 30.0")]);
+        let humidity = Channel::new("humidity", "%").reading(90.0);
+        let bad = Reading { quality: Quality::Bad, ..Channel::new("temperature", "°C").reading(99.0) };
+        let records = [&humidity, &bad].map(|reading| Record { seq: 1, reading, timestamp: Timestamp::Unsynced });
+        assert!(alerts.evaluate(&records).is_empty());
+    }
+
+    #[test]
+    fn rejects_inconsistent_rules() {
+        let cases = [
+            ("", "exactly one of 'above' or 'below'"),
+            ("above = 30.0\nbelow = 10.0", "exactly one of 'above' or 'below'"),
+            ("above = 30.0\nclear = 31.0", "clears at 31 on the wrong side of its threshold 30"),
+            ("below = 2.0\nclear = 1.0", "clears at 1 on the wrong side of its threshold 2"),
+            ("above = 30.0\nsamples = 0", "needs at least one sample"),
+        ];
+        for (toml, expected) in cases {
+            let text = format!("name = \"overheat\"\nsensor = \"temperature\"\n{}", toml);
+            let err = toml::from_str::<AlertRule>(&text).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", toml, err);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! Streaming anomaly detectors that annotate readings with a score and flag.
+//!
+//! Each detector keeps a fixed amount of state per channel (the rolling
+//! z-score a ring buffer of `window` values), so memory does not grow with
+//! the length of the run.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::error::Result;
+use crate::reading::{Anomaly, Reading};
+use crate::sensor::Sensor;
+
+/// The `anomaly` key of a `[[sensors]]` entry.
+///
+/// ```toml
+/// anomaly = { method = "ewma", alpha = 0.1, limit = 3.0 }
+/// anomaly = { method = "zscore", window = 30, limit = 3.0 }
+/// anomaly = { method = "rate", limit = 2.5 }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Detector {
+    /// Distance from an exponentially weighted moving average, in exponentially
+    /// weighted standard deviations. Scores start after `1 / alpha` readings.
+    Ewma {
+        #[serde(default = "default_alpha", deserialize_with = "alpha")]
+        alpha: f64,
+        #[serde(default = "default_limit", deserialize_with = "limit")]
+        limit: f64,
+    },
+    /// Distance from the mean of the previous `window` readings, in standard
+    /// deviations. Scores start once the window is full.
+    Zscore {
+        #[serde(default = "default_window", deserialize_with = "window")]
+        window: usize,
+        #[serde(default = "default_limit", deserialize_with = "limit")]
+        limit: f64,
+    },
+    /// Absolute change since the previous reading; flagged above `limit`.
+    Rate {
+        #[serde(deserialize_with = "limit")]
+        limit: f64,
+    },
+}
+
+fn default_alpha() -> f64 {
+    0.1
+}
+
+fn default_limit() -> f64 {
+    3.0
+}
+
+fn default_window() -> usize {
+    30
+}
+
+/// Largest z-score window; every reading is scored against all of it.
+pub const MAX_WINDOW: usize = 1000;
+
+fn alpha<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<f64, D::Error> {
+    let alpha = f64::deserialize(deserializer)?;
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        return Err(serde::de::Error::custom("alpha must be greater than 0 and at most 1"));
+    }
+    Ok(alpha)
+}
+
+fn limit<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<f64, D::Error> {
+    let limit = f64::deserialize(deserializer)?;
+    if !(limit.is_finite() && limit > 0.0) {
+        return Err(serde::de::Error::custom("limit must be a finite number greater than 0"));
+    }
+    Ok(limit)
+}
+
+fn window<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<usize, D::Error> {
+    let window = usize::deserialize(deserializer)?;
+    if !(2..=MAX_WINDOW).contains(&window) {
+        return Err(serde::de::Error::custom(format!("window must be from 2 to {} readings", MAX_WINDOW)));
+    }
+    Ok(window)
+}
+
+impl Detector {
+    fn limit(self) -> f64 {
+        match self {
+            Detector::Ewma { limit, .. } | Detector::Zscore { limit, .. } | Detector::Rate { limit } => limit,
+        }
+    }
+}
+
+/// Per-channel detector state.
+#[derive(Debug, Clone)]
+enum State {
+    Ewma { mean: f64, variance: f64, count: usize },
+    Zscore { values: VecDeque<f64> },
+    Rate { last: Option<f64> },
+}
+
+impl State {
+    fn new(detector: Detector) -> Self {
+        match detector {
+            Detector::Ewma { .. } => State::Ewma { mean: 0.0, variance: 0.0, count: 0 },
+            Detector::Zscore { window, .. } => State::Zscore { values: VecDeque::with_capacity(window) },
+            Detector::Rate { .. } => State::Rate { last: None },
+        }
+    }
+
+    /// Score `value` against the history so far, then add it to the history.
+    /// NaN and infinities are neither scored nor remembered.
+    fn score(&mut self, detector: Detector, value: f64) -> Option<f64> {
+        if !value.is_finite() {
+            return None;
+        }
+        match (self, detector) {
+            (State::Ewma { mean, variance, count }, Detector::Ewma { alpha, .. }) => {
+                *count += 1;
+                if *count == 1 {
+                    *mean = value;
+                    return None;
+                }
+                let deviation = value - *mean;
+                let warm = (*count as f64) > 1.0 / alpha && *variance > 0.0;
+                let score = warm.then(|| deviation.abs() / variance.sqrt());
+                *mean += alpha * deviation;
+                *variance = (1.0 - alpha) * (*variance + alpha * deviation * deviation);
+                score
+            }
+            // Recomputed over the window rather than from running sums, which
+            // lose the variance to cancellation once the level is large.
+            (State::Zscore { values }, Detector::Zscore { window, .. }) => {
+                let score = (values.len() == window).then(|| {
+                    let n = window as f64;
+                    let mean = values.iter().sum::<f64>() / n;
+                    let stddev = (values.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n).sqrt();
+                    if stddev > 0.0 { (value - mean).abs() / stddev } else { 0.0 }
+                });
+                if values.len() == window {
+                    values.pop_front();
+                }
+                values.push_back(value);
+                score
+            }
+            (State::Rate { last }, Detector::Rate { .. }) => last.replace(value).map(|last| (value - last).abs()),
+            _ => unreachable!("state is created from its detector"),
+        }
+    }
+}
+
+/// A sensor whose readings are annotated by a [`Detector`].
+pub struct Monitored {
+    inner: Box<dyn Sensor>,
+    detector: Detector,
+    /// State per channel of a multi-channel sensor.
+    channels: Vec<(String, State)>,
+}
+
+impl Monitored {
+    pub fn new(inner: Box<dyn Sensor>, detector: Detector) -> Self {
+        Monitored { inner, detector, channels: Vec::new() }
+    }
+
+    fn annotate(&mut self, reading: &mut Reading) {
+        let index = match self.channels.iter().position(|(sensor, _)| *sensor == reading.sensor) {
+            Some(index) => index,
+            None => {
+                self.channels.push((reading.sensor.clone(), State::new(self.detector)));
+                self.channels.len() - 1
+            }
+        };
+        let limit = self.detector.limit();
+        reading.anomaly = self.channels[index].1.score(self.detector, reading.value)
+            .map(|score| Anomaly { score, flagged: score > limit });
+    }
+}
+
+impl Sensor for Monitored {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        let mut readings = self.inner.read()?;
+        for reading in &mut readings {
+            self.annotate(reading);
+        }
+        Ok(readings)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.inner.is_exhausted()
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.inner.next_due()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::Channel;
+    use crate::sensor::{Signal, SimulatedSensor};
+
+    fn scores(detector: Detector, values: &[f64]) -> Vec<Option<f64>> {
+        let mut state = State::new(detector);
+        values.iter().map(|&value| state.score(detector, value)).collect()
+    }
+
+    #[test]
+    fn rate_scores_the_change_since_the_last_reading() {
+        let detector = Detector::Rate { limit: 2.5 };
+        assert_eq!(scores(detector, &[23.0, 26.0, 25.0, 25.0]), [None, Some(3.0), Some(1.0), Some(0.0)]);
+    }
+
+    #[test]
+    fn zscore_waits_for_a_full_window() {
+        let detector = Detector::Zscore { window: 4, limit: 3.0 };
+        let scores = scores(detector, &[1.0, 3.0, 1.0, 3.0, 2.0, 7.0, 1.0]);
+        assert_eq!(scores[..4], [None; 4]);
+        // Window 1, 3, 1, 3: mean 2, stddev 1.
+        assert_eq!(scores[4], Some(0.0));
+        // Window 3, 1, 3, 2: mean 2.25, stddev ~0.83.
+        assert!((scores[5].unwrap() - 4.75 / 0.6875f64.sqrt()).abs() < 1e-9);
+        // The spike now inflates the window.
+        assert!(scores[6].unwrap() < 1.0);
+    }
+
+    #[test]
+    fn zscore_keeps_its_precision_at_a_large_level() {
+        let detector = Detector::Zscore { window: 4, limit: 3.0 };
+        let level = 1.0e9;
+        let scores = scores(detector, &[level + 1.0, level + 3.0, level + 1.0, level + 3.0, level + 4.0]);
+        assert_eq!(scores[4], Some(2.0));
+    }
+
+    #[test]
+    fn skips_non_finite_values() {
+        for detector in [Detector::Ewma { alpha: 0.5, limit: 3.0 }, Detector::Zscore { window: 2, limit: 3.0 }, Detector::Rate { limit: 1.0 }] {
+            let scores = scores(detector, &[1.0, f64::NAN, 3.0, f64::INFINITY, 1.0, f64::NEG_INFINITY, 3.0, 2.0]);
+            assert_eq!([scores[1], scores[3], scores[5]], [None; 3], "{:?}", detector);
+            assert!(scores[7].is_some_and(f64::is_finite), "{:?}: {:?}", detector, scores);
+        }
+    }
+
+    #[test]
+    fn ewma_tracks_a_drifting_level() {
+        let detector = Detector::Ewma { alpha: 0.2, limit: 3.0 };
+        let mut values: Vec<f64> = (0..50).map(|i| 20.0 + 0.05 * i as f64 + if i % 2 == 0 { 0.2 } else { -0.2 }).collect();
+        values.push(30.0);
+        let scores = scores(detector, &values);
+        assert_eq!(scores[..5], [None; 5]);
+        assert!(scores[5..50].iter().all(|score| score.unwrap() < 3.0), "{:?}", scores);
+        assert!(scores[50].unwrap() > 10.0);
+    }
+
+    #[test]
+    fn flags_injected_spikes() {
+        let signal = Signal { seed: 11, noise: 0.2, spike_rate: 0.02, spike: 5.0, ..Signal::default() };
+        let sensor = SimulatedSensor::with_signal(Channel::new("temperature", "°C"), 20.0, 0.0, signal.clone());
+        let mut reference = SimulatedSensor::with_signal(Channel::new("temperature", "°C"), 20.0, 0.0, signal);
+        let mut monitored = Monitored::new(Box::new(sensor), Detector::Zscore { window: 20, limit: 4.0 });
+
+        let (mut hits, mut misses, mut false_alarms) = (0, 0, 0);
+        for _ in 0..2_000 {
+            let reading = monitored.read().unwrap().remove(0);
+            let spike = (reference.read().unwrap()[0].value - 20.0).abs() > 2.5;
+            match (reading.anomaly.is_some_and(|anomaly| anomaly.flagged), spike) {
+                (true, true) => hits += 1,
+                (false, true) => misses += 1,
+                (true, false) => false_alarms += 1,
+                (false, false) => {}
+            }
+        }
+        assert!(hits > 20 && misses <= hits / 5 && false_alarms <= 5, "{} hits, {} misses, {} false alarms", hits, misses, false_alarms);
+    }
+
+    #[test]
+    fn keeps_channels_apart() {
+        let mut monitored = Monitored::new(
+            Box::new(SimulatedSensor::new(Channel::new("temperature", "°C"), 20.0, 0.0)),
+            Detector::Rate { limit: 1.0 },
+        );
+        let mut other = Channel::new("humidity", "%").reading(50.0);
+        monitored.annotate(&mut other);
+        assert_eq!(monitored.read().unwrap()[0].anomaly, None);
+        let reading = monitored.read().unwrap().remove(0);
+        assert_eq!(reading.anomaly, Some(Anomaly { score: 0.0, flagged: false }));
+    }
+
+    #[test]
+    fn parses_detectors() {
+        #[derive(Deserialize)]
+        struct Entry {
+            anomaly: Detector,
+        }
+        let cases = [
+            ("anomaly = { method = \"ewma\" }", Detector::Ewma { alpha: 0.1, limit: 3.0 }),
+            ("anomaly = { method = \"zscore\", window = 10, limit = 2.5 }", Detector::Zscore { window: 10, limit: 2.5 }),
+            ("anomaly = { method = \"rate\", limit = 2.0 }", Detector::Rate { limit: 2.0 }),
+        ];
+        for (toml, expected) in cases {
+            let entry: Entry = toml::from_str(toml).unwrap();
+            assert_eq!(entry.anomaly, expected, "{}", toml);
+        }
+        let invalid = [
+            ("anomaly = { method = \"ewma\", alpha = 0.0 }", "alpha must be greater than 0 and at most 1"),
+            ("anomaly = { method = \"ewma\", alpha = 1.5 }", "alpha must be greater than 0 and at most 1"),
+            ("anomaly = { method = \"ewma\", alpha = nan }", "alpha must be greater than 0 and at most 1"),
+            ("anomaly = { method = \"zscore\", window = 1 }", "window must be from 2 to 1000 readings"),
+            ("anomaly = { method = \"zscore\", window = 1000000000 }", "window must be from 2 to 1000 readings"),
+            ("anomaly = { method = \"zscore\", limit = 0.0 }", "limit must be a finite number greater than 0"),
+            ("anomaly = { method = \"ewma\", limit = -3.0 }", "limit must be a finite number greater than 0"),
+            ("anomaly = { method = \"rate\", limit = nan }", "limit must be a finite number greater than 0"),
+            ("anomaly = { method = \"rate\", limit = inf }", "limit must be a finite number greater than 0"),
+        ];
+        for (toml, reason) in invalid {
+            let err = toml::from_str::<Entry>(toml).err().unwrap();
+            assert!(err.to_string().contains(reason), "{}: {}", toml, err);
+        }
+        assert!(toml::from_str::<Entry>("anomaly = { method = \"ewma\", alpha = 1.0 }").is_ok());
+    }
+}
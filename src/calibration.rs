@@ -0,0 +1,283 @@
+//! Per-sensor calibration and unit conversion, applied between a sensor's
+//! read and the output.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crate::error::Result;
+use crate::reading::Reading;
+use crate::sensor::Sensor;
+
+/// Correction for a physical probe, applied in the sensor's own unit.
+///
+/// ```toml
+/// calibration = { gain = 1.02, offset = -0.4 }   # gain * x + offset
+/// calibration = [0.1, 0.98, 0.0004]              # 0.1 + 0.98x + 0.0004x²
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Calibration {
+    Linear {
+        #[serde(default = "unit_gain")]
+        gain: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// Coefficients from the constant term up.
+    Polynomial(Vec<f64>),
+}
+
+fn unit_gain() -> f64 {
+    1.0
+}
+
+impl Calibration {
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            Calibration::Linear { gain, offset } => gain * x + offset,
+            Calibration::Polynomial(coefficients) => coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c),
+        }
+    }
+}
+
+/// Units readings can be converted between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+    Pascal,
+    Hectopascal,
+    Kilopascal,
+    Bar,
+    Psi,
+}
+
+impl Unit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Kelvin => "K",
+            Unit::Pascal => "Pa",
+            Unit::Hectopascal => "hPa",
+            Unit::Kilopascal => "kPa",
+            Unit::Bar => "bar",
+            Unit::Psi => "psi",
+        }
+    }
+
+    fn is_temperature(self) -> bool {
+        matches!(self, Unit::Celsius | Unit::Fahrenheit | Unit::Kelvin)
+    }
+
+    /// `value` in kelvin or pascal; [`Unit::of_base`] is the inverse.
+    fn to_base(self, value: f64) -> f64 {
+        match self {
+            Unit::Celsius => value + 273.15,
+            Unit::Fahrenheit => (value - 32.0) * 5.0 / 9.0 + 273.15,
+            Unit::Kelvin | Unit::Pascal => value,
+            Unit::Hectopascal => value * 100.0,
+            Unit::Kilopascal => value * 1_000.0,
+            Unit::Bar => value * 100_000.0,
+            Unit::Psi => value * 6_894.757_293_168,
+        }
+    }
+
+    fn of_base(self, base: f64) -> f64 {
+        match self {
+            Unit::Celsius => base - 273.15,
+            Unit::Fahrenheit => (base - 273.15) * 9.0 / 5.0 + 32.0,
+            Unit::Kelvin | Unit::Pascal => base,
+            Unit::Hectopascal => base / 100.0,
+            Unit::Kilopascal => base / 1_000.0,
+            Unit::Bar => base / 100_000.0,
+            Unit::Psi => base / 6_894.757_293_168,
+        }
+    }
+
+    /// `value` in `to`, or `None` if the units measure different things.
+    pub fn convert(self, value: f64, to: Unit) -> Option<f64> {
+        (self.is_temperature() == to.is_temperature()).then(|| to.of_base(self.to_base(value)))
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "°C" | "C" | "degC" => Ok(Unit::Celsius),
+            "°F" | "F" | "degF" => Ok(Unit::Fahrenheit),
+            "K" => Ok(Unit::Kelvin),
+            "Pa" => Ok(Unit::Pascal),
+            "hPa" | "mbar" => Ok(Unit::Hectopascal),
+            "kPa" => Ok(Unit::Kilopascal),
+            "bar" => Ok(Unit::Bar),
+            "psi" => Ok(Unit::Psi),
+            _ => Err(format!("unsupported unit '{}', expected one of: °C, °F, K, Pa, hPa, kPa, bar, psi", s)),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// The units to convert between for a channel reported in `from` and
+/// configured to `convert` to `to`.
+pub fn conversion(from: &str, to: &str) -> std::result::Result<(Unit, Unit), String> {
+    let from: Unit = from.parse()?;
+    let to: Unit = to.parse()?;
+    match from.convert(0.0, to) {
+        Some(_) => Ok((from, to)),
+        None => Err(format!("cannot convert {} to {}", from, to)),
+    }
+}
+
+/// A sensor whose readings are calibrated and then converted to another unit.
+pub struct Calibrated {
+    inner: Box<dyn Sensor>,
+    calibration: Option<Calibration>,
+    conversion: Option<(Unit, Unit)>,
+}
+
+impl Calibrated {
+    /// Wrap `inner`; `conversion` comes from [`conversion`].
+    pub fn new(inner: Box<dyn Sensor>, calibration: Option<Calibration>, conversion: Option<(Unit, Unit)>) -> Self {
+        Calibrated { inner, calibration, conversion }
+    }
+}
+
+impl Sensor for Calibrated {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        let mut readings = self.inner.read()?;
+        for reading in &mut readings {
+            if let Some(calibration) = &self.calibration {
+                reading.value = calibration.apply(reading.value);
+            }
+            if let Some((from, to)) = self.conversion {
+                reading.value = to.of_base(from.to_base(reading.value));
+                reading.unit = to.symbol().to_string();
+            }
+        }
+        Ok(readings)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.inner.is_exhausted()
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.inner.next_due()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reading::Channel;
+    use crate::sensor::SimulatedSensor;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn converts_units() {
+        let cases = [
+            (0.0, "°C", "°F", 32.0),
+            (100.0, "°C", "°F", 212.0),
+            (-40.0, "°F", "°C", -40.0),
+            (23.0, "°C", "K", 296.15),
+            (0.0, "K", "°F", -459.67),
+            (98.6, "°F", "°C", 37.0),
+            (101_325.0, "Pa", "hPa", 1_013.25),
+            (1_013.25, "hPa", "psi", 14.695_948_775_513_45),
+            (1.0, "bar", "kPa", 100.0),
+            (14.7, "psi", "Pa", 101_352.932_208_569_6),
+            (21.5, "°C", "°C", 21.5),
+        ];
+        for (value, from, to, expected) in cases {
+            let from: Unit = from.parse().unwrap();
+            let to: Unit = to.parse().unwrap();
+            let converted = from.convert(value, to).unwrap();
+            assert!(close(converted, expected), "{} {} -> {}: {} != {}", value, from, to, converted, expected);
+        }
+    }
+
+    #[test]
+    fn refuses_to_mix_dimensions() {
+        for (from, to) in [(Unit::Celsius, Unit::Pascal), (Unit::Psi, Unit::Kelvin)] {
+            assert_eq!(from.convert(1.0, to), None);
+        }
+    }
+
+    #[test]
+    fn applies_calibrations() {
+        let cases = [
+            (Calibration::Linear { gain: 1.0, offset: 0.0 }, 21.0, 21.0),
+            (Calibration::Linear { gain: 1.0, offset: -0.4 }, 21.0, 20.6),
+            (Calibration::Linear { gain: 1.02, offset: -0.4 }, 25.0, 25.1),
+            (Calibration::Polynomial(vec![]), 25.0, 0.0),
+            (Calibration::Polynomial(vec![0.5]), 25.0, 0.5),
+            (Calibration::Polynomial(vec![0.1, 0.98, 0.0004]), 25.0, 24.85),
+            (Calibration::Polynomial(vec![0.0, 0.0, 0.0, 1.0]), -2.0, -8.0),
+        ];
+        for (calibration, raw, expected) in cases {
+            let value = calibration.apply(raw);
+            assert!(close(value, expected), "{:?}({}) = {} != {}", calibration, raw, value, expected);
+        }
+    }
+
+    #[test]
+    fn parses_calibration_tables_and_arrays() {
+        #[derive(Deserialize)]
+        struct Entry {
+            calibration: Calibration,
+        }
+        let cases = [
+            ("calibration = { gain = 1.02, offset = -0.4 }", Calibration::Linear { gain: 1.02, offset: -0.4 }),
+            ("calibration = { offset = 1 }", Calibration::Linear { gain: 1.0, offset: 1.0 }),
+            ("calibration = [0.1, 0.98, 0.0004]", Calibration::Polynomial(vec![0.1, 0.98, 0.0004])),
+        ];
+        for (toml, expected) in cases {
+            let entry: Entry = toml::from_str(toml).unwrap();
+            assert_eq!(entry.calibration, expected, "{}", toml);
+        }
+    }
+
+    #[test]
+    fn calibrates_before_converting() {
+        let inner = Box::new(SimulatedSensor::new(Channel::new("temperature", "°C"), 20.0, 3.0));
+        let calibration = Calibration::Linear { gain: 1.0, offset: -3.0 };
+        let mut sensor = Calibrated::new(inner, Some(calibration), Some(conversion("°C", "°F").unwrap()));
+        let reading = sensor.read().unwrap().remove(0);
+        assert!(close(reading.value, 68.0), "{}", reading.value);
+        assert_eq!(reading.unit, "°F");
+    }
+
+    #[test]
+    fn rejects_impossible_conversions() {
+        assert_eq!(conversion("°C", "psi").unwrap_err(), "cannot convert °C to psi");
+        assert!(conversion("rpm", "°F").unwrap_err().contains("unsupported unit 'rpm'"));
+        assert!(conversion("°C", "rpm").unwrap_err().contains("unsupported unit 'rpm'"));
+    }
+
+    #[test]
+    fn registry_rejects_impossible_conversions_without_a_file() {
+        let config: crate::config::SensorConfig =
+            toml::from_str("kind = \"simulated\"\nname = \"temperature\"\nunit = \"°C\"\nconvert = \"psi\"").unwrap();
+        let err = crate::sensor::registry(&[config]).err().unwrap();
+        assert!(matches!(&err, crate::error::Error::Usage(reason) if reason == "sensor 'temperature': cannot convert °C to psi"), "{:?}", err);
+    }
+}
@@ -0,0 +1,233 @@
+//! Timestamps for readings and the policy for hosts without a valid wall clock.
+//!
+//! RTC-less edge boards often boot with the clock at (or before) the Unix
+//! epoch until NTP catches up. Any wall time before [`MIN_VALID_EPOCH_SECS`]
+//! is treated as unset and handled according to [`ClockPolicy`].
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+/// 2020-01-01T00:00:00Z; earlier wall times are assumed to be an unset clock.
+pub const MIN_VALID_EPOCH_SECS: u64 = 1_577_836_800;
+
+/// What to do with readings taken while the wall clock is not set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockPolicy {
+    /// Report the reading without a wall timestamp, flagged as unsynced.
+    #[default]
+    Flag,
+    /// Report the time since the module started instead.
+    Monotonic,
+    /// Stop the run with [`Error::Clock`].
+    Fail,
+}
+
+impl FromStr for ClockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(ClockPolicy::Flag),
+            "monotonic" => Ok(ClockPolicy::Monotonic),
+            "fail" => Ok(ClockPolicy::Fail),
+            _ => Err(format!("unknown clock policy '{}', expected one of: flag, monotonic, fail", s)),
+        }
+    }
+}
+
+impl fmt::Display for ClockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ClockPolicy::Flag => "flag",
+            ClockPolicy::Monotonic => "monotonic",
+            ClockPolicy::Fail => "fail",
+        })
+    }
+}
+
+/// When a reading was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Time since the Unix epoch from a plausible wall clock.
+    Wall(Duration),
+    /// Time since the module started; the wall clock was not set.
+    Monotonic(Duration),
+    /// The wall clock was not set and no substitute is reported.
+    Unsynced,
+}
+
+impl Timestamp {
+    /// Name of the time source, as reported in machine-readable formats.
+    pub fn source(&self) -> &'static str {
+        match self {
+            Timestamp::Wall(_) => "wall",
+            Timestamp::Monotonic(_) => "monotonic",
+            Timestamp::Unsynced => "unsynced",
+        }
+    }
+}
+
+/// Source of reading timestamps.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    policy: ClockPolicy,
+    started: Instant,
+}
+
+impl Clock {
+    pub fn new(policy: ClockPolicy) -> Self {
+        Clock { policy, started: Instant::now() }
+    }
+
+    pub fn now(&self) -> Result<Timestamp> {
+        self.at(SystemTime::now())
+    }
+
+    /// The timestamp for a reading taken when the wall clock read `now`.
+    fn at(&self, now: SystemTime) -> Result<Timestamp> {
+        let wall = now.duration_since(UNIX_EPOCH);
+        match wall {
+            Ok(since_epoch) if since_epoch.as_secs() >= MIN_VALID_EPOCH_SECS => Ok(Timestamp::Wall(since_epoch)),
+            _ => match self.policy {
+                ClockPolicy::Flag => Ok(Timestamp::Unsynced),
+                ClockPolicy::Monotonic => Ok(Timestamp::Monotonic(self.started.elapsed())),
+                ClockPolicy::Fail => Err(Error::Clock(match wall {
+                    Ok(since_epoch) => format!("wall clock not set ({}s since the epoch)", since_epoch.as_secs()),
+                    Err(_) => "wall clock is before the Unix epoch".to_string(),
+                })),
+            },
+        }
+    }
+}
+
+/// Format a time since the Unix epoch as RFC 3339 UTC with millisecond precision,
+/// e.g. `2026-02-09T16:54:42.000Z`.
+pub fn rfc3339(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year, month, day,
+            rem / 3600, rem % 3600 / 60, rem % 60,
+            since_epoch.subsec_millis())
+}
+
+/// Parse an RFC 3339 timestamp such as `2026-02-09T16:54:42.123Z` or
+/// `2026-02-09T17:54:42+01:00` into a time since the Unix epoch.
+pub fn parse_rfc3339(s: &str) -> Option<Duration> {
+    let (date, rest) = s.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+
+    let (time, offset_secs) = match rest.find(['Z', 'z', '+', '-']) {
+        Some(i) => (&rest[..i], parse_offset(&rest[i..])?),
+        None => return None,
+    };
+    let (time, frac) = match time.split_once('.') {
+        Some((time, frac)) => (time, frac),
+        None => (time, ""),
+    };
+    let mut time = time.splitn(3, ':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: i64 = time.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let nanos = if frac.is_empty() {
+        0
+    } else {
+        let digits: String = frac.chars().chain(std::iter::repeat('0')).take(9).collect();
+        digits.parse::<u32>().ok()?
+    };
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset_secs;
+    Some(Duration::new(u64::try_from(secs).ok()?, nanos))
+}
+
+fn parse_offset(offset: &str) -> Option<i64> {
+    if offset.eq_ignore_ascii_case("z") {
+        return Some(0);
+    }
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let (hours, minutes) = offset[1..].split_once(':')?;
+    Some(sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60))
+}
+
+/// Inverse of [`civil_from_days`].
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Convert days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+///
+/// Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_known_dates() {
+        let dates = [
+            (Duration::ZERO, "1970-01-01T00:00:00.000Z"),
+            (Duration::from_millis(1_709_210_096_789), "2024-02-29T12:34:56.789Z"),
+            (Duration::from_secs(4_107_542_399), "2100-02-28T23:59:59.000Z"),
+            // 2100 is not a leap year.
+            (Duration::from_secs(4_107_542_400), "2100-03-01T00:00:00.000Z"),
+        ];
+        for (since_epoch, text) in dates {
+            assert_eq!(rfc3339(since_epoch), text);
+            assert_eq!(parse_rfc3339(text), Some(since_epoch), "{}", text);
+        }
+        assert_eq!(parse_rfc3339("2024-02-29T13:34:56.789+01:00"), Some(Duration::from_millis(1_709_210_096_789)));
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59Z"), None);
+    }
+
+    #[test]
+    fn falls_back_on_an_unset_clock_by_policy() {
+        let synced = UNIX_EPOCH + Duration::from_secs(MIN_VALID_EPOCH_SECS);
+        let unset = UNIX_EPOCH + Duration::from_secs(MIN_VALID_EPOCH_SECS - 1);
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+        for policy in [ClockPolicy::Flag, ClockPolicy::Monotonic, ClockPolicy::Fail] {
+            let clock = Clock::new(policy);
+            assert_eq!(clock.at(synced).unwrap(), Timestamp::Wall(Duration::from_secs(MIN_VALID_EPOCH_SECS)));
+            for wall in [unset, before_epoch] {
+                match (policy, clock.at(wall)) {
+                    (ClockPolicy::Flag, Ok(timestamp)) => assert_eq!(timestamp, Timestamp::Unsynced),
+                    (ClockPolicy::Monotonic, Ok(timestamp)) => assert!(matches!(timestamp, Timestamp::Monotonic(_))),
+                    (ClockPolicy::Fail, Err(error)) => assert!(matches!(error, Error::Clock(_)), "{}", error),
+                    (policy, result) => panic!("{}: unexpected {:?}", policy, result),
+                }
+            }
+        }
+        assert!(matches!(Clock::new(ClockPolicy::Fail).at(unset),
+                         Err(Error::Clock(reason)) if reason == "wall clock not set (1577836799s since the epoch)"));
+    }
+}
@@ -0,0 +1,189 @@
+//! `margo:sensor-reporter` exports for the `wasm32-wasip2` component build.
+//!
+//! `main` still provides `wasi:cli/run`; this adds the typed `reporter`
+//! interface from `wit/sensor-reporter.wit` so hosts can sample readings
+//! without parsing stdout.
+
+use std::cell::RefCell;
+
+use wasm_hello::clock::{Clock, ClockPolicy, Timestamp};
+use wasm_hello::config::{ChannelConfig, SensorConfig};
+use wasm_hello::error::Error;
+use wasm_hello::reading::{Quality, Quantity};
+use wasm_hello::sensor::{self, Pace, Sensor, Signal};
+
+wit_bindgen::generate!({
+    path: "wit",
+    world: "sensor-reporter",
+});
+
+use exports::margo::sensor_reporter::reporter::Guest;
+use margo::sensor_reporter::types as wit;
+
+struct Reporter {
+    sensors: Vec<Box<dyn Sensor>>,
+    clock: Clock,
+    seq: u64,
+}
+
+impl Reporter {
+    fn new(sensors: &[SensorConfig], clock: ClockPolicy) -> Result<Self, Error> {
+        Ok(Reporter { sensors: sensor::registry(sensors)?, clock: Clock::new(clock), seq: 0 })
+    }
+}
+
+thread_local! {
+    static REPORTER: RefCell<Option<Reporter>> = const { RefCell::new(None) };
+}
+
+struct Component;
+
+impl Guest for Component {
+    fn configure(config: wit::Config) -> Result<(), wit::Error> {
+        let sensors: Vec<SensorConfig> = config.sensors.into_iter().map(SensorConfig::from).collect();
+        // A sensor that cannot be opened is a problem with the configuration.
+        let reporter = Reporter::new(&sensors, config.clock.into())
+            .map_err(|err| wit::Error::Config(err.to_string()))?;
+        REPORTER.with(|cell| *cell.borrow_mut() = Some(reporter));
+        Ok(())
+    }
+
+    fn sample() -> Result<Vec<wit::Reading>, wit::Error> {
+        REPORTER.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            if cell.is_none() {
+                *cell = Some(Reporter::new(&[], ClockPolicy::default())?);
+            }
+            let reporter = cell.as_mut().expect("initialized above");
+            reporter.seq += 1;
+            let now = reporter.clock.now()?;
+
+            let readings = sensor::read_all(&mut reporter.sensors, |_| {})?;
+            Ok(readings.into_iter()
+                .map(|reading| wit::Reading {
+                    seq: reporter.seq,
+                    clock: clock_source(reading.timestamp.unwrap_or(now)),
+                    time_ms: time_ms(reading.timestamp.unwrap_or(now)),
+                    sensor: reading.sensor,
+                    quantity: reading.quantity.into(),
+                    value: reading.value,
+                    unit: reading.unit,
+                    quality: reading.quality.into(),
+                    tags: reading.tags.into_iter().collect(),
+                    anomaly: reading.anomaly.map(|anomaly| wit::Anomaly { score: anomaly.score, flagged: anomaly.flagged }),
+                })
+                .collect())
+        })
+    }
+}
+
+fn clock_source(timestamp: Timestamp) -> wit::ClockSource {
+    match timestamp {
+        Timestamp::Wall(_) => wit::ClockSource::Wall,
+        Timestamp::Monotonic(_) => wit::ClockSource::Monotonic,
+        Timestamp::Unsynced => wit::ClockSource::Unsynced,
+    }
+}
+
+fn time_ms(timestamp: Timestamp) -> Option<u64> {
+    match timestamp {
+        Timestamp::Wall(time) | Timestamp::Monotonic(time) => Some(time.as_millis() as u64),
+        Timestamp::Unsynced => None,
+    }
+}
+
+impl From<wit::SensorConfig> for SensorConfig {
+    fn from(config: wit::SensorConfig) -> Self {
+        match config {
+            wit::SensorConfig::Simulated(s) => SensorConfig::Simulated {
+                name: s.name,
+                unit: s.unit,
+                base: s.base,
+                step: s.step,
+                signal: s.signal.map(Signal::from).unwrap_or_default(),
+                channel: ChannelConfig::default(),
+            },
+            wit::SensorConfig::Thermal(t) => SensorConfig::Thermal {
+                name: t.name,
+                root: t.root.into(),
+                zone: t.zone,
+                channel: ChannelConfig::default(),
+            },
+            wit::SensorConfig::Replay(r) => SensorConfig::Replay {
+                name: r.name,
+                unit: r.unit,
+                path: r.path.into(),
+                source: r.source,
+                format: None,
+                pace: match r.pace {
+                    wit::ReplayPace::Original => Pace::Original,
+                    wit::ReplayPace::Fast => Pace::Fast,
+                },
+                channel: ChannelConfig::default(),
+            },
+        }
+    }
+}
+
+impl From<wit::Signal> for Signal {
+    fn from(signal: wit::Signal) -> Self {
+        Signal {
+            seed: signal.seed,
+            noise: signal.noise,
+            amplitude: signal.amplitude,
+            period: signal.period,
+            drift: signal.drift,
+            spike_rate: signal.spike_rate,
+            spike: signal.spike,
+            stuck_rate: signal.stuck_rate,
+            stuck_for: signal.stuck_for,
+            dropout_rate: signal.dropout_rate,
+        }
+    }
+}
+
+impl From<Quantity> for wit::Quantity {
+    fn from(quantity: Quantity) -> Self {
+        match quantity {
+            Quantity::Temperature => wit::Quantity::Temperature,
+            Quantity::Humidity => wit::Quantity::Humidity,
+            Quantity::Pressure => wit::Quantity::Pressure,
+            Quantity::Voltage => wit::Quantity::Voltage,
+            Quantity::Current => wit::Quantity::Current,
+            Quantity::Power => wit::Quantity::Power,
+            Quantity::Other => wit::Quantity::Other,
+        }
+    }
+}
+
+impl From<Quality> for wit::Quality {
+    fn from(quality: Quality) -> Self {
+        match quality {
+            Quality::Good => wit::Quality::Good,
+            Quality::Uncertain => wit::Quality::Uncertain,
+            Quality::Bad => wit::Quality::Bad,
+        }
+    }
+}
+
+impl From<wit::ClockPolicy> for ClockPolicy {
+    fn from(policy: wit::ClockPolicy) -> Self {
+        match policy {
+            wit::ClockPolicy::Flag => ClockPolicy::Flag,
+            wit::ClockPolicy::Monotonic => ClockPolicy::Monotonic,
+            wit::ClockPolicy::Fail => ClockPolicy::Fail,
+        }
+    }
+}
+
+impl From<Error> for wit::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Clock(_) => wit::Error::Clock(err.to_string()),
+            Error::Sensor { .. } => wit::Error::Sensor(err.to_string()),
+            _ => wit::Error::Config(err.to_string()),
+        }
+    }
+}
+
+export!(Component);
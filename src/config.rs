@@ -0,0 +1,788 @@
+//! Run-loop settings from a TOML file, environment variables and CLI arguments.
+//!
+//! Precedence, highest first: command-line arguments, `WASM_HELLO_*`
+//! environment variables, the configuration file, built-in defaults.
+//!
+//! The file is read from `--config` / `WASM_HELLO_CONFIG` when given, in which
+//! case it must exist. Otherwise [`DEFAULT_CONFIG_PATH`] is used if the host
+//! mounted it (e.g. a Quadlet `Volume=` on `/config`), and skipped if not.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::alert::AlertRule;
+use crate::anomaly::Detector;
+use crate::calibration::{self, Calibration, Unit};
+use crate::clock::ClockPolicy;
+use crate::deadband::Deadband;
+use crate::error::{Error, Result};
+use crate::output::Format;
+use crate::reading::{Channel, Quantity, Tags};
+use crate::sensor::{Pace, Signal, TraceFormat};
+use crate::sink::{CoapConfig, Endpoint, HttpConfig, MqttConfig};
+use crate::spool::DropPolicy;
+
+pub const DEFAULT_CONFIG_PATH: &str = "/config/wasm-hello.toml";
+
+pub const ENV_CONFIG: &str = "WASM_HELLO_CONFIG";
+pub const ENV_ITERATIONS: &str = "WASM_HELLO_ITERATIONS";
+pub const ENV_INTERVAL_MS: &str = "WASM_HELLO_INTERVAL_MS";
+pub const ENV_FORMAT: &str = "WASM_HELLO_FORMAT";
+pub const ENV_CLOCK: &str = "WASM_HELLO_CLOCK";
+pub const ENV_MEMORY_BUDGET_KB: &str = "WASM_HELLO_MEMORY_BUDGET_KB";
+pub const ENV_STOP_FILE: &str = "WASM_HELLO_STOP_FILE";
+pub const ENV_MAX_RUNTIME_MS: &str = "WASM_HELLO_MAX_RUNTIME_MS";
+pub const ENV_WINDOW_MS: &str = "WASM_HELLO_WINDOW_MS";
+pub const ENV_SLIDE_MS: &str = "WASM_HELLO_SLIDE_MS";
+pub const ENV_RAW: &str = "WASM_HELLO_RAW";
+pub const ENV_ALERT_FILE: &str = "WASM_HELLO_ALERT_FILE";
+pub const ENV_SPOOL_DIR: &str = "WASM_HELLO_SPOOL_DIR";
+pub const ENV_SPOOL_MAX_KB: &str = "WASM_HELLO_SPOOL_MAX_KB";
+pub const ENV_SPOOL_DROP: &str = "WASM_HELLO_SPOOL_DROP";
+pub const ENV_MQTT_BROKER: &str = "WASM_HELLO_MQTT_BROKER";
+pub const ENV_HTTP_URL: &str = "WASM_HELLO_HTTP_URL";
+pub const ENV_COAP_SERVER: &str = "WASM_HELLO_COAP_SERVER";
+
+pub const USAGE: &str = "\
+Usage: wasm-hello [OPTIONS]
+
+Options:
+  -c, --config <PATH>            TOML configuration file [env: WASM_HELLO_CONFIG] [default: /config/wasm-hello.toml if present]
+  -n, --iterations <N>           Number of iterations, 0 runs forever [env: WASM_HELLO_ITERATIONS] [default: 5]
+  -i, --interval-ms <MS>         Delay between iterations in milliseconds [env: WASM_HELLO_INTERVAL_MS] [default: 2000]
+  -f, --format <FORMAT>          Output format: text, json, influx, prometheus [env: WASM_HELLO_FORMAT] [default: text]
+      --clock <POLICY>           Readings without a set wall clock: flag, monotonic, fail [env: WASM_HELLO_CLOCK] [default: flag]
+      --memory-budget-kb <KB>    Fail if the memory footprint exceeds this [env: WASM_HELLO_MEMORY_BUDGET_KB]
+      --stop-file <PATH>         Stop gracefully once this file exists [env: WASM_HELLO_STOP_FILE]
+      --max-runtime-ms <MS>      Stop gracefully after this long [env: WASM_HELLO_MAX_RUNTIME_MS]
+      --window-ms <MS>           Summarize readings over windows this long, 0 disables [env: WASM_HELLO_WINDOW_MS]
+      --slide-ms <MS>            Summarize this often; shorter than the window for sliding windows [env: WASM_HELLO_SLIDE_MS] [default: the window length]
+      --raw <BOOL>               Also write raw readings when aggregating [env: WASM_HELLO_RAW] [default: false]
+      --alert-file <PATH>        Append alert events to this file instead of stderr [env: WASM_HELLO_ALERT_FILE]
+      --spool-dir <PATH>         Keep readings an upstream sink could not take in this directory; unused without a sink [env: WASM_HELLO_SPOOL_DIR]
+      --spool-max-kb <KB>        Most unsent data to keep in the spool [env: WASM_HELLO_SPOOL_MAX_KB] [default: 10240]
+      --spool-drop <POLICY>      Which readings to drop when the spool is full: oldest, newest [env: WASM_HELLO_SPOOL_DROP] [default: oldest]
+      --mqtt-broker <HOST:PORT>  Publish every reading to this MQTT broker [env: WASM_HELLO_MQTT_BROKER]
+      --http-url <URL>           POST readings in JSON batches to this URL [env: WASM_HELLO_HTTP_URL]
+      --coap-server <HOST:PORT>  POST readings as SenML to this CoAP server [env: WASM_HELLO_COAP_SERVER]
+  -h, --help                     Print this help";
+
+/// Resolved settings for the reporting loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// Number of iterations; `0` means run until the host stops the module.
+    pub iterations: u64,
+    /// Delay after each iteration.
+    pub interval: Duration,
+    /// Output format for readings.
+    pub format: Format,
+    /// Handling of readings taken before the wall clock is set.
+    pub clock: ClockPolicy,
+    /// Memory footprint in bytes above which the run fails.
+    pub memory_budget: Option<usize>,
+    /// Sentinel file whose appearance stops the loop gracefully.
+    pub stop_file: Option<PathBuf>,
+    /// Deadline after which the loop stops gracefully.
+    pub max_runtime: Option<Duration>,
+    /// Length of the aggregation windows; `None` writes every raw reading.
+    pub window: Option<Duration>,
+    /// How often a window closes; shorter than `window` for sliding windows.
+    pub slide: Option<Duration>,
+    /// Write raw readings alongside window summaries.
+    pub raw: bool,
+    /// Where alert events are appended; stderr when unset.
+    pub alert_file: Option<PathBuf>,
+    /// Preopened directory for readings an upstream sink could not take.
+    pub spool_dir: Option<PathBuf>,
+    /// Most unsent bytes kept in the spool.
+    pub spool_max: u64,
+    /// Which readings give way when the spool is full.
+    pub spool_drop: DropPolicy,
+    /// MQTT sink, from the file's `[mqtt]` table or `--mqtt-broker`.
+    pub mqtt: Option<MqttConfig>,
+    /// HTTP push sink, from the file's `[http]` table or `--http-url`.
+    pub http: Option<HttpConfig>,
+    /// CoAP sink, from the file's `[coap]` table or `--coap-server`.
+    pub coap: Option<CoapConfig>,
+    /// Sensors to build; empty means the built-in demo sensor.
+    pub sensors: Vec<SensorConfig>,
+    /// Threshold rules evaluated on every reading.
+    pub alerts: Vec<AlertRule>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            iterations: 5,
+            interval: Duration::from_secs(2),
+            format: Format::Text,
+            clock: ClockPolicy::Flag,
+            memory_budget: None,
+            stop_file: None,
+            max_runtime: None,
+            window: None,
+            slide: None,
+            raw: false,
+            alert_file: None,
+            spool_dir: None,
+            spool_max: 10 * 1024 * 1024,
+            spool_drop: DropPolicy::Oldest,
+            mqtt: None,
+            http: None,
+            coap: None,
+            sensors: Vec::new(),
+            alerts: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Resolve settings from the configuration file, process environment and WASI argv.
+    pub fn load() -> Result<Self> {
+        let env = Overrides::from_env()?;
+        let args = Overrides::from_args(env::args().skip(1))?;
+        Self::resolve(&env, &args)
+    }
+
+    /// Layer `args` over `env` over the configuration file they name, or
+    /// the default one if present, over the defaults.
+    pub fn resolve(env: &Overrides, args: &Overrides) -> Result<Self> {
+        let file = match args.config.as_ref().or(env.config.as_ref()) {
+            Some(path) => Some(FileConfig::load(path)?),
+            None => FileConfig::load_optional(Path::new(DEFAULT_CONFIG_PATH))?,
+        };
+
+        let mut settings = Settings::default();
+        if let Some(file) = file {
+            settings.apply_file(file)?;
+        }
+        settings.apply(env)?;
+        settings.apply(args)?;
+        Ok(settings)
+    }
+
+    pub fn apply_file(&mut self, file: FileConfig) -> Result<()> {
+        self.apply(&Overrides {
+            config: None,
+            iterations: file.iterations,
+            interval_ms: file.interval_ms,
+            format: file.format,
+            clock: file.clock,
+            memory_budget_kb: file.memory_budget_kb,
+            stop_file: file.stop_file,
+            max_runtime_ms: file.max_runtime_ms,
+            window_ms: file.window_ms,
+            slide_ms: file.slide_ms,
+            raw: file.raw,
+            alert_file: file.alert_file,
+            spool_dir: file.spool_dir,
+            spool_max_kb: file.spool_max_kb,
+            spool_drop: file.spool_drop,
+            mqtt_broker: None,
+            http_url: None,
+            coap_server: None,
+        })?;
+        if !file.sensors.is_empty() {
+            self.sensors = file.sensors;
+        }
+        if !file.alerts.is_empty() {
+            self.alerts = file.alerts;
+        }
+        if file.mqtt.is_some() {
+            self.mqtt = file.mqtt;
+        }
+        if file.http.is_some() {
+            self.http = file.http;
+        }
+        if file.coap.is_some() {
+            self.coap = file.coap;
+        }
+        Ok(())
+    }
+
+    pub fn apply(&mut self, overrides: &Overrides) -> Result<()> {
+        if let Some(iterations) = overrides.iterations {
+            self.iterations = iterations;
+        }
+        if let Some(ms) = overrides.interval_ms {
+            self.interval = Duration::from_millis(ms);
+        }
+        if let Some(format) = overrides.format {
+            self.format = format;
+        }
+        if let Some(clock) = overrides.clock {
+            self.clock = clock;
+        }
+        if let Some(kb) = overrides.memory_budget_kb {
+            let bytes = kb.checked_mul(1024)
+                .ok_or_else(|| Error::Usage(format!("memory budget of {} KiB is too large", kb)))?;
+            self.memory_budget = Some(bytes);
+        }
+        if let Some(path) = &overrides.stop_file {
+            self.stop_file = Some(path.clone());
+        }
+        if let Some(ms) = overrides.max_runtime_ms {
+            self.max_runtime = Some(Duration::from_millis(ms));
+        }
+        if let Some(ms) = overrides.window_ms {
+            self.window = (ms > 0).then(|| Duration::from_millis(ms));
+        }
+        if let Some(ms) = overrides.slide_ms {
+            self.slide = (ms > 0).then(|| Duration::from_millis(ms));
+        }
+        if let Some(raw) = overrides.raw {
+            self.raw = raw;
+        }
+        if let Some(path) = &overrides.alert_file {
+            self.alert_file = Some(path.clone());
+        }
+        if let Some(path) = &overrides.spool_dir {
+            self.spool_dir = Some(path.clone());
+        }
+        if let Some(kb) = overrides.spool_max_kb {
+            self.spool_max = kb.checked_mul(1024)
+                .ok_or_else(|| Error::Usage(format!("spool size of {} KiB is too large", kb)))?;
+        }
+        if let Some(policy) = overrides.spool_drop {
+            self.spool_drop = policy;
+        }
+        if let Some(broker) = &overrides.mqtt_broker {
+            self.mqtt.get_or_insert_with(MqttConfig::default).broker = broker.clone();
+        }
+        if let Some(url) = &overrides.http_url {
+            self.http.get_or_insert_with(HttpConfig::default).url = url.clone();
+        }
+        if let Some(server) = &overrides.coap_server {
+            self.coap.get_or_insert_with(CoapConfig::default).server = server.clone();
+        }
+        Ok(())
+    }
+
+    /// Whether the loop should stop before running iteration `i` (1-based).
+    pub fn is_done(&self, i: u64) -> bool {
+        self.iterations != 0 && i > self.iterations
+    }
+}
+
+/// Contents of `wasm-hello.toml`.
+///
+/// ```toml
+/// iterations = 0
+/// interval_ms = 10000
+/// format = "json"
+/// clock = "monotonic"
+/// memory_budget_kb = 4096
+/// stop_file = "/data/stop"
+/// max_runtime_ms = 3600000
+/// window_ms = 60000
+/// slide_ms = 10000
+/// raw = true
+/// alert_file = "/data/alerts.log"
+/// spool_dir = "/data/spool"
+/// spool_max_kb = 10240
+/// spool_drop = "oldest"
+///
+/// [[sensors]]
+/// kind = "simulated"
+/// name = "temperature"
+/// unit = "°C"
+/// base = 20.0
+/// step = 3.0
+/// seed = 42
+/// noise = 0.3
+/// tags = { site = "lab" }
+/// calibration = { gain = 1.02, offset = -0.4 }
+/// convert = "°F"
+/// anomaly = { method = "zscore", window = 30, limit = 3.0 }
+/// deadband = 0.5
+/// heartbeat_ms = 300000
+///
+/// [[sensors]]
+/// kind = "thermal"
+/// name = "cpu"
+/// zone = 0
+///
+/// [[sensors]]
+/// kind = "simulated"
+/// name = "battery"
+/// quantity = "voltage"
+/// base = 12.6
+/// step = -0.01
+///
+/// [[sensors]]
+/// kind = "replay"
+/// name = "temperature"
+/// unit = "°C"
+/// path = "/data/incident.csv"
+/// pace = "fast"
+///
+/// [[alerts]]
+/// name = "overheat"
+/// sensor = "temperature"
+/// above = 30.0
+/// clear = 28.0
+/// samples = 3
+///
+/// [mqtt]
+/// broker = "mosquitto:1883"
+/// topic = "sites/{site}/{sensor}"
+/// qos = 1
+///
+/// [http]
+/// url = "http://collector:8080/api/readings"
+/// batch_size = 100
+///
+/// [coap]
+/// server = "gateway.local:5683"
+/// format = "senml-cbor"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub iterations: Option<u64>,
+    pub interval_ms: Option<u64>,
+    pub format: Option<Format>,
+    pub clock: Option<ClockPolicy>,
+    pub memory_budget_kb: Option<usize>,
+    pub stop_file: Option<PathBuf>,
+    pub max_runtime_ms: Option<u64>,
+    pub window_ms: Option<u64>,
+    pub slide_ms: Option<u64>,
+    pub raw: Option<bool>,
+    pub alert_file: Option<PathBuf>,
+    pub spool_dir: Option<PathBuf>,
+    pub spool_max_kb: Option<u64>,
+    pub spool_drop: Option<DropPolicy>,
+    #[serde(default)]
+    pub sensors: Vec<SensorConfig>,
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+    pub mqtt: Option<MqttConfig>,
+    pub http: Option<HttpConfig>,
+    pub coap: Option<CoapConfig>,
+}
+
+impl FileConfig {
+    /// Read and parse a configuration file that must exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|err| Error::config(path, err))?;
+        let config: FileConfig = toml::from_str(&text).map_err(|err| Error::config(path, err))?;
+        config.check().map_err(|reason| Error::config(path, reason))?;
+        Ok(config)
+    }
+
+    /// Rules across keys, which parsing each key alone does not catch.
+    fn check(&self) -> std::result::Result<(), String> {
+        for sensor in &self.sensors {
+            let name = sensor.channel().sensor;
+            if let Some(key) = sensor.channel_options().unknown.keys().next() {
+                return Err(format!("sensor '{}': unknown field `{}`", name, key));
+            }
+            sensor.conversion().map_err(|reason| format!("sensor '{}': {}", name, reason))?;
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.check().map_err(|reason| format!("mqtt: {}", reason))?;
+        }
+        Ok(())
+    }
+
+    /// Like [`FileConfig::load`], but a missing file is not an error.
+    pub fn load_optional(path: &Path) -> Result<Option<Self>> {
+        match fs::metadata(path) {
+            Ok(_) => Self::load(path).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::config(path, err)),
+        }
+    }
+}
+
+/// One `[[sensors]]` entry in the configuration file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SensorConfig {
+    /// Demo generator with optional noise and faults, see [`crate::sensor::SimulatedSensor`].
+    Simulated {
+        name: String,
+        #[serde(default)]
+        unit: String,
+        #[serde(default)]
+        base: f64,
+        #[serde(default)]
+        step: f64,
+        #[serde(flatten)]
+        signal: Signal,
+        #[serde(flatten)]
+        channel: ChannelConfig,
+    },
+    /// Linux thermal zone, see [`crate::sensor::SysfsThermalSensor`].
+    Thermal {
+        #[serde(default = "default_thermal_name")]
+        name: String,
+        #[serde(default = "default_thermal_root")]
+        root: PathBuf,
+        #[serde(default)]
+        zone: u32,
+        #[serde(flatten)]
+        channel: ChannelConfig,
+    },
+    /// Recorded trace, see [`crate::sensor::ReplaySensor`].
+    Replay {
+        name: String,
+        #[serde(default)]
+        unit: String,
+        path: PathBuf,
+        /// `sensor` value to replay from the trace; defaults to `name`.
+        #[serde(default)]
+        source: Option<String>,
+        #[serde(default)]
+        format: Option<TraceFormat>,
+        #[serde(default)]
+        pace: Pace,
+        #[serde(flatten)]
+        channel: ChannelConfig,
+    },
+}
+
+impl SensorConfig {
+    /// The channel this entry reports, before calibration.
+    pub fn channel(&self) -> Channel {
+        let options = self.channel_options();
+        match self {
+            SensorConfig::Simulated { name, unit, .. } | SensorConfig::Replay { name, unit, .. } => options.build(name, unit),
+            SensorConfig::Thermal { name, .. } => options.build(name, "°C"),
+        }
+    }
+
+    /// The units `convert` converts between, if set.
+    pub fn conversion(&self) -> std::result::Result<Option<(Unit, Unit)>, String> {
+        let unit = self.channel().unit;
+        self.channel_options().convert.as_deref().map(|to| calibration::conversion(&unit, to)).transpose()
+    }
+
+    pub fn channel_options(&self) -> &ChannelConfig {
+        match self {
+            SensorConfig::Simulated { channel, .. }
+            | SensorConfig::Thermal { channel, .. }
+            | SensorConfig::Replay { channel, .. } => channel,
+        }
+    }
+}
+
+/// Keys every `[[sensors]]` entry accepts to describe its readings.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ChannelConfig {
+    /// Inferred from the unit or name when not set.
+    pub quantity: Option<Quantity>,
+    pub tags: Tags,
+    /// Probe correction, applied in the sensor's own unit.
+    pub calibration: Option<Calibration>,
+    /// Unit to report in, e.g. `°F`.
+    pub convert: Option<String>,
+    /// Streaming detector annotating readings, applied after calibration.
+    pub anomaly: Option<Detector>,
+    /// Smallest change from the last written reading that is written.
+    pub deadband: Option<f64>,
+    /// Write a reading at least this often, even without a change.
+    pub heartbeat_ms: Option<u64>,
+    /// Keys no part of the entry knows, e.g. a misspelt `convert`. Flattened
+    /// entries cannot deny unknown fields, so [`FileConfig::load`] rejects
+    /// them instead.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+impl ChannelConfig {
+    pub fn build(&self, sensor: &str, unit: &str) -> Channel {
+        let mut channel = match self.quantity {
+            Some(quantity) => Channel::with_quantity(quantity, sensor, unit),
+            None => Channel::new(sensor, unit),
+        };
+        channel.tags = self.tags.clone();
+        channel
+    }
+
+    /// Report-by-exception settings, if either key is set.
+    pub fn deadband(&self) -> Option<Deadband> {
+        if self.deadband.is_none() && self.heartbeat_ms.is_none() {
+            return None;
+        }
+        Some(Deadband {
+            deadband: self.deadband.unwrap_or(0.0),
+            heartbeat: self.heartbeat_ms.map(Duration::from_millis),
+        })
+    }
+}
+
+fn default_thermal_name() -> String {
+    "temperature".to_string()
+}
+
+fn default_thermal_root() -> PathBuf {
+    PathBuf::from(crate::sensor::DEFAULT_THERMAL_ROOT)
+}
+
+/// A partial set of settings from a single source.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub iterations: Option<u64>,
+    pub interval_ms: Option<u64>,
+    pub format: Option<Format>,
+    pub clock: Option<ClockPolicy>,
+    pub memory_budget_kb: Option<usize>,
+    pub stop_file: Option<PathBuf>,
+    pub max_runtime_ms: Option<u64>,
+    pub window_ms: Option<u64>,
+    pub slide_ms: Option<u64>,
+    pub raw: Option<bool>,
+    pub alert_file: Option<PathBuf>,
+    pub spool_dir: Option<PathBuf>,
+    pub spool_max_kb: Option<u64>,
+    pub spool_drop: Option<DropPolicy>,
+    pub mqtt_broker: Option<String>,
+    pub http_url: Option<Endpoint>,
+    pub coap_server: Option<String>,
+}
+
+impl Overrides {
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| env::var(name))
+    }
+
+    /// Overrides from the variables `var` looks up, as [`env::var`] does.
+    pub fn from_vars(var: impl Fn(&str) -> std::result::Result<String, env::VarError>) -> Result<Self> {
+        Ok(Overrides {
+            config: env_var(&var, ENV_CONFIG)?,
+            iterations: env_var(&var, ENV_ITERATIONS)?,
+            interval_ms: env_var(&var, ENV_INTERVAL_MS)?,
+            format: env_var(&var, ENV_FORMAT)?,
+            clock: env_var(&var, ENV_CLOCK)?,
+            memory_budget_kb: env_var(&var, ENV_MEMORY_BUDGET_KB)?,
+            stop_file: env_var(&var, ENV_STOP_FILE)?,
+            max_runtime_ms: env_var(&var, ENV_MAX_RUNTIME_MS)?,
+            window_ms: env_var(&var, ENV_WINDOW_MS)?,
+            slide_ms: env_var(&var, ENV_SLIDE_MS)?,
+            raw: env_var(&var, ENV_RAW)?,
+            alert_file: env_var(&var, ENV_ALERT_FILE)?,
+            spool_dir: env_var(&var, ENV_SPOOL_DIR)?,
+            spool_max_kb: env_var(&var, ENV_SPOOL_MAX_KB)?,
+            spool_drop: env_var(&var, ENV_SPOOL_DROP)?,
+            mqtt_broker: env_var(&var, ENV_MQTT_BROKER)?,
+            http_url: env_var(&var, ENV_HTTP_URL)?,
+            coap_server: env_var(&var, ENV_COAP_SERVER)?,
+        })
+    }
+
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut overrides = Overrides::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`.
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || inline.clone().or_else(|| args.next())
+                .ok_or_else(|| Error::Usage(format!("missing value for {}", flag)));
+            match flag.as_str() {
+                "-c" | "--config" => overrides.config = Some(PathBuf::from(value()?)),
+                "-n" | "--iterations" => overrides.iterations = Some(parse(&flag, &value()?)?),
+                "-i" | "--interval-ms" => overrides.interval_ms = Some(parse(&flag, &value()?)?),
+                "-f" | "--format" => overrides.format = Some(parse(&flag, &value()?)?),
+                "--clock" => overrides.clock = Some(parse(&flag, &value()?)?),
+                "--memory-budget-kb" => overrides.memory_budget_kb = Some(parse(&flag, &value()?)?),
+                "--stop-file" => overrides.stop_file = Some(PathBuf::from(value()?)),
+                "--max-runtime-ms" => overrides.max_runtime_ms = Some(parse(&flag, &value()?)?),
+                "--window-ms" => overrides.window_ms = Some(parse(&flag, &value()?)?),
+                "--slide-ms" => overrides.slide_ms = Some(parse(&flag, &value()?)?),
+                "--raw" => overrides.raw = Some(parse(&flag, &value()?)?),
+                "--alert-file" => overrides.alert_file = Some(PathBuf::from(value()?)),
+                "--spool-dir" => overrides.spool_dir = Some(PathBuf::from(value()?)),
+                "--spool-max-kb" => overrides.spool_max_kb = Some(parse(&flag, &value()?)?),
+                "--spool-drop" => overrides.spool_drop = Some(parse(&flag, &value()?)?),
+                "--mqtt-broker" => overrides.mqtt_broker = Some(value()?),
+                "--http-url" => overrides.http_url = Some(parse(&flag, &value()?)?),
+                "--coap-server" => overrides.coap_server = Some(value()?),
+                "-h" | "--help" => return Err(Error::Help),
+                _ => return Err(Error::Usage(format!("unexpected argument '{}'", flag))),
+            }
+        }
+        Ok(overrides)
+    }
+}
+
+fn env_var<T: FromStr>(var: &impl Fn(&str) -> std::result::Result<String, env::VarError>, name: &str) -> Result<Option<T>>
+where
+    T::Err: fmt::Display,
+{
+    match var(name) {
+        Ok(value) => parse(name, &value).map(Some),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(Error::Usage(format!("{} is not valid UTF-8", name))),
+    }
+}
+
+fn parse<T: FromStr>(source: &str, value: &str) -> Result<T>
+where
+    T::Err: fmt::Display,
+{
+    value.trim().parse()
+        .map_err(|err| Error::Usage(format!("invalid value '{}' for {}: {}", value, source, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Overrides> {
+        Overrides::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn write_file(dir: &tempfile::TempDir, toml: &str) -> String {
+        let path = dir.path().join("wasm-hello.toml");
+        fs::write(&path, toml).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn layers_args_over_the_file_over_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(&dir, "iterations = 10\ninterval_ms = 500\nformat = \"json\"\n");
+        let settings = Settings::resolve(&Overrides::default(), &args(&["--config", &path, "-n", "3", "--format=influx"]).unwrap()).unwrap();
+        assert_eq!(settings.iterations, 3);
+        assert_eq!(settings.format, Format::Influx);
+        assert_eq!(settings.interval, Duration::from_millis(500));
+        assert_eq!(settings.clock, ClockPolicy::Flag);
+        assert_eq!(settings.spool_max, Settings::default().spool_max);
+    }
+
+    #[test]
+    fn rejects_unknown_flags_and_malformed_values() {
+        let cases: [(&[&str], &str); 5] = [
+            (&["--bogus"], "unexpected argument '--bogus'"),
+            (&["positional"], "unexpected argument 'positional'"),
+            (&["-n"], "missing value for -n"),
+            (&["-n", "many"], "invalid value 'many' for -n: invalid digit found in string"),
+            (&["--format=xml"], "invalid value 'xml' for --format: unknown format 'xml', expected one of: text, json, influx, prometheus"),
+        ];
+        for (flags, expected) in cases {
+            let err = args(flags).unwrap_err();
+            assert!(matches!(err, Error::Usage(_)), "{:?}: {:?}", flags, err);
+            assert_eq!(err.to_string(), expected);
+        }
+        assert!(matches!(args(&["-n", "1", "--help"]), Err(Error::Help)));
+
+        let dir = tempfile::tempdir().unwrap();
+        for (kind, key) in [("simulated", "convret = \"°F\""), ("thermal", "noise = 0.5"), ("simulated", "noize = 0.5")] {
+            let path = write_file(&dir, &format!("[[sensors]]\nkind = \"{}\"\nname = \"temperature\"\n{}\n", kind, key));
+            let err = FileConfig::load(Path::new(&path)).unwrap_err();
+            assert!(matches!(err, Error::Config { .. }), "{}: {:?}", key, err);
+            let field = key.split(' ').next().unwrap();
+            assert!(err.to_string().ends_with(&format!("sensor 'temperature': unknown field `{}`", field)), "{}", err);
+        }
+        assert_eq!(args(&["--interval-ms=250", "--raw", " true "]).unwrap(), Overrides {
+            interval_ms: Some(250),
+            raw: Some(true),
+            ..Overrides::default()
+        });
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Result<Overrides> {
+        Overrides::from_vars(|name| {
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string()).ok_or(env::VarError::NotPresent)
+        })
+    }
+
+    #[test]
+    fn layers_env_between_args_and_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(&dir, "iterations = 10\ninterval_ms = 500\nclock = \"monotonic\"\n");
+        let env = vars(&[(ENV_CONFIG, &path), (ENV_ITERATIONS, "7"), (ENV_INTERVAL_MS, " 250 "), (ENV_SPOOL_DROP, "newest")]).unwrap();
+        let settings = Settings::resolve(&env, &args(&["-n", "3"]).unwrap()).unwrap();
+        assert_eq!(settings.iterations, 3);
+        assert_eq!(settings.interval, Duration::from_millis(250));
+        assert_eq!(settings.clock, ClockPolicy::Monotonic);
+        assert_eq!(settings.spool_drop, DropPolicy::Newest);
+        assert_eq!(settings.format, Format::Text);
+
+        // A file named on the command line wins over the environment's.
+        let missing = dir.path().join("absent.toml");
+        let err = Settings::resolve(&env, &args(&["--config", missing.to_str().unwrap()]).unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn rejects_invalid_env_values() {
+        let err = vars(&[(ENV_ITERATIONS, "-1")]).unwrap_err();
+        assert!(matches!(err, Error::Usage(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "invalid value '-1' for WASM_HELLO_ITERATIONS: invalid digit found in string");
+        let err = vars(&[(ENV_HTTP_URL, "ftp://collector/")]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid value 'ftp://collector/' for WASM_HELLO_HTTP_URL: 'ftp://collector/' is not an http:// or https:// URL");
+        let err = Overrides::from_vars(|name| match name {
+            ENV_RAW => Err(env::VarError::NotUnicode("\u{fffd}".into())),
+            _ => Err(env::VarError::NotPresent),
+        }).unwrap_err();
+        assert_eq!(err.to_string(), "WASM_HELLO_RAW is not valid UTF-8");
+        assert_eq!(vars(&[]).unwrap(), Overrides::default());
+    }
+
+    #[test]
+    fn tells_a_missing_config_file_from_an_unreadable_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("absent.toml");
+        assert_eq!(FileConfig::load_optional(&missing).unwrap(), None);
+        // Named explicitly, it has to be there.
+        let err = Settings::resolve(&Overrides::default(), &args(&["--config", missing.to_str().unwrap()]).unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+        // A directory is there but cannot be read as a file.
+        let err = FileConfig::load_optional(dir.path()).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+
+        let path = write_file(&dir, "iterations = \"many\"\n");
+        let err = FileConfig::load_optional(Path::new(&path)).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+        assert!(err.to_string().starts_with(&format!("config file {}: TOML parse error", path)), "{}", err);
+    }
+
+    #[test]
+    fn rejects_impossible_conversions_as_config_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(&dir, "[[sensors]]\nkind = \"simulated\"\nname = \"temperature\"\nunit = \"°C\"\nconvert = \"psi\"\n");
+        let err = FileConfig::load(Path::new(&path)).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+        assert_eq!(err.to_string(), format!("config file {}: sensor 'temperature': cannot convert °C to psi", path));
+    }
+
+    #[test]
+    fn rejects_an_mqtt_password_without_a_username() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(&dir, "[mqtt]\npassword = \"secret\"\n");
+        let err = FileConfig::load(Path::new(&path)).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+        assert_eq!(err.to_string(), format!("config file {}: mqtt: a password needs a username", path));
+        let path = write_file(&dir, "[mqtt]\nusername = \"edge\"\npassword = \"secret\"\n");
+        assert!(FileConfig::load(Path::new(&path)).is_ok());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let mut settings = Settings::default();
+        settings.apply(&args(&["--memory-budget-kb", "4096"]).unwrap()).unwrap();
+        assert_eq!(settings.memory_budget, Some(4 * 1024 * 1024));
+        let err = settings.apply(&args(&["--memory-budget-kb", &usize::MAX.to_string()]).unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Usage(_)), "{:?}", err);
+        assert_eq!(err.to_string(), format!("memory budget of {} KiB is too large", usize::MAX));
+        let err = settings.apply(&args(&["--spool-max-kb", &u64::MAX.to_string()]).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), format!("spool size of {} KiB is too large", u64::MAX));
+        assert_eq!(settings.spool_max, 10 * 1024 * 1024);
+    }
+}
@@ -0,0 +1,209 @@
+//! Report-by-exception: raw readings are only written when they move past a
+//! deadband or a heartbeat is due, for devices on metered links.
+//!
+//! Filtering happens at the output, so alerts and window summaries still see
+//! every reading. A change of quality is always reported.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::clock::Timestamp;
+use crate::config::SensorConfig;
+use crate::output::Record;
+use crate::reading::Quality;
+
+/// The `deadband` and `heartbeat_ms` keys of a `[[sensors]]` entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadband {
+    /// Smallest change from the last reported value that is reported.
+    pub deadband: f64,
+    /// Report at least this often, even without a change.
+    pub heartbeat: Option<Duration>,
+}
+
+impl Deadband {
+    /// Whether `value` must be reported, given the last reported reading.
+    fn reports(&self, last: &Last, value: f64, quality: Quality, time: Duration) -> bool {
+        // A NaN is never past the deadband, so a change to or from one is reported as such.
+        quality != last.quality
+            || value.is_nan() != last.value.is_nan()
+            || (value - last.value).abs() > self.deadband
+            || self.heartbeat.is_some_and(|heartbeat| time >= last.at + heartbeat)
+    }
+}
+
+/// Last reported reading of a channel.
+struct Last {
+    value: f64,
+    quality: Quality,
+    at: Duration,
+}
+
+struct Channel {
+    sensor: String,
+    deadband: Option<Deadband>,
+    last: Option<Last>,
+    seen: u64,
+    suppressed: u64,
+}
+
+/// Deadband state for every channel of the configured sensors.
+pub struct ReportByException {
+    /// Time base for readings without a clock.
+    started: Instant,
+    /// Sensor entry names and their deadbands.
+    rules: Vec<(String, Deadband)>,
+    channels: Vec<Channel>,
+}
+
+impl ReportByException {
+    pub fn new(configs: &[SensorConfig]) -> Self {
+        let rules = configs.iter()
+            .filter_map(|config| Some((config.channel().sensor, config.channel_options().deadband()?)))
+            .collect();
+        ReportByException { started: Instant::now(), rules, channels: Vec::new() }
+    }
+
+    /// Whether any sensor reports by exception.
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// The records of one iteration that should be written.
+    pub fn filter<'a>(&mut self, records: &[Record<'a>]) -> Vec<Record<'a>> {
+        let mut reported = Vec::with_capacity(records.len());
+        for record in records {
+            let reading = record.reading;
+            let time = match record.timestamp {
+                Timestamp::Wall(time) | Timestamp::Monotonic(time) => time,
+                Timestamp::Unsynced => self.started.elapsed(),
+            };
+            let index = match self.channels.iter().position(|channel| channel.sensor == reading.sensor) {
+                Some(index) => index,
+                None => {
+                    let deadband = self.rules.iter()
+                        .find(|(name, _)| covers(name, &reading.sensor))
+                        .map(|&(_, deadband)| deadband);
+                    self.channels.push(Channel { sensor: reading.sensor.clone(), deadband, last: None, seen: 0, suppressed: 0 });
+                    self.channels.len() - 1
+                }
+            };
+            let channel = &mut self.channels[index];
+            channel.seen += 1;
+            let report = match (&channel.deadband, &channel.last) {
+                (Some(deadband), Some(last)) => deadband.reports(last, reading.value, reading.quality, time),
+                _ => true,
+            };
+            if report {
+                channel.last = Some(Last { value: reading.value, quality: reading.quality, at: time });
+                reported.push(record.clone());
+            } else {
+                channel.suppressed += 1;
+            }
+        }
+        reported
+    }
+}
+
+/// Whether the `[[sensors]]` entry `name` produced `sensor`, which is either
+/// the entry's own name or one of its channels, e.g. `bme280.humidity`.
+fn covers(name: &str, sensor: &str) -> bool {
+    sensor.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Suppressed readings per channel, e.g. `temperature=12/15`.
+impl fmt::Display for ReportByException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut channels = self.channels.iter().filter(|channel| channel.deadband.is_some());
+        match channels.next() {
+            None => f.write_str("none"),
+            Some(first) => {
+                write!(f, "{}={}/{}", first.sensor, first.suppressed, first.seen)?;
+                for channel in channels {
+                    write!(f, ", {}={}/{}", channel.sensor, channel.suppressed, channel.seen)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::reading::{Channel, Reading};
+
+    fn filter(toml: &str) -> ReportByException {
+        let file: FileConfig = toml::from_str(toml).unwrap();
+        ReportByException::new(&file.sensors)
+    }
+
+    /// Feed `(second, reading)` pairs one per iteration; returns the seqs written.
+    fn run(filter: &mut ReportByException, readings: &[(u64, Reading)]) -> Vec<u64> {
+        let mut written = Vec::new();
+        for (seq, (secs, reading)) in (1..).zip(readings) {
+            let record = Record { seq, reading, timestamp: Timestamp::Wall(Duration::from_secs(*secs)) };
+            written.extend(filter.filter(&[record]).iter().map(|record| record.seq));
+        }
+        written
+    }
+
+    const TEMPERATURE: &str = "[[sensors]]\nkind = \"simulated\"\nname = \"temperature\"\n";
+
+    #[test]
+    fn reports_changes_past_the_deadband() {
+        let channel = Channel::new("temperature", "°C");
+        let values = [20.0, 20.3, 20.6, 19.9, 21.2, 21.0];
+        let readings: Vec<_> = values.iter().map(|&value| (0, channel.reading(value))).collect();
+        let mut filter = filter(&format!("{}deadband = 0.5", TEMPERATURE));
+        // 20.6 is measured against 20.0, the last value written, not 20.3.
+        assert_eq!(run(&mut filter, &readings), [1, 3, 4, 5]);
+        assert_eq!(filter.to_string(), "temperature=2/6");
+    }
+
+    #[test]
+    fn heartbeat_reports_a_steady_value() {
+        let channel = Channel::new("temperature", "°C");
+        let readings: Vec<_> = (0..8).map(|i| (10 * i, channel.reading(20.0))).collect();
+        let mut filter = filter(&format!("{}deadband = 0.5\nheartbeat_ms = 30000", TEMPERATURE));
+        assert_eq!(run(&mut filter, &readings), [1, 4, 7]);
+    }
+
+    #[test]
+    fn reports_quality_changes() {
+        let channel = Channel::new("temperature", "°C");
+        let bad = Reading { quality: Quality::Bad, ..channel.reading(20.0) };
+        let readings = [(0, channel.reading(20.0)), (1, bad.clone()), (2, bad), (3, channel.reading(20.1))];
+        let mut filter = filter(&format!("{}deadband = 1.0", TEMPERATURE));
+        assert_eq!(run(&mut filter, &readings), [1, 2, 4]);
+    }
+
+    #[test]
+    fn reports_the_value_after_a_nan() {
+        let channel = Channel::new("temperature", "°C");
+        let values = [20.0, f64::NAN, f64::NAN, 20.0, 20.1, 25.0];
+        let readings: Vec<_> = values.iter().map(|&value| (0, channel.reading(value))).collect();
+        let mut filter = filter(&format!("{}deadband = 1.0", TEMPERATURE));
+        assert_eq!(run(&mut filter, &readings), [1, 2, 4, 6]);
+    }
+
+    #[test]
+    fn applies_to_the_entry_and_its_channels_only() {
+        let mut filter = filter(&format!("{}deadband = 1.0", TEMPERATURE));
+        let readings: Vec<_> = ["temperature", "temperature.inner", "temperature2", "humidity"].iter()
+            .flat_map(|&sensor| {
+                let channel = Channel::new(sensor, "");
+                [(0, channel.reading(20.0)), (1, channel.reading(20.0))]
+            })
+            .collect();
+        assert_eq!(run(&mut filter, &readings), [1, 3, 5, 6, 7, 8]);
+        assert_eq!(filter.to_string(), "temperature=1/2, temperature.inner=1/2");
+    }
+
+    #[test]
+    fn disabled_without_deadband_keys() {
+        assert!(!filter(TEMPERATURE).is_enabled());
+        assert!(filter(&format!("{}heartbeat_ms = 60000", TEMPERATURE)).is_enabled());
+    }
+}
@@ -0,0 +1,105 @@
+//! Error type for the whole run and the exit codes reported to the host.
+//!
+//! | Code | Meaning                                                       |
+//! |------|---------------------------------------------------------------|
+//! | 0    | Completed (or `--help`)                                       |
+//! | 2    | Invalid argument or environment variable                      |
+//! | 3    | Configuration file missing, unreadable or invalid             |
+//! | 4    | Wall clock not set and `clock = "fail"`                       |
+//! | 5    | Every sensor failed in the same iteration                     |
+//! | 6    | Readings could not be written to the output                   |
+//! | 7    | Memory footprint exceeded the configured budget               |
+//! | 8    | Stopped early by the stop file or max runtime                 |
+//!
+//! Codes 2 and 3 will not go away by restarting; list them in the unit's
+//! `RestartPreventExitStatus=` so `Restart=on-failure` only retries 4-7.
+//! Code 8 is a requested stop; add it to `SuccessExitStatus=`.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_CONFIG: u8 = 3;
+pub const EXIT_CLOCK: u8 = 4;
+pub const EXIT_SENSOR: u8 = 5;
+pub const EXIT_OUTPUT: u8 = 6;
+pub const EXIT_MEMORY: u8 = 7;
+pub const EXIT_STOPPED: u8 = 8;
+
+/// Errors produced while running the sensor workload.
+#[derive(Debug)]
+pub enum Error {
+    /// `--help` was requested; not a failure, but it ends the run.
+    Help,
+    /// Invalid command-line argument or environment variable.
+    Usage(String),
+    /// The configuration file is missing, unreadable or invalid, or a file
+    /// it names (such as the alert file or the spool directory) cannot be opened.
+    Config { path: PathBuf, reason: String },
+    /// The wall clock is not usable for timestamps.
+    Clock(String),
+    /// A sensor failed to produce a reading.
+    Sensor { sensor: String, reason: String },
+    /// Writing readings to the output failed.
+    Output(io::Error),
+    /// The memory footprint grew past the configured budget.
+    MemoryBudget { used: usize, budget: usize },
+}
+
+impl Error {
+    pub fn sensor(sensor: impl Into<String>, reason: impl Into<String>) -> Self {
+        Error::Sensor { sensor: sensor.into(), reason: reason.into() }
+    }
+
+    pub fn config(path: &Path, reason: impl fmt::Display) -> Self {
+        Error::Config { path: path.to_path_buf(), reason: reason.to_string() }
+    }
+
+    /// Process exit code reported to the host for this error.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::Help => ExitCode::SUCCESS,
+            Error::Usage(_) => ExitCode::from(EXIT_USAGE),
+            Error::Config { .. } => ExitCode::from(EXIT_CONFIG),
+            Error::Clock(_) => ExitCode::from(EXIT_CLOCK),
+            Error::Sensor { .. } => ExitCode::from(EXIT_SENSOR),
+            Error::Output(_) => ExitCode::from(EXIT_OUTPUT),
+            Error::MemoryBudget { .. } => ExitCode::from(EXIT_MEMORY),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Help => f.write_str("help requested"),
+            Error::Usage(reason) => f.write_str(reason),
+            Error::Config { path, reason } => write!(f, "config file {}: {}", path.display(), reason),
+            Error::Clock(reason) => write!(f, "clock: {}", reason),
+            Error::Sensor { sensor, reason } => write!(f, "sensor '{}' failed: {}", sensor, reason),
+            Error::Output(err) => write!(f, "writing output: {}", err),
+            Error::MemoryBudget { used, budget } => {
+                write!(f, "memory footprint {} bytes exceeds budget of {} bytes", used, budget)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Output(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Output(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
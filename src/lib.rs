@@ -0,0 +1,21 @@
+//! Sensor workload behind the `wasm-hello` WASM demo.
+//!
+//! The binary in `main.rs` is a thin reporting loop; sensors and errors live
+//! here so new drivers can be added without touching it.
+
+pub mod aggregate;
+pub mod alert;
+pub mod anomaly;
+pub mod calibration;
+pub mod clock;
+pub mod config;
+pub mod deadband;
+pub mod error;
+pub mod memory;
+pub mod output;
+pub mod reading;
+pub mod sensor;
+pub mod serve;
+pub mod shutdown;
+pub mod sink;
+pub mod spool;
@@ -1,26 +1,299 @@
-use std::thread;
-use std::time::Duration;
-
-fn main() {
-    println!("🦭 Margo WASM Demo - Hello from WebAssembly!");
-    println!("========================================");
-    println!("Runtime: wasm32-wasi");
-    println!("Build: Rust {} ({})", env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_NAME"));
-    println!();
-
-    // Simulate periodic sensor output (for demo purposes)
-    for i in 1..=5 {
-        println!("[{}] Sensor reading: temperature={}°C, timestamp={}",
-                 i,
-                 20 + (i * 3),
-                 std::time::SystemTime::now()
-                     .duration_since(std::time::UNIX_EPOCH)
-                     .unwrap()
-                     .as_secs());
-        thread::sleep(Duration::from_secs(2));
-    }
-
-    println!();
-    println!("✓ WASM workload completed successfully");
-    println!("Memory footprint: <10 MB (WASM sandbox)");
+#[cfg(all(target_os = "wasi", target_env = "p2"))]
+mod component;
+#[cfg(all(target_os = "wasi", target_env = "p2", feature = "wasi-http"))]
+mod proxy;
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use wasm_hello::aggregate::Aggregator;
+use wasm_hello::alert::Alerts;
+use wasm_hello::clock::Clock;
+use wasm_hello::config::{Settings, USAGE};
+use wasm_hello::deadband::ReportByException;
+use wasm_hello::error::{EXIT_STOPPED, Error, Result};
+use wasm_hello::memory::{self, TrackingAllocator};
+use wasm_hello::output::{self, Record};
+use wasm_hello::sensor;
+use wasm_hello::shutdown::{StopCondition, StopReason};
+use wasm_hello::sink::{self, Sink};
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+fn main() -> ExitCode {
+    let result = Settings::load().and_then(|settings| run(&settings));
+    match result {
+        Ok(None) => ExitCode::SUCCESS,
+        Ok(Some(_)) => ExitCode::from(EXIT_STOPPED),
+        Err(Error::Help) => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        Err(err @ Error::Usage(_)) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            err.exit_code()
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            err.exit_code()
+        }
+    }
+}
+
+/// Runs the reporting loop; `Ok(Some(_))` if it was stopped before completing.
+fn run(settings: &Settings) -> Result<Option<StopReason>> {
+    let human = settings.format.is_human();
+    if human {
+        println!("🦭 Margo WASM Demo - Hello from WebAssembly!");
+        println!("========================================");
+        println!("Runtime: wasm32-wasi");
+        println!("Build: Rust {} ({})", env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_NAME"));
+        println!();
+    }
+
+    let clock = Clock::new(settings.clock);
+    let mut sensors = sensor::registry(&settings.sensors)?;
+    let mut sinks = sink::registry(settings)?;
+    let mut stdout = io::stdout();
+    let max_idle = sinks.iter().filter_map(|sink| sink.max_idle()).min();
+    let stop = StopCondition::new(settings);
+    let mut stopped = None;
+    let mut completed = 0;
+    let mut aggregator = settings.window.map(|window| Aggregator::new(window, settings.slide)).transpose()?;
+    let mut alerts = Alerts::new(&settings.alerts);
+    let mut exceptions = ReportByException::new(&settings.sensors);
+    let mut alert_out: Box<dyn Write> = match &settings.alert_file {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|err| Error::config(path, format!("cannot open the alert file: {}", err)))?),
+        None => Box::new(io::stderr()),
+    };
+
+    // Report every registered sensor once per iteration
+    let mut report = || -> Result<()> {
+        for i in (1..).take_while(|&i| !settings.is_done(i)) {
+            if sensors.iter().all(|sensor| sensor.is_exhausted()) {
+                break;
+            }
+            // Replayed traces keep their recorded spacing.
+            let paced = sensors.iter().filter_map(|sensor| sensor.next_due()).min()
+                .and_then(|due| wait_until(&stop, due, max_idle, &mut sinks, i));
+            if let Some(reason) = paced.or_else(|| stop.check()) {
+                stopped = Some(reason);
+                break;
+            }
+            let timestamp = clock.now()?;
+            let now = Instant::now();
+            let due = sensors.iter_mut().filter(|sensor| sensor.next_due().is_none_or(|due| due <= now));
+            // Partial failures are logged; a tick with no readings at all ends the run.
+            let readings = sensor::read_all(due, |err| eprintln!("[{}] Sensor error: {}", i, err))?;
+            let records: Vec<Record> = readings.iter()
+                .map(|reading| Record { seq: i, reading, timestamp: reading.timestamp.unwrap_or(timestamp) })
+                .collect();
+            let reported = exceptions.filter(&records);
+            if (aggregator.is_none() || settings.raw) && !reported.is_empty() {
+                output::write_tick(&mut stdout, settings.format, &reported)?;
+            }
+            publish(&mut sinks, i, &reported);
+            if let Some(aggregator) = &mut aggregator {
+                output::write_summaries(&mut stdout, settings.format, i, &aggregator.push(&records))?;
+            }
+            output::write_alerts(&mut alert_out, settings.format, &alerts.evaluate(&records))?;
+            check_memory_budget(settings)?;
+            completed = i;
+            stop.wait(settings.interval);
+        }
+        Ok(())
+    };
+    // An error ends the loop, but what was read is still summarized, flushed
+    // and handed to the sinks before it is returned.
+    let result = report();
+    let finished = match &mut aggregator {
+        Some(aggregator) => output::write_summaries(&mut stdout, settings.format, completed, &aggregator.finish()),
+        None => Ok(()),
+    };
+    let finished = finished.and_then(|()| stdout.flush());
+    for sink in &mut sinks {
+        if let Err(err) = sink.close() {
+            eprintln!("Sink error: {}: {}", sink.name(), err);
+        }
+    }
+
+    let stats = memory::stats();
+    let result = result.and(finished.map_err(Error::from)).and_then(|()| check_memory_budget(settings));
+    if human {
+        println!();
+        match (&stopped, &result) {
+            (None, Ok(())) => println!("✓ WASM workload completed successfully"),
+            // The error is reported on the way out.
+            (None, Err(_)) => {}
+            (Some(reason), _) => println!("⏹ WASM workload stopped after {} iteration(s): {}", completed, reason),
+        }
+        if exceptions.is_enabled() {
+            println!("Suppressed readings: {}", exceptions);
+        }
+        for sink in &sinks {
+            println!("Sink {}", sink);
+        }
+        println!("Memory footprint: {}", stats);
+    } else {
+        if let Some(reason) = &stopped {
+            eprintln!("Stopped after {} iteration(s): {}", completed, reason);
+        }
+        if exceptions.is_enabled() {
+            eprintln!("Suppressed readings: {}", exceptions);
+        }
+        for sink in &sinks {
+            eprintln!("Sink {}", sink);
+        }
+        eprintln!("Memory footprint: {}", stats);
+    }
+    result.map(|()| stopped)
+}
+
+/// Push one iteration's readings to every sink; failures are logged, not fatal.
+fn publish(sinks: &mut [Box<dyn Sink>], i: u64, records: &[Record]) {
+    for sink in sinks {
+        if let Err(err) = sink.publish(records) {
+            eprintln!("[{}] Sink error: {}: {}", i, sink.name(), err);
+        }
+    }
+}
+
+/// Sleep until `due`, publishing nothing to the sinks at least every
+/// `max_idle` so their connections outlast long gaps in a trace. Returns the
+/// reason to stop if one was met on the way.
+fn wait_until(stop: &StopCondition, due: Instant, max_idle: Option<Duration>,
+              sinks: &mut [Box<dyn Sink>], i: u64) -> Option<StopReason> {
+    loop {
+        let left = due.saturating_duration_since(Instant::now());
+        match max_idle.filter(|&idle| left > idle) {
+            Some(idle) => stop.wait(idle),
+            None => {
+                stop.wait(left);
+                return stop.check();
+            }
+        }
+        if let Some(reason) = stop.check() {
+            return Some(reason);
+        }
+        publish(sinks, i, &[]);
+    }
+}
+
+fn check_memory_budget(settings: &Settings) -> Result<()> {
+    let used = memory::stats().footprint_bytes();
+    match settings.memory_budget {
+        Some(budget) if used > budget => Err(Error::MemoryBudget { used, budget }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use wasm_hello::config::FileConfig;
+
+    /// Accept one POST and return its body.
+    fn collector() -> (String, thread::JoinHandle<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/readings", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                assert_ne!(reader.read_line(&mut head).unwrap(), 0, "connection closed in the head");
+            }
+            let length = head.lines().find_map(|line| line.strip_prefix("Content-Length: ")).unwrap().parse().unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+            serde_json::from_slice(&body).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn a_failing_sensor_still_sends_the_pending_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let trace = dir.path().join("trace.csv");
+        fs::write(&trace, "timestamp,sensor,value\n1770656082,temperature,21.5\n1770656083,temperature,21.7\n1770656084,temperature,oops\n").unwrap();
+        let (url, collector) = collector();
+        let file: FileConfig = toml::from_str(&format!(
+            "iterations = 10\ninterval_ms = 0\nformat = \"json\"\n\
+             [[sensors]]\nkind = \"replay\"\nname = \"temperature\"\npath = {:?}\npace = \"fast\"\n\
+             [http]\nurl = {:?}\nbatch_size = 100\nlinger_ms = 600000\n",
+            trace.to_str().unwrap(), url,
+        )).unwrap();
+        let mut settings = Settings::default();
+        settings.apply_file(file).unwrap();
+
+        let err = run(&settings).unwrap_err();
+        assert!(matches!(err, Error::Sensor { .. }), "{:?}", err);
+        let batch = collector.join().unwrap();
+        let values: Vec<f64> = batch.as_array().unwrap().iter().map(|reading| reading["value"].as_f64().unwrap()).collect();
+        assert_eq!(values, [21.5, 21.7]);
+    }
+
+    #[test]
+    fn a_memory_budget_breach_still_sends_the_pending_batch() {
+        let (url, collector) = collector();
+        let file: FileConfig = toml::from_str(&format!(
+            "iterations = 3\ninterval_ms = 0\nformat = \"json\"\nmemory_budget_kb = 1\n\
+             [[sensors]]\nkind = \"simulated\"\nname = \"temperature\"\nbase = 21.5\n\
+             [http]\nurl = {:?}\nbatch_size = 100\nlinger_ms = 600000\n",
+            url,
+        )).unwrap();
+        let mut settings = Settings::default();
+        settings.apply_file(file).unwrap();
+
+        // The test binary runs on the tracking allocator, so 1 KiB is exceeded at once.
+        let err = run(&settings).unwrap_err();
+        assert!(matches!(err, Error::MemoryBudget { budget: 1024, .. }), "{:?}", err);
+        let batch = collector.join().unwrap();
+        assert_eq!(batch.as_array().unwrap().len(), 1);
+    }
+
+    /// Counts the publishes it gets.
+    struct Idle(usize);
+
+    impl std::fmt::Display for Idle {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "idle: {} publishes", self.0)
+        }
+    }
+
+    impl Sink for Idle {
+        fn name(&self) -> &str {
+            "idle"
+        }
+
+        fn publish(&mut self, records: &[Record]) -> io::Result<()> {
+            assert!(records.is_empty());
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn long_waits_publish_nothing_to_the_sinks_in_between() {
+        let stop = StopCondition::new(&Settings::default());
+        let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(Idle(0))];
+        let started = Instant::now();
+        let due = started + Duration::from_millis(250);
+        assert_eq!(wait_until(&stop, due, Some(Duration::from_millis(100)), &mut sinks, 1), None);
+        assert!(Instant::now() >= due);
+        assert_eq!(sinks[0].to_string(), "idle: 2 publishes");
+
+        assert_eq!(wait_until(&stop, Instant::now() + Duration::from_millis(20), None, &mut sinks, 2), None);
+        assert_eq!(sinks[0].to_string(), "idle: 2 publishes");
+    }
 }
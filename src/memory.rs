@@ -0,0 +1,156 @@
+//! Self-measured memory usage: linear memory size and heap statistics.
+//!
+//! Install [`TrackingAllocator`] as the `#[global_allocator]` to populate the
+//! heap counters; without it [`stats`] only reports the linear memory size.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Size of a WebAssembly page.
+pub const WASM_PAGE_SIZE: usize = 65_536;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// System allocator wrapper that counts allocations and tracks peak heap usage.
+pub struct TrackingAllocator;
+
+impl TrackingAllocator {
+    fn grew(size: usize) {
+        let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn shrank(size: usize) {
+        CURRENT.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            Self::grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            Self::grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        Self::shrank(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            if new_size > layout.size() {
+                Self::grew(new_size - layout.size());
+            } else {
+                Self::shrank(layout.size() - new_size);
+            }
+        }
+        new_ptr
+    }
+}
+
+/// Memory usage at the time [`stats`] was called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Size of linear memory 0; `None` when not running as WebAssembly.
+    pub linear_bytes: Option<usize>,
+    pub heap_current_bytes: usize,
+    pub heap_peak_bytes: usize,
+    pub allocations: u64,
+    pub deallocations: u64,
+}
+
+impl MemoryStats {
+    /// The figure checked against a memory budget: linear memory when running
+    /// as WebAssembly (it never shrinks, so it is also the peak), otherwise the
+    /// peak heap usage.
+    pub fn footprint_bytes(&self) -> usize {
+        self.linear_bytes.unwrap_or(self.heap_peak_bytes)
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(linear) = self.linear_bytes {
+            write!(f, "{} linear memory, ", kib(linear))?;
+        }
+        write!(f, "{} peak heap ({} allocations, {} frees)",
+               kib(self.heap_peak_bytes), self.allocations, self.deallocations)
+    }
+}
+
+pub fn stats() -> MemoryStats {
+    MemoryStats {
+        linear_bytes: linear_memory_bytes(),
+        heap_current_bytes: CURRENT.load(Ordering::Relaxed),
+        heap_peak_bytes: PEAK.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn linear_memory_bytes() -> Option<usize> {
+    Some(core::arch::wasm32::memory_size(0) * WASM_PAGE_SIZE)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn linear_memory_bytes() -> Option<usize> {
+    None
+}
+
+fn kib(bytes: usize) -> String {
+    format!("{:.1} KiB", bytes as f64 / 1024.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The library's tests run on the system allocator, so only this test moves the counters.
+    #[test]
+    fn tracks_allocations_frees_and_the_peak() {
+        let before = stats();
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        unsafe {
+            let first = TrackingAllocator.alloc(layout);
+            let second = TrackingAllocator.alloc_zeroed(layout);
+            let second = TrackingAllocator.realloc(second, layout, 1024);
+            TrackingAllocator.dealloc(second, Layout::from_size_align(1024, 8).unwrap());
+            TrackingAllocator.dealloc(first, layout);
+        }
+        let after = stats();
+        assert_eq!(after.allocations - before.allocations, 2);
+        assert_eq!(after.deallocations - before.deallocations, 2);
+        assert_eq!(after.heap_current_bytes, before.heap_current_bytes);
+        assert!(after.heap_peak_bytes >= before.heap_current_bytes + 8192, "{:?} -> {:?}", before, after);
+    }
+
+    #[test]
+    fn footprint_is_linear_memory_when_known() {
+        let stats = MemoryStats { linear_bytes: None, heap_current_bytes: 100, heap_peak_bytes: 300, allocations: 2, deallocations: 1 };
+        assert_eq!(stats.footprint_bytes(), 300);
+        assert!(stats.footprint_bytes() > 256);
+        let wasm = MemoryStats { linear_bytes: Some(2 * WASM_PAGE_SIZE), ..stats };
+        assert_eq!(wasm.footprint_bytes(), 131_072);
+        assert_eq!(wasm.to_string(), "128.0 KiB linear memory, 0.3 KiB peak heap (2 allocations, 1 frees)");
+    }
+}
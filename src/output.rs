@@ -0,0 +1,701 @@
+//! Output formats for sensor readings.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::Summary;
+use crate::alert::AlertEvent;
+use crate::clock::{self, Timestamp};
+use crate::reading::{Quality, Quantity, Reading, Tags};
+
+/// How readings are written to stdout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable lines with banner and summary (the original demo output).
+    #[default]
+    Text,
+    /// One JSON object per reading (JSON Lines), no banner.
+    Json,
+    /// InfluxDB line protocol, e.g. for Telegraf's `inputs.execd`/`inputs.tail`.
+    Influx,
+    /// Prometheus text exposition format, one complete block per iteration.
+    Prometheus,
+}
+
+impl Format {
+    /// Whether the banner and completion summary are printed around readings.
+    pub fn is_human(self) -> bool {
+        self == Format::Text
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "influx" => Ok(Format::Influx),
+            "prometheus" => Ok(Format::Prometheus),
+            _ => Err(format!("unknown format '{}', expected one of: text, json, influx, prometheus", s)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Influx => "influx",
+            Format::Prometheus => "prometheus",
+        })
+    }
+}
+
+/// A reading as it is reported in one loop iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<'a> {
+    /// Loop iteration, starting at 1.
+    pub seq: u64,
+    pub reading: &'a Reading,
+    /// The reading's own timestamp, or when the iteration started.
+    pub timestamp: Timestamp,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    seq: u64,
+    sensor: &'a str,
+    quantity: Quantity,
+    value: f64,
+    unit: &'a str,
+    quality: Quality,
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    tags: &'a Tags,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anomaly_score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anomaly: Option<bool>,
+    clock: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uptime_ms: Option<u128>,
+}
+
+/// Measurement name in line protocol and metric name prefix in Prometheus output.
+const METRIC_PREFIX: &str = "wasm_hello";
+
+/// Write the readings of one loop iteration in `format`.
+pub fn write_tick(out: &mut dyn Write, format: Format, records: &[Record]) -> io::Result<()> {
+    if format == Format::Prometheus {
+        write_prometheus(out, records)?;
+        return out.flush();
+    }
+    for record in records {
+        let reading = record.reading;
+        match format {
+            Format::Text => {
+                write!(out, "[{}] Sensor reading: {}={}{}, timestamp={}",
+                       record.seq,
+                       reading.sensor,
+                       reading.value,
+                       reading.unit,
+                       text_timestamp(record.timestamp))?;
+                if reading.quality != Quality::Good {
+                    write!(out, ", quality={}", reading.quality)?;
+                }
+                if let Some(anomaly) = reading.anomaly {
+                    write!(out, ", anomaly_score={:.2}", anomaly.score)?;
+                    if anomaly.flagged {
+                        write!(out, ", anomaly=true")?;
+                    }
+                }
+                for (key, value) in &reading.tags {
+                    write!(out, ", {}={}", key, value)?;
+                }
+                writeln!(out)?;
+            }
+            Format::Json => {
+                write_json(out, record)?;
+                writeln!(out)?;
+            }
+            // Without a wall timestamp the point is tagged and Telegraf stamps it on arrival.
+            Format::Influx => {
+                write!(out, "{},sensor={},quantity={}",
+                       METRIC_PREFIX,
+                       influx_tag(&reading.sensor),
+                       reading.quantity)?;
+                if !reading.unit.is_empty() {
+                    write!(out, ",unit={}", influx_tag(&reading.unit))?;
+                }
+                if !matches!(record.timestamp, Timestamp::Wall(_)) {
+                    write!(out, ",clock={}", record.timestamp.source())?;
+                }
+                write!(out, "{}", influx_tags(&reading.tags))?;
+                let mut fields: Vec<String> = influx_float("value", reading.value).into_iter().collect();
+                fields.push(format!("quality=\"{}\"", reading.quality));
+                if let Some(anomaly) = reading.anomaly {
+                    fields.extend(influx_float("anomaly_score", anomaly.score));
+                    fields.push(format!("anomaly={}", anomaly.flagged));
+                }
+                write!(out, " {}", fields.join(","))?;
+                match record.timestamp {
+                    Timestamp::Wall(since_epoch) => writeln!(out, " {}", since_epoch.as_nanos())?,
+                    _ => writeln!(out)?,
+                }
+            }
+            Format::Prometheus => unreachable!("handled per iteration above"),
+        }
+    }
+    out.flush()
+}
+
+/// One reading as a JSON object, without a trailing newline; the payload the
+/// network sinks send.
+pub fn write_json(out: &mut dyn Write, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(out, &json_record(record)).map_err(io::Error::from)
+}
+
+/// The object [`write_json`] writes, for sinks that encode it another way,
+/// e.g. as CBOR.
+pub fn json_value(record: &Record) -> io::Result<serde_json::Value> {
+    serde_json::to_value(json_record(record)).map_err(io::Error::from)
+}
+
+fn json_record<'a>(record: &Record<'a>) -> JsonRecord<'a> {
+    let reading = record.reading;
+    let wall = wall(record.timestamp);
+    JsonRecord {
+        seq: record.seq,
+        sensor: &reading.sensor,
+        quantity: reading.quantity,
+        value: reading.value,
+        unit: &reading.unit,
+        quality: reading.quality,
+        tags: &reading.tags,
+        anomaly_score: reading.anomaly.map(|anomaly| anomaly.score),
+        anomaly: reading.anomaly.map(|anomaly| anomaly.flagged),
+        clock: record.timestamp.source(),
+        timestamp: wall.map(clock::rfc3339),
+        epoch_ms: wall.map(|since_epoch| since_epoch.as_millis()),
+        uptime_ms: match record.timestamp {
+            Timestamp::Monotonic(uptime) => Some(uptime.as_millis()),
+            _ => None,
+        },
+    }
+}
+
+/// Write window summaries in `format`; nothing if there are none.
+pub fn write_summaries(out: &mut dyn Write, format: Format, seq: u64, summaries: &[Summary]) -> io::Result<()> {
+    if summaries.is_empty() {
+        return Ok(());
+    }
+    if format == Format::Prometheus {
+        write_prometheus_summaries(out, summaries)?;
+        return out.flush();
+    }
+    for summary in summaries {
+        let unit = &summary.unit;
+        match format {
+            Format::Text => {
+                write!(out, "[{}] Window summary: {} count={} min={}{} max={}{} mean={}{} stddev={}{} p95={}{}, window={}..{}",
+                       seq,
+                       summary.sensor,
+                       summary.count,
+                       summary.min, unit,
+                       summary.max, unit,
+                       summary.mean, unit,
+                       summary.stddev, unit,
+                       summary.p95, unit,
+                       text_timestamp(summary.start),
+                       text_timestamp(summary.end))?;
+                for (key, value) in &summary.tags {
+                    write!(out, ", {}={}", key, value)?;
+                }
+                writeln!(out)?;
+            }
+            Format::Json => {
+                let json = JsonSummary {
+                    seq,
+                    sensor: &summary.sensor,
+                    quantity: summary.quantity,
+                    unit,
+                    tags: &summary.tags,
+                    count: summary.count,
+                    min: summary.min,
+                    max: summary.max,
+                    mean: summary.mean,
+                    stddev: summary.stddev,
+                    p95: summary.p95,
+                    clock: summary.end.source(),
+                    window_start: wall(summary.start).map(clock::rfc3339),
+                    window_end: wall(summary.end).map(clock::rfc3339),
+                    window_start_ms: millis(summary.start),
+                    window_end_ms: millis(summary.end),
+                };
+                serde_json::to_writer(&mut *out, &json)?;
+                writeln!(out)?;
+            }
+            Format::Influx => {
+                write!(out, "{}_window,sensor={},quantity={}",
+                       METRIC_PREFIX,
+                       influx_tag(&summary.sensor),
+                       summary.quantity)?;
+                if !unit.is_empty() {
+                    write!(out, ",unit={}", influx_tag(unit))?;
+                }
+                if !matches!(summary.end, Timestamp::Wall(_)) {
+                    write!(out, ",clock={}", summary.end.source())?;
+                }
+                write!(out, "{}", influx_tags(&summary.tags))?;
+                let stats = [
+                    ("min", summary.min),
+                    ("max", summary.max),
+                    ("mean", summary.mean),
+                    ("stddev", summary.stddev),
+                    ("p95", summary.p95),
+                ];
+                let mut fields = vec![format!("count={}i", summary.count)];
+                fields.extend(stats.into_iter().filter_map(|(name, value)| influx_float(name, value)));
+                write!(out, " {}", fields.join(","))?;
+                match summary.end {
+                    Timestamp::Wall(since_epoch) => writeln!(out, " {}", since_epoch.as_nanos())?,
+                    _ => writeln!(out)?,
+                }
+            }
+            Format::Prometheus => unreachable!("handled above"),
+        }
+    }
+    out.flush()
+}
+
+#[derive(Serialize)]
+struct JsonSummary<'a> {
+    seq: u64,
+    sensor: &'a str,
+    quantity: Quantity,
+    unit: &'a str,
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    tags: &'a Tags,
+    count: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    stddev: f64,
+    p95: f64,
+    clock: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window_start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window_end: Option<String>,
+    /// Epoch or uptime milliseconds, depending on `clock`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window_start_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window_end_ms: Option<u128>,
+}
+
+fn wall(timestamp: Timestamp) -> Option<Duration> {
+    match timestamp {
+        Timestamp::Wall(since_epoch) => Some(since_epoch),
+        _ => None,
+    }
+}
+
+fn millis(timestamp: Timestamp) -> Option<u128> {
+    match timestamp {
+        Timestamp::Wall(time) | Timestamp::Monotonic(time) => Some(time.as_millis()),
+        Timestamp::Unsynced => None,
+    }
+}
+
+/// Prometheus exposition for one iteration. Samples carry no timestamps, which
+/// the node_exporter textfile collector rejects.
+fn write_prometheus(out: &mut dyn Write, records: &[Record]) -> io::Result<()> {
+    writeln!(out, "# HELP {}_reading Latest sensor reading.", METRIC_PREFIX)?;
+    writeln!(out, "# TYPE {}_reading gauge", METRIC_PREFIX)?;
+    for record in records {
+        let reading = record.reading;
+        writeln!(out, "{}_reading{{sensor=\"{}\",quantity=\"{}\",unit=\"{}\",quality=\"{}\"{}}} {}",
+                 METRIC_PREFIX,
+                 prometheus_label(&reading.sensor),
+                 reading.quantity,
+                 prometheus_label(&reading.unit),
+                 reading.quality,
+                 prometheus_tags(&reading.tags),
+                 prometheus_value(reading.value))?;
+    }
+    if records.iter().any(|record| record.reading.anomaly.is_some()) {
+        writeln!(out, "# HELP {}_reading_anomaly_score How unusual the latest reading is.", METRIC_PREFIX)?;
+        writeln!(out, "# TYPE {}_reading_anomaly_score gauge", METRIC_PREFIX)?;
+        for record in records {
+            if let Some(anomaly) = record.reading.anomaly {
+                writeln!(out, "{}_reading_anomaly_score{{sensor=\"{}\"{}}} {}",
+                         METRIC_PREFIX,
+                         prometheus_label(&record.reading.sensor),
+                         prometheus_tags(&record.reading.tags),
+                         prometheus_value(anomaly.score))?;
+            }
+        }
+        writeln!(out, "# HELP {}_reading_anomaly Whether the latest reading was flagged as an anomaly.", METRIC_PREFIX)?;
+        writeln!(out, "# TYPE {}_reading_anomaly gauge", METRIC_PREFIX)?;
+        for record in records {
+            if let Some(anomaly) = record.reading.anomaly {
+                writeln!(out, "{}_reading_anomaly{{sensor=\"{}\"{}}} {}",
+                         METRIC_PREFIX,
+                         prometheus_label(&record.reading.sensor),
+                         prometheus_tags(&record.reading.tags),
+                         u8::from(anomaly.flagged))?;
+            }
+        }
+    }
+    writeln!(out, "# HELP {}_reading_timestamp_seconds Unix time of the latest sensor reading.", METRIC_PREFIX)?;
+    writeln!(out, "# TYPE {}_reading_timestamp_seconds gauge", METRIC_PREFIX)?;
+    for record in records {
+        if let Timestamp::Wall(since_epoch) = record.timestamp {
+            writeln!(out, "{}_reading_timestamp_seconds{{sensor=\"{}\"{}}} {}",
+                     METRIC_PREFIX,
+                     prometheus_label(&record.reading.sensor),
+                     prometheus_tags(&record.reading.tags),
+                     since_epoch.as_secs_f64())?;
+        }
+    }
+    Ok(())
+}
+
+/// Write alert transitions to the alert stream: JSON Lines for the `json`
+/// format, `ALERT raised`/`ALERT cleared` lines otherwise.
+pub fn write_alerts(out: &mut dyn Write, format: Format, events: &[AlertEvent]) -> io::Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    for event in events {
+        if format == Format::Json {
+            let wall = wall(event.timestamp);
+            let json = JsonAlert {
+                event: "alert",
+                transition: event.transition.to_string(),
+                alert: &event.alert,
+                sensor: &event.sensor,
+                condition: event.condition.to_string(),
+                threshold: event.threshold,
+                value: event.value,
+                unit: &event.unit,
+                seq: event.seq,
+                clock: event.timestamp.source(),
+                timestamp: wall.map(clock::rfc3339),
+                epoch_ms: wall.map(|since_epoch| since_epoch.as_millis()),
+            };
+            serde_json::to_writer(&mut *out, &json)?;
+            writeln!(out)?;
+        } else {
+            writeln!(out, "ALERT {}: {} {}={}{} ({} {}{}), seq={}, timestamp={}",
+                     event.transition,
+                     event.alert,
+                     event.sensor,
+                     event.value,
+                     event.unit,
+                     event.condition,
+                     event.threshold,
+                     event.unit,
+                     event.seq,
+                     text_timestamp(event.timestamp))?;
+        }
+    }
+    out.flush()
+}
+
+#[derive(Serialize)]
+struct JsonAlert<'a> {
+    event: &'static str,
+    transition: String,
+    alert: &'a str,
+    sensor: &'a str,
+    condition: String,
+    threshold: f64,
+    value: f64,
+    unit: &'a str,
+    seq: u64,
+    clock: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch_ms: Option<u128>,
+}
+
+/// One `wasm_hello_window` sample per statistic, told apart by a `stat` label.
+fn write_prometheus_summaries(out: &mut dyn Write, summaries: &[Summary]) -> io::Result<()> {
+    writeln!(out, "# HELP {}_window Statistics of the readings in the last closed window.", METRIC_PREFIX)?;
+    writeln!(out, "# TYPE {}_window gauge", METRIC_PREFIX)?;
+    for summary in summaries {
+        let stats = [
+            ("count", summary.count as f64),
+            ("min", summary.min),
+            ("max", summary.max),
+            ("mean", summary.mean),
+            ("stddev", summary.stddev),
+            ("p95", summary.p95),
+        ];
+        for (stat, value) in stats {
+            writeln!(out, "{}_window{{sensor=\"{}\",quantity=\"{}\",unit=\"{}\",stat=\"{}\"{}}} {}",
+                     METRIC_PREFIX,
+                     prometheus_label(&summary.sensor),
+                     summary.quantity,
+                     prometheus_label(&summary.unit),
+                     stat,
+                     prometheus_tags(&summary.tags),
+                     prometheus_value(value))?;
+        }
+    }
+    Ok(())
+}
+
+fn text_timestamp(timestamp: Timestamp) -> String {
+    match timestamp {
+        Timestamp::Wall(since_epoch) => since_epoch.as_secs().to_string(),
+        Timestamp::Monotonic(uptime) => format!("+{:.3}s", uptime.as_secs_f64()),
+        Timestamp::Unsynced => "unsynced".to_string(),
+    }
+}
+
+/// Escape a line protocol tag value: commas, equals signs and spaces. Line
+/// breaks cannot be escaped and become spaces.
+fn influx_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        let c = if matches!(c, '\n' | '\r') { ' ' } else { c };
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Tags, each prefixed with a comma. Line protocol has no empty tag keys or
+/// values, so those are left out.
+fn influx_tags(tags: &Tags) -> String {
+    let mut line = String::new();
+    for (key, value) in tags {
+        if !key.is_empty() && !value.is_empty() {
+            line.push_str(&format!(",{}={}", influx_tag(key), influx_tag(value)));
+        }
+    }
+    line
+}
+
+/// A float field, or none for NaN and infinities, which line protocol cannot
+/// represent.
+fn influx_float(name: &str, value: f64) -> Option<String> {
+    value.is_finite().then(|| format!("{}={}", name, value))
+}
+
+/// Escape a Prometheus label value: backslashes, double quotes and newlines.
+fn prometheus_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Tags as extra labels, each prefixed with a comma. Characters not allowed
+/// in label names become underscores.
+fn prometheus_tags(tags: &Tags) -> String {
+    let mut labels = String::new();
+    for (key, value) in tags {
+        let name: String = key.chars()
+            .enumerate()
+            .map(|(i, c)| if c.is_ascii_alphabetic() || c == '_' || (i > 0 && c.is_ascii_digit()) { c } else { '_' })
+            .collect();
+        labels.push_str(&format!(",{}=\"{}\"", name, prometheus_label(value)));
+    }
+    labels
+}
+
+fn prometheus_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::{Condition, Transition};
+    use crate::reading::{Anomaly, Channel};
+
+    /// One tick of a multi-channel device, as each format writes it.
+    fn tick(format: Format, timestamp: Timestamp) -> String {
+        let mut humidity = Channel::new("bme280.humidity", "%");
+        humidity.tags.insert("site".to_string(), "lab 1".to_string());
+        let readings = [
+            Channel::new("bme280.temperature", "°C").reading(23.5),
+            Reading {
+                quality: Quality::Uncertain,
+                anomaly: Some(Anomaly { score: 3.5, flagged: true }),
+                ..humidity.reading(41.0)
+            },
+        ];
+        let records: Vec<Record> = readings.iter().map(|reading| Record { seq: 1, reading, timestamp }).collect();
+        let mut out = Vec::new();
+        write_tick(&mut out, format, &records).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const WALL: Timestamp = Timestamp::Wall(Duration::from_secs(1_770_656_082));
+
+    #[test]
+    fn text() {
+        assert_eq!(tick(Format::Text, WALL), concat!(
+            "[1] Sensor reading: bme280.temperature=23.5°C, timestamp=1770656082\n",
+            "[1] Sensor reading: bme280.humidity=41%, timestamp=1770656082, quality=uncertain, anomaly_score=3.50, anomaly=true, site=lab 1\n",
+        ));
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(tick(Format::Json, WALL), concat!(
+            r#"{"seq":1,"sensor":"bme280.temperature","quantity":"temperature","value":23.5,"unit":"°C","quality":"good","clock":"wall","timestamp":"2026-02-09T16:54:42.000Z","epoch_ms":1770656082000}"#, "\n",
+            r#"{"seq":1,"sensor":"bme280.humidity","quantity":"humidity","value":41.0,"unit":"%","quality":"uncertain","tags":{"site":"lab 1"},"anomaly_score":3.5,"anomaly":true,"clock":"wall","timestamp":"2026-02-09T16:54:42.000Z","epoch_ms":1770656082000}"#, "\n",
+        ));
+    }
+
+    #[test]
+    fn json_without_a_wall_clock() {
+        let monotonic = tick(Format::Json, Timestamp::Monotonic(Duration::from_millis(70_250)));
+        assert_eq!(monotonic.lines().next().unwrap(),
+                   r#"{"seq":1,"sensor":"bme280.temperature","quantity":"temperature","value":23.5,"unit":"°C","quality":"good","clock":"monotonic","uptime_ms":70250}"#);
+        let unsynced = tick(Format::Json, Timestamp::Unsynced);
+        assert_eq!(unsynced.lines().next().unwrap(),
+                   r#"{"seq":1,"sensor":"bme280.temperature","quantity":"temperature","value":23.5,"unit":"°C","quality":"good","clock":"unsynced"}"#);
+    }
+
+    #[test]
+    fn json_summaries_and_alerts() {
+        let summary = Summary {
+            sensor: "bme280.temperature".to_string(),
+            quantity: Quantity::Temperature,
+            unit: "°C".to_string(),
+            tags: Tags::new(),
+            start: Timestamp::Wall(Duration::from_secs(1_770_656_022)),
+            end: WALL,
+            count: 4,
+            min: 21.0,
+            max: 24.0,
+            mean: 22.5,
+            stddev: 1.25,
+            p95: 24.0,
+        };
+        let mut out = Vec::new();
+        write_summaries(&mut out, Format::Json, 7, &[summary]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            r#"{"seq":7,"sensor":"bme280.temperature","quantity":"temperature","unit":"°C","count":4,"min":21.0,"max":24.0,"mean":22.5,"stddev":1.25,"p95":24.0,"#,
+            r#""clock":"wall","window_start":"2026-02-09T16:53:42.000Z","window_end":"2026-02-09T16:54:42.000Z","window_start_ms":1770656022000,"window_end_ms":1770656082000}"#, "\n",
+        ));
+
+        let event = AlertEvent {
+            transition: Transition::Raised,
+            alert: "overheat".to_string(),
+            sensor: "bme280.temperature".to_string(),
+            condition: Condition::Above,
+            threshold: 30.0,
+            value: 31.5,
+            unit: "°C".to_string(),
+            seq: 7,
+            timestamp: WALL,
+        };
+        let mut out = Vec::new();
+        write_alerts(&mut out, Format::Json, &[event]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            r#"{"event":"alert","transition":"raised","alert":"overheat","sensor":"bme280.temperature","condition":"above","threshold":30.0,"value":31.5,"unit":"°C","seq":7,"#,
+            r#""clock":"wall","timestamp":"2026-02-09T16:54:42.000Z","epoch_ms":1770656082000}"#, "\n",
+        ));
+    }
+
+    #[test]
+    fn influx() {
+        assert_eq!(tick(Format::Influx, Timestamp::Unsynced), concat!(
+            "wasm_hello,sensor=bme280.temperature,quantity=temperature,unit=°C,clock=unsynced value=23.5,quality=\"good\"\n",
+            "wasm_hello,sensor=bme280.humidity,quantity=humidity,unit=%,clock=unsynced,site=lab\\ 1 value=41,quality=\"uncertain\",anomaly_score=3.5,anomaly=true\n",
+        ));
+    }
+
+    #[test]
+    fn prometheus() {
+        assert_eq!(tick(Format::Prometheus, WALL), concat!(
+            "# HELP wasm_hello_reading Latest sensor reading.\n",
+            "# TYPE wasm_hello_reading gauge\n",
+            "wasm_hello_reading{sensor=\"bme280.temperature\",quantity=\"temperature\",unit=\"°C\",quality=\"good\"} 23.5\n",
+            "wasm_hello_reading{sensor=\"bme280.humidity\",quantity=\"humidity\",unit=\"%\",quality=\"uncertain\",site=\"lab 1\"} 41\n",
+            "# HELP wasm_hello_reading_anomaly_score How unusual the latest reading is.\n",
+            "# TYPE wasm_hello_reading_anomaly_score gauge\n",
+            "wasm_hello_reading_anomaly_score{sensor=\"bme280.humidity\",site=\"lab 1\"} 3.5\n",
+            "# HELP wasm_hello_reading_anomaly Whether the latest reading was flagged as an anomaly.\n",
+            "# TYPE wasm_hello_reading_anomaly gauge\n",
+            "wasm_hello_reading_anomaly{sensor=\"bme280.humidity\",site=\"lab 1\"} 1\n",
+            "# HELP wasm_hello_reading_timestamp_seconds Unix time of the latest sensor reading.\n",
+            "# TYPE wasm_hello_reading_timestamp_seconds gauge\n",
+            "wasm_hello_reading_timestamp_seconds{sensor=\"bme280.temperature\"} 1770656082\n",
+            "wasm_hello_reading_timestamp_seconds{sensor=\"bme280.humidity\",site=\"lab 1\"} 1770656082\n",
+        ));
+    }
+
+    fn write(format: Format, reading: &Reading) -> String {
+        let mut out = Vec::new();
+        write_tick(&mut out, format, &[Record { seq: 1, reading, timestamp: WALL }]).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escapes_tags_and_labels() {
+        let mut channel = Channel::new("rack 2,slot=3", "");
+        channel.tags.insert("room".to_string(), "a \"b\"\\c\nd".to_string());
+        channel.tags.insert("empty".to_string(), String::new());
+        let reading = channel.reading(1.0);
+        assert_eq!(write(Format::Influx, &reading),
+                   "wasm_hello,sensor=rack\\ 2\\,slot\\=3,quantity=other,room=a\\ \"b\"\\c\\ d value=1,quality=\"good\" 1770656082000000000\n");
+        assert!(write(Format::Prometheus, &reading).contains(
+            "wasm_hello_reading{sensor=\"rack 2,slot=3\",quantity=\"other\",unit=\"\",quality=\"good\",empty=\"\",room=\"a \\\"b\\\"\\\\c\\nd\"} 1\n"));
+    }
+
+    #[test]
+    fn leaves_non_finite_values_out_of_line_protocol() {
+        let channel = Channel::new("probe", "V");
+        let nan = Reading { anomaly: Some(Anomaly { score: f64::INFINITY, flagged: true }), ..channel.reading(f64::NAN) };
+        assert_eq!(write(Format::Influx, &nan),
+                   "wasm_hello,sensor=probe,quantity=voltage,unit=V quality=\"good\",anomaly=true 1770656082000000000\n");
+        let prometheus = write(Format::Prometheus, &nan);
+        assert!(prometheus.contains("wasm_hello_reading{sensor=\"probe\",quantity=\"voltage\",unit=\"V\",quality=\"good\"} NaN\n"));
+        assert!(prometheus.contains("wasm_hello_reading_anomaly_score{sensor=\"probe\"} +Inf\n"));
+
+        let summary = Summary {
+            sensor: "probe".to_string(),
+            quantity: Quantity::Voltage,
+            unit: "V".to_string(),
+            tags: Tags::new(),
+            start: Timestamp::Unsynced,
+            end: Timestamp::Unsynced,
+            count: 2,
+            min: 1.0,
+            max: f64::INFINITY,
+            mean: f64::INFINITY,
+            stddev: f64::NAN,
+            p95: f64::INFINITY,
+        };
+        let mut out = Vec::new();
+        write_summaries(&mut out, Format::Influx, 1, &[summary]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "wasm_hello_window,sensor=probe,quantity=voltage,unit=V,clock=unsynced count=2i,min=1\n");
+    }
+}
@@ -0,0 +1,57 @@
+//! `wasi:http/incoming-handler` export for the `wasi-http` build, so the
+//! component can run under `wasmtime serve` as a Prometheus scrape target.
+//!
+//! `main` still provides `wasi:cli/run`; the routes themselves are in
+//! `wasm_hello::serve`.
+
+use std::io::Write;
+
+use wasi::exports::http::incoming_handler::Guest;
+use wasi::http::types::{Fields, IncomingRequest, Method, OutgoingBody, OutgoingResponse, ResponseOutparam};
+use wasm_hello::config::Settings;
+use wasm_hello::serve::{self, Response};
+
+struct Proxy;
+
+impl Guest for Proxy {
+    fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        let method = match request.method() {
+            Method::Get => "GET".to_string(),
+            Method::Head => "HEAD".to_string(),
+            Method::Post => "POST".to_string(),
+            Method::Put => "PUT".to_string(),
+            Method::Delete => "DELETE".to_string(),
+            Method::Connect => "CONNECT".to_string(),
+            Method::Options => "OPTIONS".to_string(),
+            Method::Trace => "TRACE".to_string(),
+            Method::Patch => "PATCH".to_string(),
+            Method::Other(method) => method,
+        };
+        let path = request.path_with_query().unwrap_or_else(|| "/".to_string());
+        // Arguments are empty under a proxy host; settings come from the
+        // environment and the configuration file.
+        let response = match Settings::load() {
+            Ok(settings) => serve::respond(&method, &path, || serve::sample(&settings)),
+            Err(err) => Response::text(500, format!("error: {}\n", err)),
+        };
+        send(response, response_out);
+    }
+}
+
+fn send(response: Response, out: ResponseOutparam) {
+    let headers: Vec<(String, Vec<u8>)> = response.headers.into_iter()
+        .map(|(name, value)| (name.to_string(), value.into_bytes()))
+        .collect();
+    let outgoing = OutgoingResponse::new(Fields::from_list(&headers).expect("valid response headers"));
+    outgoing.set_status_code(response.status).expect("valid status code");
+    let body = outgoing.body().expect("body taken once");
+    ResponseOutparam::set(out, Ok(outgoing));
+    {
+        let mut stream = body.write().expect("body stream taken once");
+        // The client may be gone; there is no one left to tell.
+        let _ = stream.write_all(&response.body).and_then(|()| Write::flush(&mut stream));
+    }
+    let _ = OutgoingBody::finish(body, None);
+}
+
+wasi::http::proxy::export!(Proxy);
@@ -0,0 +1,223 @@
+//! The reading model: what sensors produce and every output format writes.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Timestamp;
+
+/// Free-form `key = value` labels attached to a channel, e.g. `site = "lab"`.
+pub type Tags = BTreeMap<String, String>;
+
+/// Physical quantity a channel measures.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantity {
+    Temperature,
+    Humidity,
+    Pressure,
+    Voltage,
+    Current,
+    Power,
+    #[default]
+    Other,
+}
+
+impl Quantity {
+    /// Unit reported when a channel does not configure one.
+    pub fn default_unit(self) -> &'static str {
+        match self {
+            Quantity::Temperature => "°C",
+            Quantity::Humidity => "%",
+            Quantity::Pressure => "Pa",
+            Quantity::Voltage => "V",
+            Quantity::Current => "A",
+            Quantity::Power => "W",
+            Quantity::Other => "",
+        }
+    }
+
+    /// Best guess from a unit symbol, falling back to a sensor name such as
+    /// `humidity`.
+    pub fn infer(sensor: &str, unit: &str) -> Quantity {
+        match unit {
+            "°C" | "°F" | "K" => Quantity::Temperature,
+            "%" | "%RH" => Quantity::Humidity,
+            "Pa" | "hPa" | "mbar" | "kPa" | "bar" | "psi" => Quantity::Pressure,
+            "V" | "mV" => Quantity::Voltage,
+            "A" | "mA" => Quantity::Current,
+            "W" | "kW" => Quantity::Power,
+            _ => sensor.parse().unwrap_or_default(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quantity::Temperature => "temperature",
+            Quantity::Humidity => "humidity",
+            Quantity::Pressure => "pressure",
+            Quantity::Voltage => "voltage",
+            Quantity::Current => "current",
+            Quantity::Power => "power",
+            Quantity::Other => "other",
+        }
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature" => Ok(Quantity::Temperature),
+            "humidity" => Ok(Quantity::Humidity),
+            "pressure" => Ok(Quantity::Pressure),
+            "voltage" => Ok(Quantity::Voltage),
+            "current" => Ok(Quantity::Current),
+            "power" => Ok(Quantity::Power),
+            "other" => Ok(Quantity::Other),
+            _ => Err(format!("unknown quantity '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How far a reading can be trusted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    #[default]
+    Good,
+    /// Usable but suspect, e.g. out of the sensor's rated range.
+    Uncertain,
+    /// Known to be wrong.
+    Bad,
+}
+
+impl Quality {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quality::Good => "good",
+            Quality::Uncertain => "uncertain",
+            Quality::Bad => "bad",
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "good" => Ok(Quality::Good),
+            "uncertain" => Ok(Quality::Uncertain),
+            "bad" => Ok(Quality::Bad),
+            _ => Err(format!("unknown quality '{}', expected one of: good, uncertain, bad", s)),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Result of a streaming anomaly detector, see [`crate::anomaly`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anomaly {
+    /// How unusual the value is, in the detector's units (e.g. standard
+    /// deviations).
+    pub score: f64,
+    /// Whether the score is past the detector's limit.
+    pub flagged: bool,
+}
+
+/// What one channel of a sensor measures; sensors keep one per channel and
+/// stamp their readings from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Sensor id used in the output, e.g. `temperature` or `bme280.humidity`.
+    pub sensor: String,
+    pub quantity: Quantity,
+    pub unit: String,
+    pub tags: Tags,
+}
+
+impl Channel {
+    /// A channel whose quantity is inferred from `unit` and `sensor`. An empty
+    /// unit becomes the quantity's default.
+    pub fn new(sensor: impl Into<String>, unit: impl Into<String>) -> Self {
+        let sensor = sensor.into();
+        let unit = unit.into();
+        Self::with_quantity(Quantity::infer(&sensor, &unit), sensor, unit)
+    }
+
+    pub fn with_quantity(quantity: Quantity, sensor: impl Into<String>, unit: impl Into<String>) -> Self {
+        let unit = unit.into();
+        Channel {
+            sensor: sensor.into(),
+            quantity,
+            unit: if unit.is_empty() { quantity.default_unit().to_string() } else { unit },
+            tags: Tags::new(),
+        }
+    }
+
+    /// A good-quality reading of `value`, timestamped by the loop.
+    pub fn reading(&self, value: f64) -> Reading {
+        Reading {
+            sensor: self.sensor.clone(),
+            quantity: self.quantity,
+            unit: self.unit.clone(),
+            value,
+            quality: Quality::Good,
+            tags: self.tags.clone(),
+            anomaly: None,
+            timestamp: None,
+        }
+    }
+}
+
+/// A single value produced by a sensor channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub sensor: String,
+    pub quantity: Quantity,
+    pub unit: String,
+    pub value: f64,
+    pub quality: Quality,
+    pub tags: Tags,
+    /// Set when the channel has an anomaly detector that has warmed up.
+    pub anomaly: Option<Anomaly>,
+    /// When the value was recorded, if the sensor knows better than the loop.
+    pub timestamp: Option<Timestamp>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_quantity_and_default_unit() {
+        let cases = [
+            (("temperature", "°C"), (Quantity::Temperature, "°C")),
+            (("outdoor", "°F"), (Quantity::Temperature, "°F")),
+            (("humidity", ""), (Quantity::Humidity, "%")),
+            (("barometer", "hPa"), (Quantity::Pressure, "hPa")),
+            (("battery", "V"), (Quantity::Voltage, "V")),
+            (("pressure", ""), (Quantity::Pressure, "Pa")),
+            (("counter", "rpm"), (Quantity::Other, "rpm")),
+        ];
+        for ((sensor, unit), (quantity, expected_unit)) in cases {
+            let channel = Channel::new(sensor, unit);
+            assert_eq!((channel.quantity, channel.unit.as_str()), (quantity, expected_unit), "{} {}", sensor, unit);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! The [`Sensor`] trait, its backends and the registry the loop reports from.
+
+mod replay;
+mod simulated;
+mod thermal;
+
+use std::time::Instant;
+
+pub use replay::{Pace, ReplaySensor, TraceFormat};
+pub use simulated::{Signal, SimulatedSensor};
+pub(crate) use simulated::SplitMix64;
+pub use thermal::{DEFAULT_THERMAL_ROOT, SysfsThermalSensor};
+
+use crate::anomaly::Monitored;
+use crate::calibration::Calibrated;
+use crate::config::SensorConfig;
+use crate::error::{Error, Result};
+use crate::reading::{Channel, Reading};
+
+/// A source of periodic readings.
+///
+/// Implement this for real hardware drivers and add them to the
+/// [`registry`]; the reporting loop in `main` only talks to this trait.
+pub trait Sensor {
+    /// Name used in error messages, e.g. `temperature`.
+    fn name(&self) -> &str;
+
+    /// Take the next reading of every channel. Devices that measure several
+    /// quantities return one reading each; an empty list is a dropout, which
+    /// is skipped rather than treated as a failure.
+    fn read(&mut self) -> Result<Vec<Reading>>;
+
+    /// Whether the sensor has no more readings (e.g. the end of a replayed
+    /// trace). Exhausted sensors are skipped; the loop ends once all are.
+    fn is_exhausted(&self) -> bool {
+        false
+    }
+
+    /// When the next reading is due, for sensors that pace themselves. The
+    /// loop waits for the earliest due sensor before reading.
+    fn next_due(&self) -> Option<Instant> {
+        None
+    }
+}
+
+/// Sensors reported on every iteration of the loop.
+///
+/// An empty configuration yields the demo's simulated temperature sensor.
+pub fn registry(configs: &[SensorConfig]) -> Result<Vec<Box<dyn Sensor>>> {
+    if configs.is_empty() {
+        return Ok(vec![Box::new(SimulatedSensor::new(Channel::new("temperature", "°C"), 20.0, 3.0))]);
+    }
+    configs.iter().map(build).collect()
+}
+
+/// Read `sensors` once, like one iteration of the loop. Exhausted sensors
+/// are skipped, and a failure is passed to `failed` and left out; this fails
+/// only if every sensor read failed, with the last error.
+pub fn read_all<'a>(sensors: impl IntoIterator<Item = &'a mut Box<dyn Sensor>>, mut failed: impl FnMut(&Error)) -> Result<Vec<Reading>> {
+    let mut readings = Vec::new();
+    let mut last_error = None;
+    for sensor in sensors.into_iter().filter(|sensor| !sensor.is_exhausted()) {
+        match sensor.read() {
+            Ok(channels) => readings.extend(channels),
+            Err(err) => {
+                failed(&err);
+                last_error = Some(err);
+            }
+        }
+    }
+    match (readings.is_empty(), last_error) {
+        (true, Some(err)) => Err(err),
+        _ => Ok(readings),
+    }
+}
+
+fn build(config: &SensorConfig) -> Result<Box<dyn Sensor>> {
+    let channel = config.channel();
+    let sensor: Box<dyn Sensor> = match config {
+        SensorConfig::Simulated { base, step, signal, .. } => {
+            Box::new(SimulatedSensor::with_signal(channel.clone(), *base, *step, signal.clone()))
+        }
+        SensorConfig::Thermal { root, zone, .. } => Box::new(SysfsThermalSensor::new(channel.clone(), root, *zone)),
+        SensorConfig::Replay { name, path, source, format, pace, .. } => {
+            let source = source.as_deref().unwrap_or(name);
+            Box::new(ReplaySensor::open(channel.clone(), path, source, *format, *pace)?)
+        }
+    };
+    let options = config.channel_options();
+    let sensor: Box<dyn Sensor> = match (&options.calibration, &options.convert) {
+        (None, None) => sensor,
+        (calibration, _) => {
+            // Checked when a file is loaded, but configs can be built in code too.
+            let conversion = config.conversion()
+                .map_err(|reason| Error::Usage(format!("sensor '{}': {}", channel.sensor, reason)))?;
+            Box::new(Calibrated::new(sensor, calibration.clone(), conversion))
+        }
+    };
+    Ok(match options.anomaly {
+        Some(detector) => Box::new(Monitored::new(sensor, detector)),
+        None => sensor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sensor whose every read fails.
+    struct Broken;
+
+    impl Sensor for Broken {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        fn read(&mut self) -> Result<Vec<Reading>> {
+            Err(Error::sensor("broken", "no response"))
+        }
+    }
+
+    #[test]
+    fn read_all_fails_only_if_every_sensor_failed() {
+        let simulated = || -> Box<dyn Sensor> { Box::new(SimulatedSensor::new(Channel::new("temperature", "°C"), 20.0, 1.0)) };
+        let mut sensors: Vec<Box<dyn Sensor>> = vec![Box::new(Broken), simulated(), Box::new(Broken)];
+        let mut failures = 0;
+        let readings = read_all(&mut sensors, |_| failures += 1).unwrap();
+        assert_eq!((readings.len(), failures), (1, 2));
+
+        let mut sensors: Vec<Box<dyn Sensor>> = vec![Box::new(Broken), Box::new(Broken)];
+        let err = read_all(&mut sensors, |_| {}).unwrap_err();
+        assert_eq!(err.to_string(), Error::sensor("broken", "no response").to_string());
+        assert!(read_all(&mut Vec::new(), |_| {}).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::clock::{self, Timestamp};
+use crate::error::{Error, Result};
+use crate::reading::{Channel, Quality, Reading};
+
+use super::Sensor;
+
+/// How a [`ReplaySensor`] spaces its readings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pace {
+    /// Keep the gaps between recorded timestamps.
+    #[default]
+    Original,
+    /// Emit one recorded reading per loop iteration, as fast as the loop runs.
+    Fast,
+}
+
+/// Trace file layout, by default derived from the file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceFormat {
+    /// Comma-separated with a header naming `timestamp`, `sensor` and `value`
+    /// columns, and optionally `quality` (in any order). No quoting.
+    Csv,
+    /// One JSON object per line with `timestamp` (or `epoch_ms`), `sensor`,
+    /// `value` and optionally `quality`; the module's own `--format json`
+    /// output qualifies.
+    Jsonl,
+}
+
+impl TraceFormat {
+    fn from_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => TraceFormat::Csv,
+            _ => TraceFormat::Jsonl,
+        }
+    }
+}
+
+/// One line of a trace.
+#[derive(Debug, Clone, PartialEq)]
+struct TraceRecord {
+    timestamp: Duration,
+    sensor: String,
+    value: f64,
+    quality: Quality,
+}
+
+/// Column positions from a CSV header.
+#[derive(Debug, Clone, Copy)]
+struct CsvColumns {
+    timestamp: usize,
+    sensor: usize,
+    value: usize,
+    quality: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct JsonTraceRecord {
+    #[serde(default)]
+    timestamp: Option<serde_json::Value>,
+    #[serde(default)]
+    epoch_ms: Option<u64>,
+    sensor: String,
+    value: f64,
+    #[serde(default)]
+    quality: Quality,
+}
+
+/// Replays recorded readings of one sensor from a CSV or JSONL trace.
+///
+/// Readings carry their recorded timestamps. The sensor is exhausted after
+/// the last matching record; the loop finishes once every sensor is.
+pub struct ReplaySensor {
+    channel: Channel,
+    path: PathBuf,
+    /// Only records whose `sensor` column matches are replayed.
+    source: String,
+    format: TraceFormat,
+    pace: Pace,
+    lines: Lines<BufReader<File>>,
+    line_no: usize,
+    csv_columns: Option<CsvColumns>,
+    /// Look-ahead; a malformed line is reported by the `read` that reaches it.
+    next: Option<Result<TraceRecord>>,
+    first: Option<Duration>,
+    started: Instant,
+}
+
+impl ReplaySensor {
+    /// Open the trace and look ahead to the first record for `source`.
+    pub fn open(channel: Channel, path: impl AsRef<Path>, source: impl Into<String>,
+                format: Option<TraceFormat>, pace: Pace) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)
+            .map_err(|err| Error::sensor(&channel.sensor, format!("{}: {}", path.display(), err)))?;
+        let mut sensor = ReplaySensor {
+            format: format.unwrap_or_else(|| TraceFormat::from_path(&path)),
+            channel,
+            path,
+            source: source.into(),
+            pace,
+            lines: BufReader::new(file).lines(),
+            line_no: 0,
+            csv_columns: None,
+            next: None,
+            first: None,
+            started: Instant::now(),
+        };
+        sensor.next = sensor.next_record().transpose();
+        sensor.first = match &sensor.next {
+            Some(Ok(record)) => Some(record.timestamp),
+            _ => None,
+        };
+        Ok(sensor)
+    }
+
+    fn error(&self, reason: impl std::fmt::Display) -> Error {
+        Error::sensor(&self.channel.sensor, format!("{}:{}: {}", self.path.display(), self.line_no, reason))
+    }
+
+    /// Next record for this sensor, skipping blank lines, `#` comments and other sensors.
+    fn next_record(&mut self) -> Result<Option<TraceRecord>> {
+        while let Some(line) = self.lines.next() {
+            self.line_no += 1;
+            let line = line.map_err(|err| self.error(err))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let record = match self.format {
+                TraceFormat::Csv => match self.csv_columns {
+                    None => {
+                        self.csv_columns = Some(self.csv_header(line)?);
+                        continue;
+                    }
+                    Some(columns) => self.csv_record(line, columns)?,
+                },
+                TraceFormat::Jsonl => self.json_record(line)?,
+            };
+            if record.sensor == self.source {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+
+    fn csv_header(&self, line: &str) -> Result<CsvColumns> {
+        let names: Vec<&str> = line.split(',').map(str::trim).collect();
+        let find = |wanted: &[&str]| names.iter().position(|name| wanted.contains(name));
+        let column = |wanted: &[&str]| find(wanted)
+            .ok_or_else(|| self.error(format!("header has no '{}' column", wanted[0])));
+        Ok(CsvColumns {
+            timestamp: column(&["timestamp", "time"])?,
+            sensor: column(&["sensor"])?,
+            value: column(&["value"])?,
+            quality: find(&["quality"]),
+        })
+    }
+
+    fn csv_record(&self, line: &str, columns: CsvColumns) -> Result<TraceRecord> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let field = |i: usize| fields.get(i).copied().ok_or_else(|| self.error("missing column"));
+        Ok(TraceRecord {
+            timestamp: parse_timestamp(field(columns.timestamp)?).ok_or_else(|| self.error("invalid timestamp"))?,
+            sensor: field(columns.sensor)?.to_string(),
+            value: field(columns.value)?.parse().map_err(|_| self.error("invalid value"))?,
+            quality: match columns.quality.map(field).transpose()? {
+                Some(quality) if !quality.is_empty() => quality.parse().map_err(|err| self.error(err))?,
+                _ => Quality::Good,
+            },
+        })
+    }
+
+    fn json_record(&self, line: &str) -> Result<TraceRecord> {
+        let record: JsonTraceRecord = serde_json::from_str(line).map_err(|err| self.error(err))?;
+        let timestamp = match (record.epoch_ms, &record.timestamp) {
+            (Some(ms), _) => Some(Duration::from_millis(ms)),
+            (None, Some(serde_json::Value::Number(secs))) => secs.as_f64().and_then(|s| Duration::try_from_secs_f64(s).ok()),
+            (None, Some(serde_json::Value::String(text))) => parse_timestamp(text),
+            _ => None,
+        };
+        Ok(TraceRecord {
+            timestamp: timestamp.ok_or_else(|| self.error("missing or invalid timestamp"))?,
+            sensor: record.sensor,
+            value: record.value,
+            quality: record.quality,
+        })
+    }
+}
+
+/// Epoch seconds (fractional allowed) or RFC 3339.
+fn parse_timestamp(text: &str) -> Option<Duration> {
+    match text.parse::<f64>() {
+        Ok(secs) => Duration::try_from_secs_f64(secs).ok(),
+        Err(_) => clock::parse_rfc3339(text),
+    }
+}
+
+impl Sensor for ReplaySensor {
+    fn name(&self) -> &str {
+        &self.channel.sensor
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        let record = self.next.take()
+            .ok_or_else(|| Error::sensor(&self.channel.sensor, format!("{}: trace exhausted", self.path.display())))?;
+        self.next = self.next_record().transpose();
+        let record = record?;
+        if self.first.is_none() {
+            self.first = Some(record.timestamp);
+        }
+        Ok(vec![Reading {
+            quality: record.quality,
+            timestamp: Some(Timestamp::Wall(record.timestamp)),
+            ..self.channel.reading(record.value)
+        }])
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.next.is_none()
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        match (self.pace, &self.next, self.first) {
+            (Pace::Original, Some(Ok(next)), Some(first)) => Some(self.started + next.timestamp.saturating_sub(first)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/incident.csv");
+
+    fn replay(path: &Path, source: &str, pace: Pace) -> ReplaySensor {
+        ReplaySensor::open(Channel::new(source, "°C"), path, source, None, pace).unwrap()
+    }
+
+    fn drain(sensor: &mut ReplaySensor) -> Vec<(f64, Option<Timestamp>)> {
+        let mut readings = Vec::new();
+        while !sensor.is_exhausted() {
+            readings.extend(sensor.read().unwrap().into_iter().map(|reading| (reading.value, reading.timestamp)));
+        }
+        readings
+    }
+
+    #[test]
+    fn replays_csv_for_one_sensor_in_order() {
+        let mut sensor = replay(Path::new(TRACE), "temperature", Pace::Fast);
+        let values: Vec<f64> = drain(&mut sensor).into_iter().map(|(value, _)| value).collect();
+        assert_eq!(values, [23.0, 26.0, 29.0, 32.0, 35.0]);
+        assert!(sensor.read().is_err());
+    }
+
+    #[test]
+    fn keeps_recorded_timestamps() {
+        let mut sensor = replay(Path::new(TRACE), "humidity", Pace::Fast);
+        let readings = drain(&mut sensor);
+        assert_eq!(readings[0], (41.5, Some(Timestamp::Wall(Duration::from_secs(1_770_656_082)))));
+        assert_eq!(readings[1].1, Some(Timestamp::Wall(Duration::from_secs(1_770_656_086))));
+    }
+
+    #[test]
+    fn replays_own_json_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        std::fs::write(&path, concat!(
+            r#"{"seq":1,"sensor":"temperature","value":23.0,"unit":"°C","clock":"wall","timestamp":"2026-02-09T16:54:42.000Z","epoch_ms":1770656082000}"#, "\n",
+            r#"{"sensor":"temperature","value":26.5,"timestamp":"2026-02-09T17:54:44.500+01:00"}"#, "\n",
+            r#"{"sensor":"temperature","value":29.0,"timestamp":1770656086}"#, "\n",
+        )).unwrap();
+        let mut sensor = replay(&path, "temperature", Pace::Fast);
+        assert_eq!(drain(&mut sensor), [
+            (23.0, Some(Timestamp::Wall(Duration::from_secs(1_770_656_082)))),
+            (26.5, Some(Timestamp::Wall(Duration::from_millis(1_770_656_084_500)))),
+            (29.0, Some(Timestamp::Wall(Duration::from_secs(1_770_656_086)))),
+        ]);
+    }
+
+    #[test]
+    fn replays_quality_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.csv");
+        std::fs::write(&path, "time,value,quality,sensor\n1770656082,23,good,temperature\n1770656084,85,bad,temperature\n1770656086,29,,temperature\n1770656088,32,warm,temperature\n").unwrap();
+        let mut sensor = replay(&path, "temperature", Pace::Fast);
+        assert_eq!(sensor.read().unwrap()[0].quality, Quality::Good);
+        assert_eq!(sensor.read().unwrap()[0].quality, Quality::Bad);
+        assert_eq!(sensor.read().unwrap()[0].quality, Quality::Good);
+        let err = sensor.read().unwrap_err();
+        assert!(err.to_string().ends_with("trace.csv:5: unknown quality 'warm', expected one of: good, uncertain, bad"), "{}", err);
+    }
+
+    #[test]
+    fn original_pace_follows_recorded_gaps() {
+        let mut sensor = replay(Path::new(TRACE), "temperature", Pace::Original);
+        let start = sensor.started;
+        assert_eq!(sensor.next_due(), Some(start));
+        sensor.read().unwrap();
+        assert_eq!(sensor.next_due(), Some(start + Duration::from_secs(2)));
+
+        let mut fast = replay(Path::new(TRACE), "temperature", Pace::Fast);
+        assert_eq!(fast.next_due(), None);
+        fast.read().unwrap();
+        assert_eq!(fast.next_due(), None);
+    }
+
+    #[test]
+    fn reports_line_of_bad_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.csv");
+        std::fs::write(&path, "timestamp,sensor,value\n1770656082,temperature,23\n1770656084,temperature,warm\n").unwrap();
+        let mut sensor = replay(&path, "temperature", Pace::Fast);
+        assert_eq!(sensor.read().unwrap()[0].value, 23.0);
+        let err = sensor.read().unwrap_err();
+        assert!(err.to_string().ends_with("trace.csv:3: invalid value"), "{}", err);
+        assert!(sensor.is_exhausted());
+    }
+}
@@ -0,0 +1,208 @@
+use std::f64::consts::TAU;
+
+use serde::Deserialize;
+
+use crate::error::Result;
+use crate::reading::{Channel, Reading};
+
+use super::Sensor;
+
+/// Shape and faults layered on top of the linear generator.
+///
+/// Rates are per-reading probabilities and periods are counted in readings,
+/// so a run is reproducible from its seed regardless of the interval. The
+/// default adds nothing, leaving the demo's straight line.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Signal {
+    /// Seed for the noise and fault generator.
+    pub seed: u64,
+    /// Standard deviation of the Gaussian noise added to every reading.
+    pub noise: f64,
+    /// Amplitude of the sinusoidal cycle.
+    pub amplitude: f64,
+    /// Length of the cycle in readings, e.g. 43200 for a day at 2 s.
+    pub period: u64,
+    /// Standard deviation of the random walk the baseline drifts by per reading.
+    pub drift: f64,
+    /// Probability of a spike of `±spike` on a reading.
+    pub spike_rate: f64,
+    pub spike: f64,
+    /// Probability of the sensor getting stuck at its last value for `stuck_for` readings.
+    pub stuck_rate: f64,
+    pub stuck_for: u64,
+    /// Probability of a reading being dropped.
+    pub dropout_rate: f64,
+}
+
+/// Demo generator: `base + n * step` for the n-th read, shaped by a [`Signal`].
+pub struct SimulatedSensor {
+    channel: Channel,
+    base: f64,
+    step: f64,
+    signal: Signal,
+    rng: SplitMix64,
+    reads: u64,
+    offset: f64,
+    stuck: Option<(f64, u64)>,
+    last: Option<f64>,
+}
+
+impl SimulatedSensor {
+    pub fn new(channel: Channel, base: f64, step: f64) -> Self {
+        Self::with_signal(channel, base, step, Signal::default())
+    }
+
+    pub fn with_signal(channel: Channel, base: f64, step: f64, signal: Signal) -> Self {
+        SimulatedSensor {
+            channel,
+            base,
+            step,
+            rng: SplitMix64(signal.seed),
+            signal,
+            reads: 0,
+            offset: 0.0,
+            stuck: None,
+            last: None,
+        }
+    }
+}
+
+impl Sensor for SimulatedSensor {
+    fn name(&self) -> &str {
+        &self.channel.sensor
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        self.reads += 1;
+        let signal = &self.signal;
+        // Draw the same numbers on every read so that enabling one fault does
+        // not move the others.
+        let (noise, walk) = self.rng.gaussian_pair();
+        let (spike, sign, stuck, dropout) = (self.rng.unit(), self.rng.unit(), self.rng.unit(), self.rng.unit());
+
+        self.offset += walk * signal.drift;
+        let mut value = self.base + self.reads as f64 * self.step + self.offset + noise * signal.noise;
+        if signal.period > 0 {
+            value += signal.amplitude * (TAU * self.reads as f64 / signal.period as f64).sin();
+        }
+        if spike < signal.spike_rate {
+            value += if sign < 0.5 { -signal.spike } else { signal.spike };
+        }
+
+        if self.stuck.is_none() && stuck < signal.stuck_rate && signal.stuck_for > 0 {
+            if let Some(last) = self.last {
+                self.stuck = Some((last, signal.stuck_for));
+            }
+        }
+        if let Some((held, remaining)) = self.stuck {
+            value = held;
+            self.stuck = (remaining > 1).then_some((held, remaining - 1));
+        }
+        self.last = Some(value);
+
+        if dropout < signal.dropout_rate {
+            return Ok(Vec::new());
+        }
+        Ok(vec![self.channel.reading(value)])
+    }
+}
+
+/// Small, dependency-free PRNG; good enough for test signals and protocol
+/// jitter, not for secrets.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Two independent standard normal samples (Box-Muller).
+    fn gaussian_pair(&mut self) -> (f64, f64) {
+        let radius = (-2.0 * (1.0 - self.unit()).ln()).sqrt();
+        let angle = TAU * self.unit();
+        (radius * angle.cos(), radius * angle.sin())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulated(base: f64, step: f64, signal: Signal) -> SimulatedSensor {
+        SimulatedSensor::with_signal(Channel::new("t", "°C"), base, step, signal)
+    }
+
+    fn values(sensor: &mut SimulatedSensor, n: usize) -> Vec<Option<f64>> {
+        (0..n).map(|_| sensor.read().unwrap().first().map(|reading| reading.value)).collect()
+    }
+
+    #[test]
+    fn default_signal_is_the_demo_line() {
+        let mut sensor = SimulatedSensor::new(Channel::new("temperature", "°C"), 20.0, 3.0);
+        assert_eq!(values(&mut sensor, 5), [23.0, 26.0, 29.0, 32.0, 35.0].map(Some));
+    }
+
+    #[test]
+    fn same_seed_repeats() {
+        let signal = Signal { seed: 7, noise: 0.5, drift: 0.1, spike_rate: 0.1, spike: 10.0, dropout_rate: 0.1, ..Signal::default() };
+        let mut a = simulated(20.0, 0.0, signal.clone());
+        let mut b = simulated(20.0, 0.0, signal.clone());
+        let mut c = simulated(20.0, 0.0, Signal { seed: 8, ..signal });
+        let a = values(&mut a, 100);
+        assert_eq!(a, values(&mut b, 100));
+        assert_ne!(a, values(&mut c, 100));
+    }
+
+    #[test]
+    fn noise_has_configured_spread() {
+        let signal = Signal { seed: 1, noise: 2.0, ..Signal::default() };
+        let mut sensor = simulated(0.0, 0.0, signal);
+        let values: Vec<f64> = values(&mut sensor, 10_000).into_iter().flatten().collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        assert!(mean.abs() < 0.1, "mean {}", mean);
+        assert!((variance.sqrt() - 2.0).abs() < 0.1, "stddev {}", variance.sqrt());
+    }
+
+    #[test]
+    fn cycle_peaks_a_quarter_period_in() {
+        let signal = Signal { amplitude: 5.0, period: 8, ..Signal::default() };
+        let mut sensor = simulated(20.0, 0.0, signal);
+        let values: Vec<f64> = values(&mut sensor, 8).into_iter().flatten().collect();
+        assert!((values[1] - 25.0).abs() < 1e-9);
+        assert!((values[5] - 15.0).abs() < 1e-9);
+        assert!((values[7] - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn faults_fire_at_their_rates() {
+        let signal = Signal { seed: 3, spike_rate: 1.0, spike: 10.0, ..Signal::default() };
+        let mut sensor = simulated(20.0, 0.0, signal);
+        assert!(values(&mut sensor, 20).into_iter().flatten().all(|v| v == 10.0 || v == 30.0));
+
+        let signal = Signal { seed: 3, dropout_rate: 0.25, ..Signal::default() };
+        let mut sensor = simulated(20.0, 0.0, signal);
+        let dropped = values(&mut sensor, 10_000).iter().filter(|v| v.is_none()).count();
+        assert!((2_300..2_700).contains(&dropped), "dropped {}", dropped);
+    }
+
+    #[test]
+    fn stuck_sensor_holds_its_last_value() {
+        let signal = Signal { seed: 5, stuck_rate: 0.05, stuck_for: 4, ..Signal::default() };
+        let mut sensor = simulated(0.0, 1.0, signal);
+        let values: Vec<f64> = values(&mut sensor, 200).into_iter().flatten().collect();
+        let held = (1..values.len()).filter(|&i| values[i] == values[i - 1]).count();
+        assert!(held >= 4, "held {}", held);
+        assert!((1..values.len()).all(|i| values[i] == (i + 1) as f64 || values[i] == values[i - 1]));
+    }
+}
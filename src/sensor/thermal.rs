@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::reading::{Channel, Reading};
+
+use super::Sensor;
+
+/// Where thermal zones are expected inside the module's filesystem.
+///
+/// The zone directories under the host's `/sys/class/thermal` are symlinks
+/// into `/sys/devices`, which a WASI preopen will not follow, so mount the
+/// real directory instead:
+/// `Volume=/sys/devices/virtual/thermal:/sys/class/thermal:ro`.
+pub const DEFAULT_THERMAL_ROOT: &str = "/sys/class/thermal";
+
+/// Linux thermal zone read through a preopened sysfs directory.
+///
+/// Reads `<root>/thermal_zone<N>/temp`, which the kernel reports in
+/// millidegrees Celsius.
+pub struct SysfsThermalSensor {
+    channel: Channel,
+    path: PathBuf,
+}
+
+impl SysfsThermalSensor {
+    /// `channel` should be in °C, which is what the kernel reports.
+    pub fn new(channel: Channel, root: impl AsRef<Path>, zone: u32) -> Self {
+        let path = root.as_ref().join(format!("thermal_zone{}", zone)).join("temp");
+        SysfsThermalSensor { channel, path }
+    }
+}
+
+impl Sensor for SysfsThermalSensor {
+    fn name(&self) -> &str {
+        &self.channel.sensor
+    }
+
+    fn read(&mut self) -> Result<Vec<Reading>> {
+        let raw = fs::read_to_string(&self.path)
+            .map_err(|err| Error::sensor(self.name(), format!("{}: {}", self.path.display(), err)))?;
+        let millidegrees: i64 = raw.trim().parse()
+            .map_err(|_| Error::sensor(self.name(), format!("{}: invalid temperature '{}'", self.path.display(), raw.trim())))?;
+        Ok(vec![self.channel.reading(millidegrees as f64 / 1000.0)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_sysfs(zones: &[(u32, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (zone, temp) in zones {
+            let dir = root.path().join(format!("thermal_zone{}", zone));
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("temp"), temp).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn converts_millidegrees_to_celsius() {
+        let root = fake_sysfs(&[(0, "45123\n"), (1, "-5500\n")]);
+        let mut zone0 = SysfsThermalSensor::new(Channel::new("temperature", "°C"), root.path(), 0);
+        let mut zone1 = SysfsThermalSensor::new(Channel::new("outdoor", "°C"), root.path(), 1);
+        assert_eq!(zone0.read().unwrap()[0].value, 45.123);
+        assert_eq!(zone1.read().unwrap()[0].value, -5.5);
+    }
+
+    #[test]
+    fn follows_value_changes() {
+        let root = fake_sysfs(&[(0, "40000\n")]);
+        let mut sensor = SysfsThermalSensor::new(Channel::new("temperature", "°C"), root.path(), 0);
+        assert_eq!(sensor.read().unwrap()[0].value, 40.0);
+        fs::write(root.path().join("thermal_zone0/temp"), "41500\n").unwrap();
+        assert_eq!(sensor.read().unwrap()[0].value, 41.5);
+    }
+
+    #[test]
+    fn missing_zone_is_a_sensor_error() {
+        let root = fake_sysfs(&[(0, "40000\n")]);
+        let mut sensor = SysfsThermalSensor::new(Channel::new("temperature", "°C"), root.path(), 3);
+        assert!(matches!(sensor.read(), Err(Error::Sensor { .. })));
+    }
+
+    #[test]
+    fn garbage_is_a_sensor_error() {
+        let root = fake_sysfs(&[(0, "hot\n")]);
+        let mut sensor = SysfsThermalSensor::new(Channel::new("temperature", "°C"), root.path(), 0);
+        let err = sensor.read().unwrap_err();
+        assert!(err.to_string().contains("invalid temperature 'hot'"), "{}", err);
+    }
+}
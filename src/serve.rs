@@ -0,0 +1,154 @@
+//! Answers for the `wasi:http` proxy build: `/metrics` in the Prometheus
+//! text format, `/readings/latest` as JSON and `/healthz`.
+//!
+//! A proxy host such as `wasmtime serve` gives every request a fresh
+//! instance, so each request loads the settings, builds the sensors and reads
+//! them once; nothing carries over between requests. Routing and rendering
+//! live here, away from the bindings, so they can be tested natively.
+
+use std::io;
+
+use crate::clock::{Clock, Timestamp};
+use crate::config::Settings;
+use crate::error::Result;
+use crate::output::{self, Format, Record};
+use crate::reading::Reading;
+use crate::sensor;
+
+const PROMETHEUS: &str = "text/plain; version=0.0.4; charset=utf-8";
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain; charset=utf-8";
+
+/// An HTTP response, ready for the bindings to send.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Response { status, headers: vec![("content-type", content_type.to_string())], body }
+    }
+
+    /// A plain-text response, e.g. an error message.
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status, TEXT, body.into().into_bytes())
+    }
+}
+
+/// Read every configured sensor once, like one iteration of the loop.
+/// Fails only if every sensor failed.
+pub fn sample(settings: &Settings) -> Result<(Vec<Reading>, Timestamp)> {
+    let timestamp = Clock::new(settings.clock).now()?;
+    let mut sensors = sensor::registry(&settings.sensors)?;
+    let readings = sensor::read_all(&mut sensors, |_| {})?;
+    Ok((readings, timestamp))
+}
+
+/// Answer `method` on `path`, which may carry a query; `sample` is only
+/// called for a known route.
+pub fn respond(method: &str, path: &str, sample: impl FnOnce() -> Result<(Vec<Reading>, Timestamp)>) -> Response {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    if !matches!(path, "/metrics" | "/readings/latest" | "/healthz") {
+        return Response::text(404, "not found\n");
+    }
+    if method != "GET" && method != "HEAD" {
+        let mut response = Response::text(405, "method not allowed\n");
+        response.headers.push(("allow", "GET, HEAD".to_string()));
+        return response;
+    }
+    let mut response = match sample() {
+        Ok((readings, timestamp)) => {
+            let records: Vec<Record> = readings.iter()
+                .map(|reading| Record { seq: 1, reading, timestamp: reading.timestamp.unwrap_or(timestamp) })
+                .collect();
+            match render(path, &records) {
+                Ok(response) => response,
+                Err(err) => Response::text(500, format!("error: {}\n", err)),
+            }
+        }
+        // Unhealthy as far as a probe or scraper is concerned.
+        Err(err) => Response::text(503, format!("error: {}\n", err)),
+    };
+    if method == "HEAD" {
+        response.body.clear();
+    }
+    response
+}
+
+fn render(path: &str, records: &[Record]) -> io::Result<Response> {
+    let mut body = Vec::new();
+    match path {
+        "/metrics" => {
+            output::write_tick(&mut body, Format::Prometheus, records)?;
+            Ok(Response::new(200, PROMETHEUS, body))
+        }
+        "/readings/latest" => {
+            body.push(b'[');
+            for (i, record) in records.iter().enumerate() {
+                if i > 0 {
+                    body.push(b',');
+                }
+                output::write_json(&mut body, record)?;
+            }
+            body.extend_from_slice(b"]\n");
+            Ok(Response::new(200, JSON, body))
+        }
+        _ => Ok(Response::text(200, "ok\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::error::Error;
+    use crate::reading::Channel;
+
+    fn readings() -> Result<(Vec<Reading>, Timestamp)> {
+        let readings = vec![Channel::new("temperature", "°C").reading(21.5), Channel::new("humidity", "%").reading(40.0)];
+        Ok((readings, Timestamp::Wall(Duration::from_secs(1_770_656_082))))
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    #[test]
+    fn serves_metrics_and_latest_readings() {
+        let metrics = respond("GET", "/metrics", readings);
+        assert_eq!((metrics.status, metrics.headers[0].1.as_str()), (200, PROMETHEUS));
+        assert!(body(&metrics).contains("# TYPE wasm_hello_reading gauge\n"), "{}", body(&metrics));
+        assert!(body(&metrics).contains("wasm_hello_reading{sensor=\"humidity\""), "{}", body(&metrics));
+
+        let latest = respond("GET", "/readings/latest?pretty", readings);
+        assert_eq!((latest.status, latest.headers[0].1.as_str()), (200, JSON));
+        let json: serde_json::Value = serde_json::from_slice(&latest.body).unwrap();
+        assert_eq!(json[0]["sensor"], "temperature");
+        assert_eq!(json[1]["value"], 40.0);
+        assert_eq!(json.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn health_follows_the_sensors() {
+        assert_eq!(respond("GET", "/healthz", readings), Response::text(200, "ok\n"));
+        let failed = respond("GET", "/healthz", || Err(Error::sensor("temperature", "no such zone")));
+        assert_eq!(failed.status, 503);
+        assert!(body(&failed).starts_with("error: "), "{}", body(&failed));
+    }
+
+    #[test]
+    fn rejects_other_routes_and_methods() {
+        let sample = || -> Result<(Vec<Reading>, Timestamp)> { panic!("sampled for a rejected request") };
+        assert_eq!(respond("GET", "/", sample).status, 404);
+        assert_eq!(respond("GET", "/metrics/extra", sample).status, 404);
+        let post = respond("POST", "/metrics", sample);
+        assert_eq!(post.status, 405);
+        assert!(post.headers.contains(&("allow", "GET, HEAD".to_string())));
+        let head = respond("HEAD", "/metrics", readings);
+        assert_eq!((head.status, head.body.len()), (200, 0));
+    }
+}
@@ -0,0 +1,147 @@
+//! Graceful stop conditions for the reporting loop.
+//!
+//! WASI has no signals: when systemd stops the Quadlet the module is simply
+//! killed. To finish cleanly the loop instead watches for a sentinel file in
+//! a preopened directory (created by the operator or a stop hook) and an
+//! optional maximum runtime, and waits between iterations in short slices so
+//! either is noticed promptly.
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Settings;
+
+/// How often the stop file is checked while waiting between iterations.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Longest single sleep, for waits without a deadline.
+const MAX_SLEEP: Duration = Duration::from_secs(3600);
+
+/// Why the loop stopped before completing its iterations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    StopFile(PathBuf),
+    Deadline(Duration),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::StopFile(path) => write!(f, "stop file {} found", path.display()),
+            StopReason::Deadline(limit) => write!(f, "max runtime of {} ms reached", limit.as_millis()),
+        }
+    }
+}
+
+/// Stop file and deadline, checked between iterations.
+#[derive(Debug, Clone)]
+pub struct StopCondition {
+    stop_file: Option<PathBuf>,
+    max_runtime: Option<Duration>,
+    started: Instant,
+}
+
+impl StopCondition {
+    pub fn new(settings: &Settings) -> Self {
+        StopCondition {
+            stop_file: settings.stop_file.clone(),
+            max_runtime: settings.max_runtime,
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns the reason to stop, if any.
+    ///
+    /// A stop file that triggers is removed (best effort) so the next start
+    /// is not stopped immediately.
+    pub fn check(&self) -> Option<StopReason> {
+        if let Some(limit) = self.max_runtime.filter(|&limit| self.started.elapsed() >= limit) {
+            return Some(StopReason::Deadline(limit));
+        }
+        let path = self.stop_file.as_ref().filter(|path| path.exists())?;
+        let _ = fs::remove_file(path);
+        Some(StopReason::StopFile(path.clone()))
+    }
+
+    /// Sleep for `duration`, returning early once a stop condition is met.
+    pub fn wait(&self, duration: Duration) {
+        // An instant too far ahead to represent is no deadline at all.
+        let until = [Instant::now().checked_add(duration), self.max_runtime.and_then(|limit| self.started.checked_add(limit))]
+            .into_iter()
+            .flatten()
+            .min();
+        loop {
+            let now = Instant::now();
+            if until.is_some_and(|until| now >= until) || self.stop_file_exists() {
+                return;
+            }
+            let remaining = until.map_or(MAX_SLEEP, |until| (until - now).min(MAX_SLEEP));
+            thread::sleep(if self.stop_file.is_some() { remaining.min(POLL_INTERVAL) } else { remaining });
+        }
+    }
+
+    fn stop_file_exists(&self) -> bool {
+        self.stop_file.as_ref().is_some_and(|path| path.exists())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(stop_file: Option<PathBuf>, max_runtime: Option<Duration>) -> StopCondition {
+        StopCondition::new(&Settings { stop_file, max_runtime, ..Settings::default() })
+    }
+
+    #[test]
+    fn stops_at_the_max_runtime() {
+        let stop = condition(None, Some(Duration::from_millis(50)));
+        assert_eq!(stop.check(), None);
+        let started = Instant::now();
+        // Far past what an `Instant` can hold; the deadline comes first.
+        stop.wait(Duration::MAX);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let reason = stop.check().unwrap();
+        assert_eq!(reason, StopReason::Deadline(Duration::from_millis(50)));
+        assert_eq!(reason.to_string(), "max runtime of 50 ms reached");
+    }
+
+    #[test]
+    fn stops_on_the_stop_file_and_removes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stop");
+        let stop = condition(Some(path.clone()), None);
+        assert_eq!(stop.check(), None);
+
+        let started = Instant::now();
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(150));
+                fs::write(path, "").unwrap();
+            })
+        };
+        stop.wait(Duration::from_secs(60));
+        writer.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let reason = stop.check().unwrap();
+        assert_eq!(reason.to_string(), format!("stop file {} found", path.display()));
+        assert_eq!(reason, StopReason::StopFile(path.clone()));
+        // Removed, so the next start is not stopped at once.
+        assert!(!path.exists());
+        assert_eq!(stop.check(), None);
+    }
+
+    #[test]
+    fn waits_without_overflowing() {
+        let stop = condition(None, Some(Duration::MAX));
+        let started = Instant::now();
+        stop.wait(Duration::from_millis(20));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(stop.check(), None);
+        condition(None, None).wait(Duration::ZERO);
+    }
+}
@@ -0,0 +1,880 @@
+//! CoAP (RFC 7252) client sink: every iteration's readings go to one resource
+//! as a confirmable POST, for gateways that only speak CoAP over UDP.
+//!
+//! The payload is a SenML pack in CBOR or JSON, or an array of the JSON
+//! output's objects in CBOR; see [`senml`]. A POST is retransmitted with the
+//! same message ID and a doubling timeout until it is acknowledged, and a
+//! response that arrives separately is acknowledged in turn; duplicates of
+//! it are recognised by their message ID and not taken twice. Payloads
+//! larger than `block_size` go block by block with Block1 (RFC 7959),
+//! following the server if it asks for smaller blocks.
+//!
+//! Retransmissions happen within the iteration, so an unreachable server
+//! holds the loop up for up to `1.5 × ack_timeout_ms × 2^(max_retransmit + 1)`;
+//! after that the sink backs off exponentially and posts nothing until the
+//! backoff is over. Payloads that were not delivered go to the spool, when
+//! one is configured, and are posted first once the server is back; ones the
+//! server refused with a 4.xx code are dropped.
+//!
+//! [`senml`]: super::senml
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer};
+
+use super::{Sink, forward, frame, with_default_port};
+use super::senml;
+use crate::output::Record;
+use crate::sensor::SplitMix64;
+use crate::spool::Spool;
+
+const DEFAULT_PORT: u16 = 5683;
+/// Spread of the initial retransmission timeout, RFC 7252 section 4.8.
+const ACK_RANDOM_FACTOR: f64 = 1.5;
+/// Largest datagram read; a 1024-byte block and its header fit.
+const MAX_DATAGRAM: usize = 2048;
+/// Message IDs of separate responses remembered to spot duplicates.
+const SEEN_IDS: usize = 32;
+
+// Message types.
+const CON: u8 = 0;
+const NON: u8 = 1;
+const ACK: u8 = 2;
+const RST: u8 = 3;
+
+// Codes, `class << 5 | detail`.
+const EMPTY: u8 = 0x00;
+const POST: u8 = 0x02;
+const CONTINUE: u8 = 0x5f;
+const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+
+// Option numbers.
+const URI_PATH: u16 = 11;
+const CONTENT_FORMAT: u16 = 12;
+const BLOCK1: u16 = 27;
+const SIZE1: u16 = 60;
+
+/// The `[coap]` table of the configuration file.
+///
+/// ```toml
+/// [coap]
+/// server = "gateway.local:5683"
+/// path = "/sensors/lab1"
+/// format = "senml-cbor"
+/// base_name = "urn:dev:lab1:"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoapConfig {
+    /// `host:port`; the port defaults to 5683. An IPv6 address with a port
+    /// goes in brackets, e.g. `[fe80::1]:5683`.
+    pub server: String,
+    /// Resource to POST to, sent as Uri-Path options.
+    #[serde(deserialize_with = "path")]
+    pub path: String,
+    pub format: CoapFormat,
+    /// SenML base name, prepended to every sensor name by the receiver.
+    pub base_name: Option<String>,
+    /// Largest payload sent in one message; bigger ones go block-wise.
+    #[serde(deserialize_with = "block_size")]
+    pub block_size: usize,
+    /// Wait for the first acknowledgement; doubled for every retransmission.
+    #[serde(deserialize_with = "ack_timeout_ms")]
+    pub ack_timeout_ms: u64,
+    #[serde(deserialize_with = "max_retransmit")]
+    pub max_retransmit: u32,
+    /// Wait after a failed post before the next; doubled for every further failure.
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for CoapConfig {
+    fn default() -> Self {
+        CoapConfig {
+            server: format!("localhost:{}", DEFAULT_PORT),
+            path: "/readings".to_string(),
+            format: CoapFormat::SenmlCbor,
+            base_name: None,
+            block_size: 1024,
+            ack_timeout_ms: 2000,
+            max_retransmit: 4,
+            backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+fn path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let path = String::deserialize(deserializer)?;
+    if !path.starts_with('/') || path.contains(['?', '#']) || path.split('/').any(|segment| segment.len() > 255) {
+        return Err(serde::de::Error::custom(format!("'{}' is not a valid resource path", path)));
+    }
+    Ok(path)
+}
+
+fn block_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let size = usize::deserialize(deserializer)?;
+    if !size.is_power_of_two() || !(16..=1024).contains(&size) {
+        return Err(serde::de::Error::custom("block_size must be a power of two from 16 to 1024"));
+    }
+    Ok(size)
+}
+
+fn ack_timeout_ms<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let timeout = u64::deserialize(deserializer)?;
+    if !(1..=60_000).contains(&timeout) {
+        return Err(serde::de::Error::custom("ack_timeout_ms must be from 1 to 60000"));
+    }
+    Ok(timeout)
+}
+
+/// Bounds the doubling timeout, which reaches `ack_timeout_ms × 2^max_retransmit`.
+fn max_retransmit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let retransmissions = u32::deserialize(deserializer)?;
+    if retransmissions > 10 {
+        return Err(serde::de::Error::custom("max_retransmit must be at most 10"));
+    }
+    Ok(retransmissions)
+}
+
+/// Payload encoding, sent as the Content-Format option.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoapFormat {
+    /// `application/senml+cbor`
+    #[default]
+    SenmlCbor,
+    /// `application/senml+json`
+    SenmlJson,
+    /// `application/cbor`: the objects `--format json` writes.
+    Cbor,
+}
+
+impl CoapFormat {
+    fn content_format(self) -> u64 {
+        match self {
+            CoapFormat::SenmlCbor => 112,
+            CoapFormat::SenmlJson => 110,
+            CoapFormat::Cbor => 60,
+        }
+    }
+
+    fn encode(self, base_name: Option<&str>, records: &[Record]) -> io::Result<Vec<u8>> {
+        match self {
+            CoapFormat::SenmlCbor => Ok(senml::senml_cbor(base_name, records)),
+            CoapFormat::SenmlJson => senml::senml_json(base_name, records),
+            CoapFormat::Cbor => senml::cbor(records),
+        }
+    }
+}
+
+/// POSTs every iteration's readings to a CoAP resource.
+pub struct CoapSink {
+    client: Client,
+    spool: Option<Spool>,
+    posted: u64,
+    /// Readings in payloads the server refused with a 4.xx code.
+    rejected: u64,
+    /// Readings that could not be delivered and had no spool to go to.
+    lost: u64,
+    /// Consecutive failed posts, for the backoff.
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl CoapSink {
+    /// A sink for `config`; the socket is opened on the first post.
+    pub fn new(config: CoapConfig, spool: Option<Spool>) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        let mut rng = SplitMix64(seed);
+        // A random start makes it unlikely that a restarted client repeats
+        // IDs the server still remembers.
+        let message_id = rng.next() as u16;
+        let client = Client { config, socket: None, message_id, rng, seen: VecDeque::new() };
+        CoapSink { client, spool, posted: 0, rejected: 0, lost: 0, failures: 0, retry_at: None }
+    }
+
+    /// Post the spool, then `records`; a failure spools the payload and backs
+    /// off, and while backing off payloads are spooled without posting.
+    fn publish_at(&mut self, records: &[Record], now: Instant) -> io::Result<()> {
+        let payload = if records.is_empty() {
+            None
+        } else {
+            Some(self.client.config.format.encode(self.client.config.base_name.as_deref(), records)?)
+        };
+        let readings = records.len() as u64;
+        if self.retry_at.is_some_and(|at| now < at) {
+            self.hold(payload, readings)?;
+            if self.spool.is_none() && readings > 0 {
+                let reason = format!("{} is backing off after a failure: {} readings lost", self.client.resource(), readings);
+                return Err(io::Error::other(reason));
+            }
+            return Ok(());
+        }
+        let CoapSink { client, spool, posted, rejected, .. } = self;
+        if let Some(spool) = spool {
+            let forwarded = forward(spool, posted, rejected, |payload| match client.send(payload) {
+                Outcome::Posted => Ok(true),
+                Outcome::Rejected(_) => Ok(false),
+                Outcome::Failed(err) => Err(err),
+            });
+            if let Err(err) = forwarded {
+                self.hold(payload, readings)?;
+                return Err(self.failed(now, err));
+            }
+        }
+        let outcome = payload.as_ref().map(|payload| self.client.send(payload));
+        match outcome {
+            None | Some(Outcome::Posted) => {
+                self.posted += readings;
+                self.failures = 0;
+                self.retry_at = None;
+                Ok(())
+            }
+            Some(Outcome::Rejected(code)) => {
+                self.rejected += readings;
+                let reason = format!("{} rejected {} readings: {}", self.client.resource(), readings, code_name(code));
+                Err(io::Error::new(io::ErrorKind::InvalidData, reason))
+            }
+            Some(Outcome::Failed(err)) => {
+                self.hold(payload, readings)?;
+                Err(self.failed(now, err))
+            }
+        }
+    }
+
+    /// Back off after a failed post.
+    fn failed(&mut self, now: Instant, err: io::Error) -> io::Error {
+        self.failures += 1;
+        let backoff = self.client.config.backoff_ms.saturating_mul(1 << (self.failures - 1).min(30));
+        self.retry_at = Some(now + Duration::from_millis(backoff.min(self.client.config.max_backoff_ms)));
+        err
+    }
+
+    /// Spool a payload that was not posted, or count it lost.
+    fn hold(&mut self, payload: Option<Vec<u8>>, readings: u64) -> io::Result<()> {
+        match (payload, &mut self.spool) {
+            (None, _) => Ok(()),
+            (Some(payload), Some(spool)) => spool.push(&frame(readings, &payload)),
+            (Some(_), None) => {
+                self.lost += readings;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Sink for CoapSink {
+    fn name(&self) -> &str {
+        "coap"
+    }
+
+    fn publish(&mut self, records: &[Record]) -> io::Result<()> {
+        self.publish_at(records, Instant::now())
+    }
+}
+
+impl fmt::Display for CoapSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "coap {}: {} posted", self.client.resource(), self.posted)?;
+        if self.rejected > 0 {
+            write!(f, ", {} rejected", self.rejected)?;
+        }
+        match &self.spool {
+            Some(spool) => write!(f, ", {} queued, {} dropped", spool.len(), spool.dropped()),
+            None if self.lost > 0 => write!(f, ", {} lost", self.lost),
+            None => Ok(()),
+        }
+    }
+}
+
+enum Outcome {
+    Posted,
+    /// A 4.xx code: the same request will not do better.
+    Rejected(u8),
+    /// Worth retrying: no answer, a reset or a 5.xx code.
+    Failed(io::Error),
+}
+
+/// `4.13`
+fn code_name(code: u8) -> String {
+    format!("{}.{:02}", code >> 5, code & 0x1f)
+}
+
+/// Size of a block from the SZX field of a Block1 option.
+fn block_size_of(block1: u64) -> Option<usize> {
+    match block1 & 0x7 {
+        7 => None,
+        szx => Some(16 << szx),
+    }
+}
+
+/// One UDP endpoint talking to the server, reopened after an error.
+struct Client {
+    config: CoapConfig,
+    socket: Option<UdpSocket>,
+    /// ID of the last request; the next one takes the following.
+    message_id: u16,
+    rng: SplitMix64,
+    /// Message IDs of recent separate responses, newest last.
+    seen: VecDeque<u16>,
+}
+
+impl Client {
+    fn resource(&self) -> String {
+        format!("{}{}", self.config.server, self.config.path)
+    }
+
+    fn send(&mut self, payload: &[u8]) -> Outcome {
+        match self.post(payload) {
+            Ok(code) if code >> 5 == 2 => Outcome::Posted,
+            Ok(code) if code >> 5 == 4 => Outcome::Rejected(code),
+            Ok(code) => Outcome::Failed(io::Error::other(format!("{} answered {}", self.resource(), code_name(code)))),
+            Err(err) => {
+                self.socket = None;
+                Outcome::Failed(err)
+            }
+        }
+    }
+
+    /// POST `payload`, block by block if it is larger than a block, and
+    /// return the code of the final response.
+    fn post(&mut self, payload: &[u8]) -> io::Result<u8> {
+        let mut size = self.config.block_size;
+        let mut blockwise = payload.len() > size;
+        let mut offset = 0;
+        loop {
+            let end = if blockwise { (offset + size).min(payload.len()) } else { payload.len() };
+            let more = end < payload.len();
+            let mut options: Vec<(u16, Vec<u8>)> = self.config.path.split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| (URI_PATH, segment.as_bytes().to_vec()))
+                .collect();
+            options.push((CONTENT_FORMAT, uint(self.config.format.content_format())));
+            if blockwise {
+                let szx = size.trailing_zeros() as u64 - 4;
+                options.push((BLOCK1, uint(((offset / size) as u64) << 4 | u64::from(more) << 3 | szx)));
+                if offset == 0 {
+                    options.push((SIZE1, uint(payload.len() as u64)));
+                }
+            }
+            let response = self.exchange(POST, options, &payload[offset..end])?;
+            // The server may ask for smaller blocks, never for larger ones.
+            let smaller = response.option(BLOCK1)
+                .and_then(|block1| block_size_of(read_uint(block1)))
+                .filter(|&asked| asked < size);
+            match (response.code, smaller) {
+                (CONTINUE, _) if blockwise && more => {
+                    offset = end;
+                    size = smaller.unwrap_or(size);
+                }
+                (REQUEST_ENTITY_TOO_LARGE, Some(smaller)) if offset == 0 => {
+                    size = smaller;
+                    blockwise = true;
+                }
+                (code, _) if more && code >> 5 == 2 => {
+                    return Err(io::Error::other(format!("{} answered {} before the last block", self.resource(), code_name(code))));
+                }
+                (code, _) => return Ok(code),
+            }
+        }
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        if self.socket.is_some() {
+            return Ok(());
+        }
+        let address = with_default_port(&self.config.server, DEFAULT_PORT);
+        let server = address.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", address)))?;
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server)?;
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    /// Send a confirmable request and wait for its response, piggybacked on
+    /// the acknowledgement or separate.
+    fn exchange(&mut self, code: u8, options: Vec<(u16, Vec<u8>)>, payload: &[u8]) -> io::Result<Message> {
+        self.connect()?;
+        self.message_id = self.message_id.wrapping_add(1);
+        let token = self.rng.next().to_be_bytes()[..4].to_vec();
+        let request = Message { kind: CON, code, id: self.message_id, token, options, payload: payload.to_vec() };
+        let bytes = request.encode();
+
+        let Client { config, socket, rng, seen, .. } = self;
+        let socket = socket.as_ref().expect("connected above");
+        let ack_timeout = Duration::from_millis(config.ack_timeout_ms);
+        let mut timeout = ack_timeout.mul_f64(1.0 + (ACK_RANDOM_FACTOR - 1.0) * rng.unit());
+        // MAX_TRANSMIT_WAIT: how long the request may take to be answered.
+        let max_wait = ack_timeout.mul_f64(((1u64 << (config.max_retransmit + 1)) - 1) as f64 * ACK_RANDOM_FACTOR);
+        let mut retransmissions = 0;
+        let mut acknowledged = false;
+        socket.send(&bytes)?;
+        let mut deadline = Instant::now() + timeout;
+        let mut buffer = [0; MAX_DATAGRAM];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                if acknowledged {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} acknowledged but did not respond", config.server)));
+                }
+                if retransmissions == config.max_retransmit {
+                    let reason = format!("{} did not answer after {} retransmissions", config.server, retransmissions);
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, reason));
+                }
+                retransmissions += 1;
+                timeout *= 2;
+                socket.send(&bytes)?;
+                deadline = now + timeout;
+                continue;
+            }
+            socket.set_read_timeout(Some(deadline - now))?;
+            let reply = match socket.recv(&mut buffer) {
+                // Datagrams that are not CoAP are ignored.
+                Ok(len) => match Message::decode(&buffer[..len]) {
+                    Ok(reply) => reply,
+                    Err(_) => continue,
+                },
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                Err(err) => return Err(err),
+            };
+            match reply.kind {
+                // An answer to an earlier request, retransmitted.
+                ACK | RST if reply.id != request.id => {}
+                RST => return Err(io::Error::new(io::ErrorKind::ConnectionReset, format!("{} reset the request", config.server))),
+                ACK if reply.code == EMPTY => {
+                    // The response follows separately; stop retransmitting.
+                    acknowledged = true;
+                    deadline = now + max_wait;
+                }
+                ACK if reply.token == request.token => return Ok(reply),
+                ACK => {}
+                CON | NON => {
+                    let duplicate = seen.contains(&reply.id);
+                    let ours = !duplicate && reply.token == request.token && reply.code >> 5 >= 2;
+                    if reply.kind == CON {
+                        // A duplicate means our acknowledgement was lost.
+                        let kind = if ours || duplicate { ACK } else { RST };
+                        socket.send(&Message::empty(kind, reply.id).encode())?;
+                    }
+                    if ours {
+                        if seen.len() == SEEN_IDS {
+                            seen.pop_front();
+                        }
+                        seen.push_back(reply.id);
+                        return Ok(reply);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A CoAP message; options are kept in the order they are sent.
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    kind: u8,
+    code: u8,
+    id: u16,
+    token: Vec<u8>,
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    /// An empty ACK or RST for message `id`.
+    fn empty(kind: u8, id: u16) -> Self {
+        Message { kind, code: EMPTY, id, token: Vec::new(), options: Vec::new(), payload: Vec::new() }
+    }
+
+    fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options.iter().find(|(option, _)| *option == number).map(|(_, value)| value.as_slice())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0x40 | self.kind << 4 | self.token.len() as u8, self.code];
+        bytes.extend_from_slice(&self.id.to_be_bytes());
+        bytes.extend_from_slice(&self.token);
+        let mut options: Vec<_> = self.options.iter().collect();
+        // Stable, so repeated options such as Uri-Path keep their order.
+        options.sort_by_key(|(number, _)| *number);
+        let mut previous = 0;
+        for (number, value) in options {
+            let (delta, delta_extended) = nibble(number - previous);
+            let (length, length_extended) = nibble(value.len() as u16);
+            bytes.push(delta << 4 | length);
+            bytes.extend_from_slice(&delta_extended);
+            bytes.extend_from_slice(&length_extended);
+            bytes.extend_from_slice(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            bytes.push(0xff);
+            bytes.extend_from_slice(&self.payload);
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Message> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("malformed CoAP message: {}", what));
+        let (header, rest) = bytes.split_first_chunk::<4>().ok_or_else(|| invalid("too short"))?;
+        if header[0] >> 6 != 1 {
+            return Err(invalid("unknown version"));
+        }
+        let token_length = usize::from(header[0] & 0x0f);
+        if token_length > 8 || rest.len() < token_length {
+            return Err(invalid("bad token length"));
+        }
+        let (token, mut rest) = rest.split_at(token_length);
+        let mut message = Message {
+            kind: header[0] >> 4 & 0x3,
+            code: header[1],
+            id: u16::from_be_bytes([header[2], header[3]]),
+            token: token.to_vec(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        };
+        let mut number = 0u16;
+        while let Some((&first, after)) = rest.split_first() {
+            if first == 0xff {
+                if after.is_empty() {
+                    return Err(invalid("payload marker without a payload"));
+                }
+                message.payload = after.to_vec();
+                break;
+            }
+            rest = after;
+            let delta = extended(first >> 4, &mut rest).ok_or_else(|| invalid("bad option delta"))?;
+            let length = extended(first & 0x0f, &mut rest).ok_or_else(|| invalid("bad option length"))?;
+            number = number.checked_add(delta).ok_or_else(|| invalid("option number out of range"))?;
+            if rest.len() < usize::from(length) {
+                return Err(invalid("option longer than the message"));
+            }
+            let (value, after) = rest.split_at(usize::from(length));
+            message.options.push((number, value.to_vec()));
+            rest = after;
+        }
+        Ok(message)
+    }
+}
+
+/// An option delta or length as its 4-bit field and extended bytes.
+fn nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+/// The value of a 4-bit field, reading its extended bytes from `rest`.
+fn extended(field: u8, rest: &mut &[u8]) -> Option<u16> {
+    match field {
+        0..=12 => Some(u16::from(field)),
+        13 => {
+            let (&byte, after) = rest.split_first()?;
+            *rest = after;
+            Some(u16::from(byte) + 13)
+        }
+        14 => {
+            let (bytes, after) = rest.split_first_chunk::<2>()?;
+            *rest = after;
+            u16::from_be_bytes(*bytes).checked_add(269)
+        }
+        _ => None,
+    }
+}
+
+/// An option value as an unsigned integer in the fewest bytes; zero is empty.
+fn uint(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = (value.leading_zeros() / 8) as usize;
+    bytes[skip..].to_vec()
+}
+
+fn read_uint(value: &[u8]) -> u64 {
+    value.iter().take(8).fold(0, |uint, &byte| uint << 8 | u64::from(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::clock::Timestamp;
+    use crate::reading::Channel;
+    use crate::spool::DropPolicy;
+
+    const CHANGED: u8 = 0x44;
+    const BAD_REQUEST: u8 = 0x80;
+    /// Message ID of the stand-in's separate responses.
+    const SEPARATE_ID: u16 = 0x7000;
+
+    /// What the server stand-in does with a request.
+    enum Reply {
+        /// Nothing, as if the request was lost.
+        Drop,
+        /// Answer on the acknowledgement, echoing any Block1 option.
+        Piggyback(u8),
+        /// Answer on the acknowledgement, asking for blocks of this SZX.
+        Shrink(u8, u64),
+        /// Acknowledge, then answer in a confirmable message sent twice.
+        Separate(u8),
+    }
+
+    /// CoAP server stand-in on a loopback port. It answers one confirmable
+    /// request per entry of `replies`, then listens until the client goes
+    /// quiet; the thread returns every message it received.
+    fn server(replies: Vec<Reply>) -> (String, thread::JoinHandle<Vec<(Instant, Message)>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buffer = [0; MAX_DATAGRAM];
+            let mut received = Vec::new();
+            for reply in replies {
+                let (request, peer) = loop {
+                    let (len, peer) = socket.recv_from(&mut buffer).unwrap();
+                    let message = Message::decode(&buffer[..len]).unwrap();
+                    received.push((Instant::now(), message.clone()));
+                    if message.kind == CON {
+                        break (message, peer);
+                    }
+                };
+                let block1 = request.option(BLOCK1).map(read_uint);
+                let answer = |kind, id, code, block1: Option<u64>| {
+                    let options = block1.map(|block1| (BLOCK1, uint(block1))).into_iter().collect();
+                    let message = Message { kind, code, id, token: request.token.clone(), options, payload: Vec::new() };
+                    socket.send_to(&message.encode(), peer).unwrap();
+                };
+                match reply {
+                    Reply::Drop => {}
+                    Reply::Piggyback(code) => answer(ACK, request.id, code, block1),
+                    Reply::Shrink(code, szx) => answer(ACK, request.id, code, Some(block1.unwrap_or(0) & !0x7 | szx)),
+                    Reply::Separate(code) => {
+                        socket.send_to(&Message::empty(ACK, request.id).encode(), peer).unwrap();
+                        answer(CON, SEPARATE_ID, code, None);
+                        answer(CON, SEPARATE_ID, code, None);
+                    }
+                }
+            }
+            socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            while let Ok(len) = socket.recv(&mut buffer) {
+                received.push((Instant::now(), Message::decode(&buffer[..len]).unwrap()));
+            }
+            received
+        });
+        (address, handle)
+    }
+
+    fn config(server: &str) -> CoapConfig {
+        CoapConfig { server: server.to_string(), ack_timeout_ms: 40, max_retransmit: 2, ..CoapConfig::default() }
+    }
+
+    fn publish(sink: &mut CoapSink, readings: usize) -> io::Result<()> {
+        publish_at(sink, Instant::now(), readings)
+    }
+
+    fn publish_at(sink: &mut CoapSink, now: Instant, readings: usize) -> io::Result<()> {
+        let reading = Channel::new("temperature", "°C").reading(21.5);
+        let records: Vec<_> = (0..readings)
+            .map(|seq| Record { seq: seq as u64, reading: &reading, timestamp: Timestamp::Wall(Duration::from_secs(1_770_656_082)) })
+            .collect();
+        sink.publish_at(&records, now)
+    }
+
+    fn requests(received: &[(Instant, Message)]) -> Vec<&Message> {
+        received.iter().map(|(_, message)| message).filter(|message| message.kind == CON).collect()
+    }
+
+    #[test]
+    fn encodes_and_decodes_messages() {
+        let message = Message {
+            kind: CON,
+            code: POST,
+            id: 0x1234,
+            token: vec![0xab, 0xcd],
+            options: vec![
+                (SIZE1, uint(2000)),
+                (URI_PATH, b"sensors".to_vec()),
+                (URI_PATH, b"lab1".to_vec()),
+                (CONTENT_FORMAT, uint(112)),
+                (BLOCK1, uint(1 << 4 | 1 << 3 | 6)),
+            ],
+            payload: vec![0x01, 0x02],
+        };
+        let bytes = message.encode();
+        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, concat!(
+            "42", "02", "1234", "abcd",
+            "b7", "73656e736f7273", "04", "6c616231",
+            "11", "70",
+            "d1", "02", "1e",
+            "d2", "14", "07d0",
+            "ff", "0102",
+        ));
+        let decoded = Message::decode(&bytes).unwrap();
+        assert_eq!(decoded.option(SIZE1).map(read_uint), Some(2000));
+        assert_eq!(decoded.encode(), bytes);
+        assert_eq!(Message::decode(&Message::empty(ACK, 7).encode()).unwrap(), Message::empty(ACK, 7));
+
+        for (bytes, expected) in [
+            (&[0x40, 0x02][..], "too short"),
+            (&[0x80, 0x02, 0x00, 0x01], "unknown version"),
+            (&[0x49, 0x02, 0x00, 0x01], "bad token length"),
+            (&[0x40, 0x02, 0x00, 0x01, 0xff], "payload marker without a payload"),
+            (&[0x40, 0x02, 0x00, 0x01, 0xf0], "bad option delta"),
+            (&[0x40, 0x02, 0x00, 0x01, 0x13, 0x00], "option longer than the message"),
+        ] {
+            let err = Message::decode(bytes).unwrap_err();
+            assert!(err.to_string().ends_with(expected), "{:02x?}: {}", bytes, err);
+        }
+    }
+
+    #[test]
+    fn retransmits_with_the_same_message_id_and_backoff() {
+        let (address, server) = server(vec![Reply::Drop, Reply::Drop, Reply::Piggyback(CHANGED)]);
+        let mut sink = CoapSink::new(config(&address), None);
+        publish(&mut sink, 2).unwrap();
+        assert_eq!(sink.to_string(), format!("coap {}/readings: 2 posted", address));
+
+        let received = server.join().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|(_, message)| *message == received[0].1), "{:?}", received);
+        let request = &received[0].1;
+        assert_eq!((request.kind, request.code), (CON, POST));
+        assert_eq!(request.option(URI_PATH), Some(&b"readings"[..]));
+        assert_eq!(request.option(CONTENT_FORMAT).map(read_uint), Some(112));
+        let first = received[1].0 - received[0].0;
+        let second = received[2].0 - received[1].0;
+        assert!(first >= Duration::from_millis(40), "{:?}", first);
+        assert!(second >= first.mul_f64(1.5), "{:?} then {:?}", first, second);
+    }
+
+    #[test]
+    fn acknowledges_separate_responses_once() {
+        let (address, server) = server(vec![Reply::Separate(CHANGED), Reply::Piggyback(CHANGED)]);
+        let mut sink = CoapSink::new(config(&address), None);
+        publish(&mut sink, 1).unwrap();
+        // The duplicate is still waiting; it must not pass for this answer.
+        publish(&mut sink, 1).unwrap();
+        assert_eq!(sink.posted, 2);
+
+        let received = server.join().unwrap();
+        let requests = requests(&received);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].id, requests[0].id.wrapping_add(1));
+        let acks: Vec<_> = received.iter().map(|(_, message)| message).filter(|message| message.kind == ACK).collect();
+        assert_eq!(acks, [&Message::empty(ACK, SEPARATE_ID), &Message::empty(ACK, SEPARATE_ID)]);
+    }
+
+    #[test]
+    fn transfers_large_payloads_block_wise() {
+        let reading = Channel::new("temperature", "°C").reading(21.5);
+        let records: Vec<_> = (0..8)
+            .map(|seq| Record { seq, reading: &reading, timestamp: Timestamp::Wall(Duration::from_secs(1_770_656_082 + seq)) })
+            .collect();
+        let payload = CoapFormat::SenmlJson.encode(None, &records).unwrap();
+        assert!(payload.len() > 160, "{}", payload.len());
+        // 128-byte blocks are too large, 64 will do until the second block
+        // asks for 32.
+        let mut replies = vec![Reply::Shrink(REQUEST_ENTITY_TOO_LARGE, 2), Reply::Piggyback(CONTINUE), Reply::Shrink(CONTINUE, 1)];
+        let remaining = (payload.len() - 128).div_ceil(32);
+        replies.extend((1..remaining).map(|_| Reply::Piggyback(CONTINUE)));
+        replies.push(Reply::Piggyback(CHANGED));
+        let (address, server) = server(replies);
+        let config = CoapConfig { format: CoapFormat::SenmlJson, block_size: 128, ..config(&address) };
+        let mut sink = CoapSink::new(config, None);
+        sink.publish(&records).unwrap();
+        assert_eq!(sink.posted, 8);
+
+        let received = server.join().unwrap();
+        let requests = requests(&received);
+        let blocks: Vec<_> = requests.iter()
+            .map(|request| {
+                let block1 = read_uint(request.option(BLOCK1).unwrap());
+                (block1 >> 4, block1 >> 3 & 1 == 1, block_size_of(block1).unwrap())
+            })
+            .collect();
+        assert_eq!(blocks[..4], [(0, true, 128), (0, true, 64), (1, true, 64), (4, true, 32)]);
+        assert!(!blocks.last().unwrap().1);
+        let sizes: Vec<_> = requests.iter().map(|request| request.option(SIZE1).map(read_uint)).collect();
+        assert_eq!(sizes[..3], [Some(payload.len() as u64), Some(payload.len() as u64), None]);
+        let reassembled: Vec<u8> = requests[1..].iter().flat_map(|request| request.payload.clone()).collect();
+        assert_eq!(reassembled, payload);
+        let mut ids: Vec<_> = requests.iter().map(|request| request.id).collect();
+        ids.dedup();
+        assert_eq!(ids.len(), requests.len());
+    }
+
+    #[test]
+    fn counts_rejected_and_lost_readings() {
+        let (address, server) = server(vec![Reply::Piggyback(BAD_REQUEST), Reply::Drop, Reply::Drop]);
+        let mut sink = CoapSink::new(CoapConfig { max_retransmit: 1, ..config(&address) }, None);
+        let err = publish(&mut sink, 2).unwrap_err();
+        assert_eq!(err.to_string(), format!("{}/readings rejected 2 readings: 4.00", address));
+        let err = publish(&mut sink, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), format!("{} did not answer after 1 retransmissions", address));
+        // Nothing to post is no failure, but readings during the backoff are lost.
+        publish(&mut sink, 0).unwrap();
+        let err = publish(&mut sink, 1).unwrap_err();
+        assert_eq!(err.to_string(), format!("{}/readings is backing off after a failure: 1 readings lost", address));
+
+        assert_eq!(server.join().unwrap().len(), 3);
+        assert_eq!(sink.to_string(), format!("coap {}/readings: 0 posted, 2 rejected, 4 lost", address));
+    }
+
+    #[test]
+    fn backs_off_and_spools_until_the_server_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut replies = vec![Reply::Drop, Reply::Drop];
+        replies.extend((0..3).map(|_| Reply::Piggyback(CHANGED)));
+        let (address, server) = server(replies);
+        let spool = Spool::open(dir.path(), 1024 * 1024, DropPolicy::Oldest).unwrap();
+        let mut sink = CoapSink::new(CoapConfig { max_retransmit: 1, ..config(&address) }, Some(spool));
+        let t0 = Instant::now();
+        assert_eq!(publish_at(&mut sink, t0, 2).unwrap_err().kind(), io::ErrorKind::TimedOut);
+        // Nothing is posted during the backoff, so the loop is not held up again.
+        let started = Instant::now();
+        publish_at(&mut sink, t0 + Duration::from_millis(500), 3).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(40), "{:?}", started.elapsed());
+        assert_eq!(sink.spool.as_ref().unwrap().len(), 2);
+        publish_at(&mut sink, t0 + Duration::from_millis(1000), 1).unwrap();
+        assert_eq!(sink.to_string(), format!("coap {}/readings: 6 posted, 0 queued, 0 dropped", address));
+
+        assert_eq!(requests(&server.join().unwrap()).len(), 5);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let cases = [
+            ("path = \"readings\"", "'readings' is not a valid resource path"),
+            ("path = \"/readings?site=lab1\"", "'/readings?site=lab1' is not a valid resource path"),
+            ("block_size = 1000", "block_size must be a power of two from 16 to 1024"),
+            ("block_size = 2048", "block_size must be a power of two from 16 to 1024"),
+            ("ack_timeout_ms = 0", "ack_timeout_ms must be from 1 to 60000"),
+            ("ack_timeout_ms = 60001", "ack_timeout_ms must be from 1 to 60000"),
+            ("max_retransmit = 11", "max_retransmit must be at most 10"),
+            ("max_retransmit = 4294967295", "max_retransmit must be at most 10"),
+            ("format = \"json\"", "unknown variant `json`"),
+            ("url = \"coap://gateway/\"", "unknown field `url`"),
+        ];
+        for (toml, expected) in cases {
+            let err = toml::from_str::<CoapConfig>(toml).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", toml, err);
+        }
+        let config: CoapConfig = toml::from_str("format = \"cbor\"\nbase_name = \"urn:dev:lab1:\"").unwrap();
+        assert_eq!((config.format.content_format(), config.block_size), (60, 1024));
+    }
+}
@@ -0,0 +1,643 @@
+//! HTTP push sink: readings are POSTed to an endpoint as JSON arrays, in
+//! batches.
+//!
+//! A batch goes out once it holds `batch_size` readings or its oldest reading
+//! has waited `linger_ms`. A batch the endpoint did not take is retried with
+//! exponential backoff, between iterations rather than by stalling the loop;
+//! once out of retries it goes to the spool, when one is configured, and the
+//! spool is always sent before anything newer.
+//!
+//! On `wasm32-wasip2` built with the `wasi-http` feature the requests go
+//! through `wasi:http/outgoing-handler`, which also handles `https://`.
+//! Everywhere else a minimal HTTP/1.1 client over `std::net` sends them.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Deserializer};
+
+use super::{Sink, forward, frame};
+use crate::output::{self, Record};
+use crate::spool::Spool;
+
+/// Whether requests go through `wasi:http` rather than `std::net`.
+const WASI_HTTP: bool = cfg!(all(target_os = "wasi", target_env = "p2", feature = "wasi-http"));
+
+/// Batches kept in memory behind one being retried; older ones give way to
+/// newer ones, into the spool if there is one.
+const MAX_READY: usize = 16;
+
+/// The `[http]` table of the configuration file.
+///
+/// ```toml
+/// [http]
+/// url = "http://collector:8080/api/readings"
+/// batch_size = 100
+/// linger_ms = 30000
+/// retries = 3
+/// headers = { Authorization = "Bearer 4f1c..." }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub url: Endpoint,
+    /// Most readings per request.
+    #[serde(deserialize_with = "batch_size")]
+    pub batch_size: usize,
+    /// Longest a reading waits for its batch to fill.
+    pub linger_ms: u64,
+    /// Further attempts at a batch after the first one failed.
+    pub retries: u32,
+    /// Wait before the first retry; doubled for every further failure.
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Connect and response timeout of a single request.
+    #[serde(deserialize_with = "timeout_ms")]
+    pub timeout_ms: u64,
+    /// Extra request headers, e.g. `Authorization`.
+    #[serde(deserialize_with = "headers")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            url: "http://localhost:8080/readings".parse().expect("valid default URL"),
+            batch_size: 50,
+            linger_ms: 10_000,
+            retries: 5,
+            backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            timeout_ms: 5000,
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+fn batch_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let size = usize::deserialize(deserializer)?;
+    if size == 0 {
+        return Err(serde::de::Error::custom("batch_size must be at least 1"));
+    }
+    Ok(size)
+}
+
+/// Sockets refuse a zero timeout, which would fail every request.
+fn timeout_ms<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let timeout = u64::deserialize(deserializer)?;
+    if timeout == 0 {
+        return Err(serde::de::Error::custom("timeout_ms must be at least 1"));
+    }
+    Ok(timeout)
+}
+
+/// Headers the client writes itself, or that would contradict its framing.
+const RESERVED_HEADERS: [&str; 5] = ["Host", "Content-Type", "Content-Length", "Transfer-Encoding", "Connection"];
+
+/// Header names and values that cannot break out of their line.
+fn headers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error> {
+    let headers = BTreeMap::<String, String>::deserialize(deserializer)?;
+    for (name, value) in &headers {
+        let token = !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte));
+        if !token || value.contains(['\r', '\n', '\0']) {
+            return Err(serde::de::Error::custom(format!("'{}' is not a valid header", name)));
+        }
+        if RESERVED_HEADERS.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+            return Err(serde::de::Error::custom(format!("the '{}' header is managed by the sink", name)));
+        }
+    }
+    Ok(headers)
+}
+
+/// An `http://` or `https://` URL to POST to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Endpoint {
+    https: bool,
+    host: String,
+    port: u16,
+    /// Path and query, at least `/`.
+    path: String,
+}
+
+impl Endpoint {
+    /// `host`, or `host:port` for a port other than the scheme's.
+    fn authority(&self) -> String {
+        let default_port = if self.https { 443 } else { 80 };
+        if self.port == default_port {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let (https, rest) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+            (Some(rest), _) => (false, rest),
+            (_, Some(rest)) => (true, rest),
+            _ => return Err(format!("'{}' is not an http:// or https:// URL", url)),
+        };
+        if https && !WASI_HTTP {
+            return Err("https:// needs the wasm32-wasip2 build with the wasi-http feature".to_string());
+        }
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(at) if rest[at..].starts_with('?') => (&rest[..at], format!("/{}", &rest[at..])),
+            Some(at) => (&rest[..at], rest[at..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port.parse().map_err(|_| format!("'{}' is not a valid port", port))?;
+                (host, port)
+            }
+            _ => (authority, if https { 443 } else { 80 }),
+        };
+        if host.is_empty() || host.contains(['@', ' ']) {
+            return Err(format!("'{}' has no valid host", url));
+        }
+        Ok(Endpoint { https, host: host.to_string(), port, path })
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = String;
+
+    fn try_from(url: String) -> Result<Self, Self::Error> {
+        url.parse()
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+        write!(f, "{}://{}{}", scheme, self.authority(), self.path)
+    }
+}
+
+/// Readings sealed into one request body.
+struct Batch {
+    body: Vec<u8>,
+    readings: u64,
+    /// Failed attempts so far.
+    attempts: u32,
+}
+
+/// POSTs readings to an HTTP endpoint in batches.
+pub struct HttpSink {
+    config: HttpConfig,
+    /// The batch being filled: comma-separated JSON objects.
+    filling: Vec<u8>,
+    filled: u64,
+    /// When the oldest reading of the batch being filled arrived.
+    since: Option<Instant>,
+    /// Sealed batches, oldest first; all newer than what is spooled.
+    ready: VecDeque<Batch>,
+    spool: Option<Spool>,
+    /// Consecutive failed requests, for the backoff.
+    failures: u32,
+    retry_at: Option<Instant>,
+    sent: u64,
+    /// Readings in batches the endpoint refused with a 4xx status.
+    rejected: u64,
+    /// Readings that ran out of retries and had no spool to go to.
+    lost: u64,
+}
+
+impl HttpSink {
+    /// A sink for `config`; nothing is sent until the first batch is sealed.
+    pub fn new(config: HttpConfig, spool: Option<Spool>) -> Self {
+        HttpSink {
+            config,
+            filling: Vec::new(),
+            filled: 0,
+            since: None,
+            ready: VecDeque::new(),
+            spool,
+            failures: 0,
+            retry_at: None,
+            sent: 0,
+            rejected: 0,
+            lost: 0,
+        }
+    }
+
+    fn publish_at(&mut self, records: &[Record], now: Instant) -> io::Result<()> {
+        for record in records {
+            if self.filled > 0 {
+                self.filling.push(b',');
+            }
+            output::write_json(&mut self.filling, record)?;
+            self.filled += 1;
+            self.since.get_or_insert(now);
+            if self.filled >= self.config.batch_size as u64 {
+                self.seal()?;
+            }
+        }
+        let linger = Duration::from_millis(self.config.linger_ms);
+        if self.since.is_some_and(|since| now.duration_since(since) >= linger) {
+            self.seal()?;
+        }
+        self.pump(now)
+    }
+
+    /// Close the batch being filled and queue it for sending.
+    fn seal(&mut self) -> io::Result<()> {
+        if self.filled == 0 {
+            return Ok(());
+        }
+        let mut body = Vec::with_capacity(self.filling.len() + 2);
+        body.push(b'[');
+        body.append(&mut self.filling);
+        body.push(b']');
+        self.ready.push_back(Batch { body, readings: self.filled, attempts: 0 });
+        self.filled = 0;
+        self.since = None;
+        if self.ready.len() > MAX_READY {
+            let oldest = self.ready.pop_front().expect("more than MAX_READY batches");
+            self.give_up(oldest)?;
+        }
+        Ok(())
+    }
+
+    /// Send what is due: the spool first, then the sealed batches, stopping
+    /// at the first failure.
+    fn pump(&mut self, now: Instant) -> io::Result<()> {
+        if self.retry_at.is_some_and(|at| now < at) {
+            return Ok(());
+        }
+        if let Some(spool) = &mut self.spool {
+            let HttpSink { config, sent, rejected, .. } = self;
+            let forwarded = forward(spool, sent, rejected, |body| match send(config, body) {
+                Outcome::Sent => Ok(true),
+                Outcome::Rejected(_) => Ok(false),
+                Outcome::Failed(err) => Err(err),
+            });
+            if let Err(err) = forwarded {
+                return Err(self.failed(now, err));
+            }
+        }
+        while let Some(batch) = self.ready.front_mut() {
+            match send(&self.config, &batch.body) {
+                Outcome::Sent => {
+                    self.sent += batch.readings;
+                    self.ready.pop_front();
+                }
+                Outcome::Rejected(status) => {
+                    let readings = batch.readings;
+                    self.rejected += readings;
+                    self.ready.pop_front();
+                    let reason = format!("{} rejected a batch of {} readings: status {}", self.config.url, readings, status);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, reason));
+                }
+                Outcome::Failed(err) => {
+                    batch.attempts += 1;
+                    if batch.attempts > self.config.retries {
+                        let batch = self.ready.pop_front().expect("front batch");
+                        self.give_up(batch)?;
+                    }
+                    return Err(self.failed(now, err));
+                }
+            }
+        }
+        self.failures = 0;
+        self.retry_at = None;
+        Ok(())
+    }
+
+    /// Back off after a failed request.
+    fn failed(&mut self, now: Instant, err: io::Error) -> io::Error {
+        self.failures += 1;
+        let backoff = self.config.backoff_ms.saturating_mul(1 << (self.failures - 1).min(30));
+        self.retry_at = Some(now + Duration::from_millis(backoff.min(self.config.max_backoff_ms)));
+        err
+    }
+
+    /// Spool a batch that will not be retried from memory, or count it lost.
+    fn give_up(&mut self, batch: Batch) -> io::Result<()> {
+        match &mut self.spool {
+            Some(spool) => spool.push(&frame(batch.readings, &batch.body)),
+            None => {
+                self.lost += batch.readings;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Sink for HttpSink {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn publish(&mut self, records: &[Record]) -> io::Result<()> {
+        self.publish_at(records, Instant::now())
+    }
+
+    /// Send the partial batch and make one last attempt at everything
+    /// pending, regardless of the backoff.
+    fn close(&mut self) -> io::Result<()> {
+        self.seal()?;
+        self.retry_at = None;
+        let result = self.pump(Instant::now());
+        while let Some(batch) = self.ready.pop_front() {
+            self.give_up(batch)?;
+        }
+        result
+    }
+}
+
+impl fmt::Display for HttpSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http {}: {} sent", self.config.url, self.sent)?;
+        if self.rejected > 0 {
+            write!(f, ", {} rejected", self.rejected)?;
+        }
+        match &self.spool {
+            Some(spool) => write!(f, ", {} queued, {} dropped", spool.len(), spool.dropped()),
+            None if self.lost > 0 => write!(f, ", {} lost", self.lost),
+            None => Ok(()),
+        }
+    }
+}
+
+enum Outcome {
+    Sent,
+    /// A 4xx status other than 408 and 429: the same request will not do better.
+    Rejected(u16),
+    /// Worth retrying: no answer, a 5xx, 408 or 429.
+    Failed(io::Error),
+}
+
+fn send(config: &HttpConfig, body: &[u8]) -> Outcome {
+    match post(config, body) {
+        Ok(200..=299) => Outcome::Sent,
+        Ok(status @ (408 | 429 | 500..)) => Outcome::Failed(io::Error::other(format!("{} answered status {}", config.url, status))),
+        Ok(status) => Outcome::Rejected(status),
+        Err(err) => Outcome::Failed(err),
+    }
+}
+
+/// POST `body` and return the response status.
+#[cfg(not(all(target_os = "wasi", target_env = "p2", feature = "wasi-http")))]
+fn post(config: &HttpConfig, body: &[u8]) -> io::Result<u16> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpStream, ToSocketAddrs};
+
+    let url = &config.url;
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", url.host));
+    for addr in (url.host.trim_matches(['[', ']']), url.port).to_socket_addrs()? {
+        let mut stream = match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => stream,
+            Err(err) => {
+                last_error = err;
+                continue;
+            }
+        };
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            url.path, url.authority(), body.len(),
+        );
+        for (name, value) in &config.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        // `HTTP/1.1 200 OK`
+        return status_line.split(' ').nth(1)
+            .filter(|_| status_line.starts_with("HTTP/1."))
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed status line '{}'", status_line.trim_end())));
+    }
+    Err(last_error)
+}
+
+/// POST `body` through `wasi:http/outgoing-handler` and return the response
+/// status.
+#[cfg(all(target_os = "wasi", target_env = "p2", feature = "wasi-http"))]
+fn post(config: &HttpConfig, body: &[u8]) -> io::Result<u16> {
+    use wasi::http::outgoing_handler;
+    use wasi::http::types::{Fields, Method, OutgoingBody, OutgoingRequest, RequestOptions, Scheme};
+
+    let url = &config.url;
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: invalid {}", url, what));
+    let mut fields = vec![("content-type".to_string(), b"application/json".to_vec())];
+    fields.extend(config.headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.as_bytes().to_vec())));
+    let headers = Fields::from_list(&fields).map_err(|_| invalid("headers"))?;
+    let request = OutgoingRequest::new(headers);
+    request.set_method(&Method::Post).map_err(|()| invalid("method"))?;
+    request.set_scheme(Some(if url.https { &Scheme::Https } else { &Scheme::Http })).map_err(|()| invalid("scheme"))?;
+    request.set_authority(Some(&url.authority())).map_err(|()| invalid("authority"))?;
+    request.set_path_with_query(Some(&url.path)).map_err(|()| invalid("path"))?;
+    let outgoing = request.body().map_err(|()| io::Error::other("request body already taken"))?;
+
+    let timeout = Some(config.timeout_ms.saturating_mul(1_000_000));
+    let options = RequestOptions::new();
+    // Hosts that do not support a timeout refuse it; send without one then.
+    let _ = options.set_connect_timeout(timeout);
+    let _ = options.set_first_byte_timeout(timeout);
+    let _ = options.set_between_bytes_timeout(timeout);
+
+    let response = outgoing_handler::handle(request, Some(options)).map_err(|err| io::Error::other(err.to_string()))?;
+    {
+        let stream = outgoing.write().map_err(|()| io::Error::other("request body stream already taken"))?;
+        // A single blocking write may carry at most 4096 bytes.
+        for chunk in body.chunks(4096) {
+            stream.blocking_write_and_flush(chunk).map_err(|err| io::Error::other(format!("{:?}", err)))?;
+        }
+    }
+    OutgoingBody::finish(outgoing, None).map_err(|err| io::Error::other(err.to_string()))?;
+    response.subscribe().block();
+    match response.get() {
+        Some(Ok(Ok(response))) => Ok(response.status()),
+        Some(Ok(Err(err))) => Err(io::Error::other(err.to_string())),
+        _ => Err(io::Error::other("no response")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::clock::Timestamp;
+    use crate::reading::Channel;
+    use crate::spool::DropPolicy;
+
+    /// A request as the server stand-in saw it.
+    #[derive(Debug)]
+    struct Request {
+        head: String,
+        body: serde_json::Value,
+    }
+
+    impl Request {
+        /// The `seq` of every reading in the batch.
+        fn seqs(&self) -> Vec<u64> {
+            self.body.as_array().unwrap().iter().map(|reading| reading["seq"].as_u64().unwrap()).collect()
+        }
+    }
+
+    /// HTTP server stand-in on a loopback port. It takes one connection per
+    /// entry of `statuses` and answers its request with that status; the
+    /// thread returns the requests.
+    fn server(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<Request>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/readings", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            statuses.into_iter().map(|status| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    assert_ne!(reader.read_line(&mut head).unwrap(), 0, "connection closed in the head");
+                }
+                let length = head.lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let response = format!("HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                Request { head, body: serde_json::from_slice(&body).unwrap() }
+            }).collect()
+        });
+        (url, handle)
+    }
+
+    fn config(url: &str) -> HttpConfig {
+        HttpConfig { url: url.parse().unwrap(), batch_size: 1, retries: 0, ..HttpConfig::default() }
+    }
+
+    /// Publish one reading per entry of `seqs` at `at`.
+    fn publish(sink: &mut HttpSink, at: Instant, seqs: &[u64]) -> io::Result<()> {
+        let reading = Channel::new("temperature", "°C").reading(21.5);
+        let records: Vec<_> = seqs.iter()
+            .map(|&seq| Record { seq, reading: &reading, timestamp: Timestamp::Wall(Duration::from_secs(1_770_656_082)) })
+            .collect();
+        sink.publish_at(&records, at)
+    }
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn batches_by_size_and_linger() {
+        let (url, server) = server(vec![200, 200]);
+        let mut headers = BTreeMap::new();
+        headers.insert("X-Site".to_string(), "lab1".to_string());
+        let mut sink = HttpSink::new(HttpConfig { batch_size: 3, headers, ..config(&url) }, None);
+        let t0 = Instant::now();
+        publish(&mut sink, t0, &[1, 2]).unwrap();
+        publish(&mut sink, t0 + secs(1.0), &[3, 4]).unwrap();
+        // 4 waits until it has lingered for ten seconds.
+        publish(&mut sink, t0 + secs(10.9), &[]).unwrap();
+        assert_eq!(sink.sent, 3);
+        publish(&mut sink, t0 + secs(11.0), &[]).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.iter().map(Request::seqs).collect::<Vec<_>>(), [vec![1, 2, 3], vec![4]]);
+        let head = &requests[0].head;
+        assert!(head.starts_with("POST /readings HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("\r\nContent-Type: application/json\r\n") && head.contains("\r\nX-Site: lab1\r\n"), "{}", head);
+        assert_eq!(requests[0].body[0]["sensor"], "temperature");
+        assert_eq!(sink.to_string(), format!("http {}: 4 sent", url));
+    }
+
+    #[test]
+    fn retries_with_exponential_backoff() {
+        let (url, server) = server(vec![503, 429, 503, 200]);
+        let config = HttpConfig { retries: 3, backoff_ms: 1000, max_backoff_ms: 1500, ..config(&url) };
+        let mut sink = HttpSink::new(config, None);
+        let t0 = Instant::now();
+        let err = publish(&mut sink, t0, &[1]).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} answered status 503", url));
+        // Backing off for 1s, then 2s capped at 1.5s, then 1.5s.
+        for (at, attempt) in [(0.9, false), (1.0, true), (2.4, false), (2.5, true), (3.9, false), (4.0, true)] {
+            assert_eq!(publish(&mut sink, t0 + secs(at), &[]).is_err(), attempt && at < 4.0, "at {}s", at);
+        }
+        let requests = server.join().unwrap();
+        assert!(requests.iter().all(|request| request.seqs() == [1]));
+        assert_eq!(sink.to_string(), format!("http {}: 1 sent", url));
+    }
+
+    #[test]
+    fn spools_batches_out_of_retries_and_sends_them_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, server) = server(vec![500, 200, 200]);
+        let spool = Spool::open(dir.path(), 1024 * 1024, DropPolicy::Oldest).unwrap();
+        let mut sink = HttpSink::new(config(&url), Some(spool));
+        let t0 = Instant::now();
+        assert!(publish(&mut sink, t0, &[1]).is_err());
+        assert_eq!(sink.spool.as_ref().unwrap().len(), 1);
+        publish(&mut sink, t0 + secs(1.0), &[2]).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.iter().map(Request::seqs).collect::<Vec<_>>(), [vec![1], vec![1], vec![2]]);
+        assert_eq!(sink.to_string(), format!("http {}: 2 sent, 0 queued, 0 dropped", url));
+    }
+
+    #[test]
+    fn counts_rejected_and_lost_batches() {
+        let (url, server) = server(vec![400, 503, 200]);
+        let mut sink = HttpSink::new(config(&url), None);
+        let t0 = Instant::now();
+        let err = publish(&mut sink, t0, &[1]).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} rejected a batch of 1 readings: status 400", url));
+        // A rejection is not retried and does not back off.
+        assert!(publish(&mut sink, t0, &[2]).is_err());
+        publish(&mut sink, t0 + secs(1.0), &[3]).unwrap();
+
+        assert_eq!(server.join().unwrap().iter().map(Request::seqs).collect::<Vec<_>>(), [vec![1], vec![2], vec![3]]);
+        assert_eq!(sink.to_string(), format!("http {}: 1 sent, 1 rejected, 1 lost", url));
+    }
+
+    #[test]
+    fn close_sends_the_partial_batch() {
+        let (url, server) = server(vec![200]);
+        let mut sink = HttpSink::new(HttpConfig { batch_size: 10, ..config(&url) }, None);
+        publish(&mut sink, Instant::now(), &[1, 2]).unwrap();
+        sink.close().unwrap();
+        assert_eq!(server.join().unwrap()[0].seqs(), [1, 2]);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let cases = [
+            ("url = \"ftp://collector/readings\"", "'ftp://collector/readings' is not an http:// or https:// URL"),
+            ("url = \"http://:8080/\"", "'http://:8080/' has no valid host"),
+            ("url = \"http://collector:http/\"", "'http' is not a valid port"),
+            ("batch_size = 0", "batch_size must be at least 1"),
+            ("timeout_ms = 0", "timeout_ms must be at least 1"),
+            ("headers = { \"X-A\" = \"1\\r\\nX-B: 2\" }", "'X-A' is not a valid header"),
+            ("headers = { \"content-length\" = \"0\" }", "the 'content-length' header is managed by the sink"),
+            ("headers = { Host = \"collector\" }", "the 'Host' header is managed by the sink"),
+            ("headers = { \"Content-TYPE\" = \"text/plain\" }", "the 'Content-TYPE' header is managed by the sink"),
+            ("headers = { connection = \"keep-alive\" }", "the 'connection' header is managed by the sink"),
+            ("headers = { \"Transfer-Encoding\" = \"chunked\" }", "the 'Transfer-Encoding' header is managed by the sink"),
+            ("endpoint = \"http://collector/\"", "unknown field `endpoint`"),
+        ];
+        for (toml, expected) in cases {
+            let err = toml::from_str::<HttpConfig>(toml).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", toml, err);
+        }
+        let url: Endpoint = "http://collector?site=lab1".parse().unwrap();
+        assert_eq!((url.authority(), url.path.as_str()), ("collector".to_string(), "/?site=lab1"));
+        assert_eq!(url.to_string(), "http://collector/?site=lab1");
+    }
+}
@@ -0,0 +1,206 @@
+//! The [`Sink`] trait and the upstream sinks readings are pushed to, next to
+//! the output on stdout.
+
+mod coap;
+mod http;
+mod mqtt;
+mod senml;
+mod sparkplug;
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+pub use coap::{CoapConfig, CoapFormat, CoapSink};
+pub use http::{Endpoint, HttpConfig, HttpSink};
+pub use mqtt::{MqttConfig, MqttSink, MqttVersion, QoS, Will};
+pub use sparkplug::SparkplugConfig;
+
+use crate::config::Settings;
+use crate::error::{Error, Result};
+use crate::output::Record;
+use crate::spool::Spool;
+
+/// A destination for readings beyond stdout, e.g. a broker or an HTTP
+/// collector.
+///
+/// Sinks fail softly: the loop logs a failed [`Sink::publish`] and carries
+/// on, and sinks with a spool keep what they could not deliver for later.
+/// The `Display` impl is the line in the completion summary.
+pub trait Sink: fmt::Display {
+    /// Short name used in error messages, e.g. `mqtt`.
+    fn name(&self) -> &str;
+
+    /// Deliver one iteration's readings. Called every iteration, even when
+    /// there are none, so sinks can send what has been waiting.
+    fn publish(&mut self, records: &[Record]) -> io::Result<()>;
+
+    /// Longest the sink can go without a `publish`, e.g. to keep a
+    /// connection alive. Longer waits in the loop publish nothing in between.
+    fn max_idle(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Flush and disconnect at the end of the run.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sinks configured in `settings`; usually none.
+pub fn registry(settings: &Settings) -> Result<Vec<Box<dyn Sink>>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    if let Some(config) = &settings.mqtt {
+        let keep_alive = Duration::from_secs(config.keep_alive_secs.into());
+        if !keep_alive.is_zero() && keep_alive < settings.interval {
+            let reason = format!("the MQTT keep-alive of {} s is shorter than the interval of {} ms", config.keep_alive_secs, settings.interval.as_millis());
+            return Err(Error::Usage(reason));
+        }
+        let spool = spool(settings, "mqtt")?;
+        sinks.push(Box::new(MqttSink::new(config.clone(), spool)));
+    }
+    if let Some(config) = &settings.http {
+        let spool = spool(settings, "http")?;
+        sinks.push(Box::new(HttpSink::new(config.clone(), spool)));
+    }
+    if let Some(config) = &settings.coap {
+        let spool = spool(settings, "coap")?;
+        sinks.push(Box::new(CoapSink::new(config.clone(), spool)));
+    }
+    Ok(sinks)
+}
+
+/// The sink's own spool under `--spool-dir`, if one is configured. A
+/// directory that cannot be used is a configuration error.
+fn spool(settings: &Settings, sink: &str) -> Result<Option<Spool>> {
+    match &settings.spool_dir {
+        None => Ok(None),
+        Some(dir) => {
+            let dir = dir.join(sink);
+            let spool = Spool::open(&dir, settings.spool_max, settings.spool_drop)
+                .map_err(|err| Error::config(&dir, format!("cannot use the spool directory: {}", err)))?;
+            Ok(Some(spool))
+        }
+    }
+}
+
+/// `server` as `host:port`, with `port` added unless it names one. A bare
+/// IPv6 address such as `fe80::1` is put in brackets first.
+fn with_default_port(server: &str, port: u16) -> String {
+    match server.rsplit_once(':') {
+        _ if server.starts_with('[') && server.ends_with(']') => format!("{}:{}", server, port),
+        Some((host, _)) if host.starts_with('[') || !host.contains(':') => server.to_string(),
+        Some(_) => format!("[{}]:{}", server, port),
+        None => format!("{}:{}", server, port),
+    }
+}
+
+/// A spooled payload: its reading count as 4 little-endian bytes, then the
+/// payload, so readings forwarded later are still counted.
+fn frame(readings: u64, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(readings as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn unframe(frame: &[u8]) -> io::Result<(u64, &[u8])> {
+    match frame.split_first_chunk::<4>() {
+        Some((count, payload)) => Ok((u64::from(u32::from_le_bytes(*count)), payload)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt spooled payload")),
+    }
+}
+
+/// Send the [`frame`]d payloads in `spool` oldest first. `send` tells
+/// whether the receiver accepted a payload; its readings are added to
+/// `delivered` or `rejected`. An error leaves the payload queued.
+fn forward(
+    spool: &mut Spool,
+    delivered: &mut u64,
+    rejected: &mut u64,
+    mut send: impl FnMut(&[u8]) -> io::Result<bool>,
+) -> io::Result<usize> {
+    spool.forward(|frame| {
+        let (readings, payload) = unframe(frame)?;
+        if send(payload)? {
+            *delivered += readings;
+        } else {
+            // Resending will not change the answer; don't let it hold up the
+            // rest of the spool.
+            *rejected += readings;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spool::DropPolicy;
+
+    #[test]
+    fn adds_the_default_port_unless_one_is_given() {
+        let cases = [
+            ("gateway.local", "gateway.local:5683"),
+            ("gateway.local:1234", "gateway.local:1234"),
+            ("192.0.2.1", "192.0.2.1:5683"),
+            ("fe80::1", "[fe80::1]:5683"),
+            ("::1", "[::1]:5683"),
+            ("[fe80::1]", "[fe80::1]:5683"),
+            ("[fe80::1]:1234", "[fe80::1]:1234"),
+        ];
+        for (server, expected) in cases {
+            assert_eq!(with_default_port(server, 5683), expected);
+        }
+    }
+
+    #[test]
+    fn forwards_framed_payloads_and_counts_their_readings() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1 << 20, DropPolicy::Oldest).unwrap();
+        for (readings, payload) in [(3, "accepted"), (2, "refused"), (4, "unreachable"), (1, "later")] {
+            spool.push(&frame(readings, payload.as_bytes())).unwrap();
+        }
+        let (mut delivered, mut rejected) = (0, 0);
+        let err = forward(&mut spool, &mut delivered, &mut rejected, |payload| match payload {
+            b"accepted" => Ok(true),
+            b"refused" => Ok(false),
+            _ => Err(io::Error::other("no route to host")),
+        }).unwrap_err();
+        assert_eq!(err.to_string(), "no route to host");
+        assert_eq!((delivered, rejected, spool.len()), (3, 2, 2));
+
+        let mut sent = Vec::new();
+        forward(&mut spool, &mut delivered, &mut rejected, |payload| {
+            sent.push(payload.to_vec());
+            Ok(true)
+        }).unwrap();
+        assert_eq!(sent, [b"unreachable".to_vec(), b"later".to_vec()]);
+        assert_eq!((delivered, rejected, spool.len()), (8, 2, 0));
+        assert_eq!(unframe(&[1, 0]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_a_keep_alive_shorter_than_the_interval() {
+        let mut settings = Settings {
+            interval: Duration::from_secs(90),
+            mqtt: Some(MqttConfig { keep_alive_secs: 60, ..MqttConfig::default() }),
+            ..Settings::default()
+        };
+        let err = registry(&settings).err().unwrap();
+        assert_eq!(err.to_string(), "the MQTT keep-alive of 60 s is shorter than the interval of 90000 ms");
+        settings.mqtt = Some(MqttConfig { keep_alive_secs: 0, ..MqttConfig::default() });
+        assert_eq!(registry(&settings).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn an_unusable_spool_dir_is_a_config_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("spool");
+        std::fs::write(&file, "not a directory").unwrap();
+        let settings = Settings { spool_dir: Some(file), http: Some(HttpConfig::default()), ..Settings::default() };
+        let err = registry(&settings).err().unwrap();
+        assert!(matches!(err, Error::Config { .. }), "{:?}", err);
+        assert_eq!(format!("{:?}", err.exit_code()), format!("{:?}", std::process::ExitCode::from(crate::error::EXIT_CONFIG)));
+    }
+}
@@ -0,0 +1,863 @@
+//! MQTT 3.1.1 and 5 publisher: one message per reading, on a topic rendered
+//! from a template.
+//!
+//! The client speaks just enough of the protocol to publish: CONNECT with an
+//! optional last will, PUBLISH at QoS 0, 1 or 2, PINGREQ to keep an idle
+//! connection open between iterations, and DISCONNECT. It runs over
+//! `std::net`, which `wasm32-wasip2` maps to `wasi:sockets`, so the host has
+//! to grant network access. Messages the broker did not take go to the spool,
+//! when one is configured, and are published first once it is back.
+//!
+//! With a `sparkplug` key the sink speaks Sparkplug B instead: one NDATA per
+//! iteration, framed by the NBIRTH and NDEATH lifecycle from [`sparkplug`].
+//!
+//! [`sparkplug`]: super::sparkplug
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Deserializer};
+
+use super::{Sink, with_default_port};
+use super::sparkplug::{Node, SparkplugConfig};
+use crate::output::{self, Record};
+use crate::reading::Reading;
+use crate::spool::Spool;
+
+const DEFAULT_PORT: u16 = 1883;
+/// How long to wait for the broker to accept a connection or answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Control packet types and their fixed flags, the first byte of a packet.
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+/// The `[mqtt]` table of the configuration file.
+///
+/// ```toml
+/// [mqtt]
+/// broker = "mosquitto:1883"
+/// version = "5"
+/// client_id = "wasm-hello-lab1"
+/// topic = "sites/{site}/{sensor}"
+/// qos = 1
+/// will = { topic = "sites/lab1/status", payload = "offline", retain = true }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// `host:port`; the port defaults to 1883. An IPv6 address with a port
+    /// goes in brackets, e.g. `[fe80::1]:1883`.
+    pub broker: String,
+    pub version: MqttVersion,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topic per reading; `{sensor}`, `{quantity}`, `{unit}` and tag names in
+    /// braces are replaced by the reading's values.
+    #[serde(deserialize_with = "topic")]
+    pub topic: String,
+    pub qos: QoS,
+    pub retain: bool,
+    /// How long the broker waits without hearing from the client before it
+    /// drops the connection and publishes the will; 0 disables it. The
+    /// client pings the broker once the connection has been idle for half of
+    /// it, so it must not be shorter than the interval.
+    pub keep_alive_secs: u16,
+    /// Published by the broker if the connection is lost without a DISCONNECT.
+    pub will: Option<Will>,
+    /// Publish Sparkplug B messages for this edge node; `topic`, `qos`,
+    /// `retain` and `will` are then set by the specification.
+    pub sparkplug: Option<SparkplugConfig>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            broker: format!("localhost:{}", DEFAULT_PORT),
+            version: MqttVersion::V311,
+            client_id: "wasm-hello".to_string(),
+            username: None,
+            password: None,
+            topic: "sensors/{sensor}".to_string(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            keep_alive_secs: 60,
+            will: None,
+            sparkplug: None,
+        }
+    }
+}
+
+impl MqttConfig {
+    /// Rules across keys; MQTT 3.1.1 does not allow a password alone.
+    pub fn check(&self) -> Result<(), String> {
+        if self.password.is_some() && self.username.is_none() {
+            return Err("a password needs a username".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Last will and testament, e.g. an `offline` status.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Will {
+    #[serde(deserialize_with = "topic")]
+    pub topic: String,
+    pub payload: String,
+    #[serde(default)]
+    pub qos: QoS,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum MqttVersion {
+    #[default]
+    #[serde(rename = "3.1.1")]
+    V311,
+    #[serde(rename = "5")]
+    V5,
+}
+
+impl MqttVersion {
+    /// Protocol level in the CONNECT packet.
+    fn level(self) -> u8 {
+        match self {
+            MqttVersion::V311 => 4,
+            MqttVersion::V5 => 5,
+        }
+    }
+}
+
+impl fmt::Display for MqttVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MqttVersion::V311 => "3.1.1",
+            MqttVersion::V5 => "5",
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u8")]
+pub enum QoS {
+    #[default]
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+    ExactlyOnce = 2,
+}
+
+impl TryFrom<u8> for QoS {
+    type Error = String;
+
+    fn try_from(qos: u8) -> Result<Self, Self::Error> {
+        match qos {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            _ => Err(format!("qos must be 0, 1 or 2, not {}", qos)),
+        }
+    }
+}
+
+/// A topic without the wildcards only subscriptions may use.
+fn topic<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let topic = String::deserialize(deserializer)?;
+    if topic.is_empty() || topic.contains(['+', '#', '\0']) {
+        return Err(serde::de::Error::custom(format!("'{}' is not a topic to publish to", topic)));
+    }
+    Ok(topic)
+}
+
+/// `template` with its placeholders replaced. Values are stripped of the
+/// characters that would change the topic's structure.
+fn render_topic(template: &str, reading: &Reading) -> String {
+    let mut topic = String::with_capacity(template.len() + reading.sensor.len());
+    let mut rest = template;
+    while let Some((before, after)) = rest.split_once('{') {
+        let (key, after) = match after.split_once('}') {
+            Some(split) => split,
+            None => break,
+        };
+        topic.push_str(before);
+        let value = match key {
+            "sensor" => &reading.sensor,
+            "quantity" => reading.quantity.as_str(),
+            "unit" => &reading.unit,
+            tag => reading.tags.get(tag).map_or("", String::as_str),
+        };
+        topic.extend(value.chars().map(|c| if matches!(c, '/' | '+' | '#' | '\0') { '_' } else { c }));
+        rest = after;
+    }
+    topic.push_str(rest);
+    topic
+}
+
+/// Publishes every reading to an MQTT broker.
+pub struct MqttSink {
+    client: Client,
+    spool: Option<Spool>,
+    published: u64,
+    /// Messages that could not be delivered and had no spool to go to.
+    lost: u64,
+}
+
+impl MqttSink {
+    /// A sink for `config`; connects on the first publish.
+    pub fn new(config: MqttConfig, spool: Option<Spool>) -> Self {
+        let node = config.sparkplug.clone().map(Node::new);
+        let client = Client { config, stream: None, packet_id: 0, node, last_sent: Instant::now() };
+        MqttSink { client, spool, published: 0, lost: 0 }
+    }
+}
+
+impl Sink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    /// Half the keep-alive, when a publish with nothing pings the broker.
+    fn max_idle(&self) -> Option<Duration> {
+        let keep_alive = Duration::from_secs(self.client.config.keep_alive_secs.into());
+        (!keep_alive.is_zero()).then(|| keep_alive / 2)
+    }
+
+    fn publish(&mut self, records: &[Record]) -> io::Result<()> {
+        let MqttSink { client, spool, published, lost } = self;
+        let mut messages = Vec::with_capacity(records.len());
+        match &mut client.node {
+            Some(node) if !records.is_empty() => messages.push((node.topic("NDATA"), node.data(records))),
+            Some(_) => {}
+            None => {
+                for record in records {
+                    let mut payload = Vec::new();
+                    output::write_json(&mut payload, record)?;
+                    messages.push((render_topic(&client.config.topic, record.reading), payload));
+                }
+            }
+        }
+        if messages.is_empty() {
+            client.keep_alive()?;
+        }
+        let spool = match spool {
+            Some(spool) if !spool.is_empty() => spool,
+            spool => {
+                // Nothing is queued, so publish directly; after a failure
+                // the rest queue behind the one that failed.
+                for (index, (topic, payload)) in messages.iter().enumerate() {
+                    if let Err(err) = client.publish(topic, payload) {
+                        match spool {
+                            Some(spool) => {
+                                for (topic, payload) in &messages[index..] {
+                                    spool.push(&frame(topic, payload))?;
+                                }
+                            }
+                            None => *lost += (messages.len() - index) as u64,
+                        }
+                        return Err(err);
+                    }
+                    *published += 1;
+                }
+                return Ok(());
+            }
+        };
+        for (topic, payload) in &messages {
+            spool.push(&frame(topic, payload))?;
+        }
+        spool.forward(|message| {
+            let (topic, payload) = unframe(message)?;
+            client.publish(topic, payload)?;
+            *published += 1;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.client.disconnect()
+    }
+}
+
+impl fmt::Display for MqttSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mqtt {}: {} published", self.client.config.broker, self.published)?;
+        match &self.spool {
+            Some(spool) => write!(f, ", {} queued, {} dropped", spool.len(), spool.dropped()),
+            None if self.lost > 0 => write!(f, ", {} lost", self.lost),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A spooled message: the topic, a NUL (which topics cannot contain), then
+/// the payload.
+fn frame(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(topic.len() + 1 + payload.len());
+    message.extend_from_slice(topic.as_bytes());
+    message.push(0);
+    message.extend_from_slice(payload);
+    message
+}
+
+fn unframe(message: &[u8]) -> io::Result<(&str, &[u8])> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt spooled MQTT message");
+    let split = message.iter().position(|&byte| byte == 0).ok_or_else(invalid)?;
+    let topic = std::str::from_utf8(&message[..split]).map_err(|_| invalid())?;
+    Ok((topic, &message[split + 1..]))
+}
+
+/// One broker connection, reopened as needed.
+struct Client {
+    config: MqttConfig,
+    stream: Option<TcpStream>,
+    /// Identifier of the last QoS 1 or 2 publish.
+    packet_id: u16,
+    /// Sparkplug lifecycle, which starts a new session with every connection.
+    node: Option<Node>,
+    /// When the client last sent the broker a packet.
+    last_sent: Instant,
+}
+
+impl Client {
+    fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let reused = self.stream.is_some();
+        let mut result = self.try_publish(topic, payload);
+        if result.is_err() && reused {
+            // The broker drops connections idle past the keep-alive; retry
+            // once on a fresh one.
+            self.stream = None;
+            result = self.try_publish(topic, payload);
+        }
+        match result {
+            Ok(()) => self.last_sent = Instant::now(),
+            Err(_) => self.stream = None,
+        }
+        result
+    }
+
+    /// Ping the broker if the connection has been idle for half the
+    /// keep-alive, so that it is not dropped, and the will published,
+    /// between iterations with nothing to send.
+    fn keep_alive(&mut self) -> io::Result<()> {
+        let keep_alive = Duration::from_secs(self.config.keep_alive_secs.into());
+        let stream = match &mut self.stream {
+            Some(stream) if !keep_alive.is_zero() && self.last_sent.elapsed() >= keep_alive / 2 => stream,
+            _ => return Ok(()),
+        };
+        let result = stream.write_all(&packet_with(PINGREQ, &[])).and_then(|()| loop {
+            if read_packet(stream)?.0 == PINGRESP {
+                return Ok(());
+            }
+        });
+        match result {
+            Ok(()) => self.last_sent = Instant::now(),
+            // Reconnect with the next message.
+            Err(_) => self.stream = None,
+        }
+        result
+    }
+
+    fn try_publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(match &mut self.node {
+                Some(node) => {
+                    let death = node.session();
+                    let topic = node.topic("NDEATH");
+                    open(&self.config, Some(&LastWill { topic: &topic, payload: &death, qos: QoS::AtLeastOnce, retain: false }))?
+                }
+                None => open(&self.config, self.config.will.as_ref().map(LastWill::from).as_ref())?,
+            });
+        }
+        let Client { config, stream, packet_id, node, .. } = self;
+        let stream = stream.as_mut().expect("connected above");
+        match node {
+            // Sparkplug messages go out at QoS 0 and are never retained.
+            Some(node) => {
+                if node.needs_birth() {
+                    send(stream, config.version, packet_id, &node.topic("NBIRTH"), &node.birth(), QoS::AtMostOnce, false)?;
+                }
+                send(stream, config.version, packet_id, topic, &node.stamp(payload), QoS::AtMostOnce, false)
+            }
+            None => send(stream, config.version, packet_id, topic, payload, config.qos, config.retain),
+        }
+    }
+
+    /// Disconnect cleanly, so the broker discards the will. A Sparkplug node
+    /// publishes its NDEATH itself first.
+    fn disconnect(&mut self) -> io::Result<()> {
+        match self.stream.take() {
+            Some(mut stream) => {
+                if let Some(node) = &self.node {
+                    let topic = node.topic("NDEATH");
+                    send(&mut stream, self.config.version, &mut self.packet_id, &topic, &node.death(), QoS::AtLeastOnce, false)?;
+                }
+                stream.write_all(&packet_with(DISCONNECT, &[]))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Publish one message on an open connection and wait for its
+/// acknowledgements.
+fn send(stream: &mut TcpStream, version: MqttVersion, packet_id: &mut u16, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> io::Result<()> {
+    let id = match qos {
+        QoS::AtMostOnce => None,
+        _ => {
+            *packet_id = packet_id.wrapping_add(1).max(1);
+            Some(*packet_id)
+        }
+    };
+    stream.write_all(&publish_packet(version, topic, payload, qos, retain, id)?)?;
+    match (qos, id) {
+        (QoS::AtLeastOnce, Some(id)) => expect_ack(stream, PUBACK, id),
+        (QoS::ExactlyOnce, Some(id)) => {
+            expect_ack(stream, PUBREC, id)?;
+            stream.write_all(&packet_with(PUBREL, &id.to_be_bytes()))?;
+            expect_ack(stream, PUBCOMP, id)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The will of one connection: the configured [`Will`], or an NDEATH.
+struct LastWill<'a> {
+    topic: &'a str,
+    payload: &'a [u8],
+    qos: QoS,
+    retain: bool,
+}
+
+impl<'a> From<&'a Will> for LastWill<'a> {
+    fn from(will: &'a Will) -> Self {
+        LastWill { topic: &will.topic, payload: will.payload.as_bytes(), qos: will.qos, retain: will.retain }
+    }
+}
+
+/// Connect to the broker and complete the CONNECT handshake.
+fn open(config: &MqttConfig, will: Option<&LastWill>) -> io::Result<TcpStream> {
+    let address = with_default_port(&config.broker, DEFAULT_PORT);
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", address));
+    for addr in address.to_socket_addrs()? {
+        let mut stream = match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => stream,
+            Err(err) => {
+                last_error = err;
+                continue;
+            }
+        };
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.write_all(&connect_packet(config, will)?)?;
+        let (header, body) = read_packet(&mut stream)?;
+        if header != CONNACK {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected CONNACK, got packet 0x{:02x}", header)));
+        }
+        return match body.get(1) {
+            Some(0) => Ok(stream),
+            Some(&code) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, refusal(config.version, code))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "short CONNACK")),
+        };
+    }
+    Err(last_error)
+}
+
+fn refusal(version: MqttVersion, code: u8) -> String {
+    let reason = match (version, code) {
+        (MqttVersion::V311, 1) | (MqttVersion::V5, 0x84) => "unsupported protocol version",
+        (MqttVersion::V311, 2) | (MqttVersion::V5, 0x85) => "client identifier rejected",
+        (MqttVersion::V311, 3) | (MqttVersion::V5, 0x88) => "server unavailable",
+        (MqttVersion::V311, 4) | (MqttVersion::V5, 0x86) => "bad user name or password",
+        (MqttVersion::V311, 5) | (MqttVersion::V5, 0x87) => "not authorized",
+        _ => "refused",
+    };
+    format!("broker refused the connection: {} (0x{:02x})", reason, code)
+}
+
+/// Wait for the acknowledgement of type `kind` for packet `id`, skipping
+/// anything else the broker sends meanwhile.
+fn expect_ack(stream: &mut TcpStream, kind: u8, id: u16) -> io::Result<()> {
+    loop {
+        let (header, body) = read_packet(stream)?;
+        if header & 0xf0 != kind & 0xf0 || body.get(..2) != Some(&id.to_be_bytes()[..]) {
+            continue;
+        }
+        // MQTT 5 acknowledgements may add a reason code; 0x80 and up are failures.
+        return match body.get(2) {
+            Some(&code) if code >= 0x80 => Err(io::Error::other(format!("broker rejected message {}: reason code 0x{:02x}", id, code))),
+            _ => Ok(()),
+        };
+    }
+}
+
+fn connect_packet(config: &MqttConfig, will: Option<&LastWill>) -> io::Result<Vec<u8>> {
+    let v5 = config.version == MqttVersion::V5;
+    let mut flags = 0x02; // clean session
+    if let Some(will) = will {
+        flags |= 0x04 | (will.qos as u8) << 3 | u8::from(will.retain) << 5;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    let mut body = Vec::new();
+    put(&mut body, b"MQTT")?;
+    body.push(config.version.level());
+    body.push(flags);
+    body.extend_from_slice(&config.keep_alive_secs.to_be_bytes());
+    if v5 {
+        body.push(0); // no properties
+    }
+    put(&mut body, config.client_id.as_bytes())?;
+    if let Some(will) = will {
+        if v5 {
+            body.push(0); // no will properties
+        }
+        put(&mut body, will.topic.as_bytes())?;
+        put(&mut body, will.payload)?;
+    }
+    for field in [&config.username, &config.password].into_iter().flatten() {
+        put(&mut body, field.as_bytes())?;
+    }
+    Ok(packet_with(CONNECT, &body))
+}
+
+fn publish_packet(version: MqttVersion, topic: &str, payload: &[u8], qos: QoS, retain: bool, id: Option<u16>) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(2 + topic.len() + 3 + payload.len());
+    put(&mut body, topic.as_bytes())?;
+    if let Some(id) = id {
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    if version == MqttVersion::V5 {
+        body.push(0); // no properties
+    }
+    body.extend_from_slice(payload);
+    Ok(packet_with(PUBLISH | (qos as u8) << 1 | u8::from(retain), &body))
+}
+
+/// A length-prefixed string or binary field.
+fn put(body: &mut Vec<u8>, field: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(field.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("field of {} bytes is longer than MQTT allows", field.len()))
+    })?;
+    body.extend_from_slice(&len.to_be_bytes());
+    body.extend_from_slice(field);
+    Ok(())
+}
+
+/// `first` byte, the remaining length as a variable byte integer, then `body`.
+fn packet_with(first: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(5 + body.len());
+    packet.push(first);
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Read one packet; returns its first byte and body.
+fn read_packet(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte)?;
+    let first = byte[0];
+    let mut len = 0;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte)?;
+        len |= usize::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body)?;
+            return Ok((first, body));
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "malformed remaining length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::clock::Timestamp;
+    use crate::reading::Channel;
+    use crate::spool::DropPolicy;
+
+    /// A publish as the broker saw it.
+    #[derive(Debug, PartialEq)]
+    struct Received {
+        topic: String,
+        qos: u8,
+        retain: bool,
+        payload: Vec<u8>,
+    }
+
+    /// Everything the broker stand-in received.
+    #[derive(Debug, Default)]
+    struct Seen {
+        connects: Vec<Vec<u8>>,
+        received: Vec<Received>,
+        pings: usize,
+    }
+
+    /// Broker stand-in on a loopback port. It takes one connection per entry
+    /// of `connacks`, answering the CONNECT with that return code and closing
+    /// refused connections; accepted ones are served until DISCONNECT. The
+    /// thread returns every CONNECT body and publish it received.
+    fn broker(connacks: Vec<u8>) -> (String, thread::JoinHandle<Seen>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut seen = Seen::default();
+            for code in connacks {
+                let (mut stream, _) = listener.accept().unwrap();
+                let (header, body) = read_packet(&mut stream).unwrap();
+                assert_eq!(header, CONNECT);
+                let v5 = body[6] == 5;
+                seen.connects.push(body);
+                let connack: &[u8] = if v5 { &[0, code, 0] } else { &[0, code] };
+                stream.write_all(&packet_with(CONNACK, connack)).unwrap();
+                if code != 0 {
+                    continue;
+                }
+                while let Ok((header, body)) = read_packet(&mut stream) {
+                    match header & 0xf0 {
+                        PUBLISH => {
+                            let qos = (header >> 1) & 3;
+                            let topic_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                            let mut at = 2 + topic_len;
+                            let id = [body[at], body[at + 1]];
+                            if qos > 0 {
+                                at += 2;
+                            }
+                            if v5 {
+                                at += 1;
+                            }
+                            seen.received.push(Received {
+                                topic: String::from_utf8(body[2..2 + topic_len].to_vec()).unwrap(),
+                                qos,
+                                retain: header & 1 == 1,
+                                payload: body[at..].to_vec(),
+                            });
+                            match qos {
+                                1 => stream.write_all(&packet_with(PUBACK, &id)).unwrap(),
+                                2 => stream.write_all(&packet_with(PUBREC, &id)).unwrap(),
+                                _ => {}
+                            }
+                        }
+                        0x60 => stream.write_all(&packet_with(PUBCOMP, &body[..2])).unwrap(),
+                        PINGREQ => {
+                            seen.pings += 1;
+                            stream.write_all(&packet_with(PINGRESP, &[])).unwrap();
+                        }
+                        DISCONNECT => break,
+                        other => panic!("unexpected packet 0x{:02x}", other),
+                    }
+                }
+            }
+            seen
+        });
+        (address, handle)
+    }
+
+    fn publish_values(sink: &mut MqttSink, values: &[f64]) -> Vec<io::Result<()>> {
+        let channel = Channel::new("temperature", "°C");
+        (1..).zip(values).map(|(seq, &value)| {
+            let reading = channel.reading(value);
+            sink.publish(&[Record { seq, reading: &reading, timestamp: Timestamp::Wall(Duration::from_secs(1_770_656_082)) }])
+        }).collect()
+    }
+
+    #[test]
+    fn encodes_connect_with_will_and_credentials() {
+        let mut config = MqttConfig {
+            client_id: "c".to_string(),
+            username: Some("u".to_string()),
+            password: Some("p".to_string()),
+            will: Some(Will { topic: "s".to_string(), payload: "off".to_string(), qos: QoS::AtLeastOnce, retain: true }),
+            ..MqttConfig::default()
+        };
+        let will = config.will.clone();
+        let will = will.as_ref().map(LastWill::from);
+        assert_eq!(connect_packet(&config, will.as_ref()).unwrap(), [
+            0x10, 27,
+            0, 4, b'M', b'Q', b'T', b'T', 4, 0xee, 0, 60,
+            0, 1, b'c',
+            0, 1, b's', 0, 3, b'o', b'f', b'f',
+            0, 1, b'u', 0, 1, b'p',
+        ]);
+        config.version = MqttVersion::V5;
+        assert_eq!(connect_packet(&config, will.as_ref()).unwrap(), [
+            0x10, 29,
+            0, 4, b'M', b'Q', b'T', b'T', 5, 0xee, 0, 60, 0,
+            0, 1, b'c',
+            0, 0, 1, b's', 0, 3, b'o', b'f', b'f',
+            0, 1, b'u', 0, 1, b'p',
+        ]);
+    }
+
+    #[test]
+    fn encodes_long_remaining_lengths() {
+        assert_eq!(packet_with(PUBLISH, &[0; 321])[..3], [0x30, 0xc1, 0x02]);
+        let packet = packet_with(PUBLISH, &[7; 20_000]);
+        assert_eq!(read_packet(&mut &packet[..]).unwrap(), (PUBLISH, vec![7; 20_000]));
+    }
+
+    #[test]
+    fn rejects_fields_longer_than_mqtt_allows() {
+        let topic = "t".repeat(usize::from(u16::MAX));
+        assert!(publish_packet(MqttVersion::V311, &topic, b"", QoS::AtMostOnce, false, None).is_ok());
+        let topic = topic + "t";
+        let err = publish_packet(MqttVersion::V311, &topic, b"", QoS::AtMostOnce, false, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "field of 65536 bytes is longer than MQTT allows");
+        let config = MqttConfig { client_id: topic, ..MqttConfig::default() };
+        assert!(connect_packet(&config, None).is_err());
+    }
+
+    #[test]
+    fn publishes_at_every_qos() {
+        for version in [MqttVersion::V311, MqttVersion::V5] {
+            for qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce] {
+                let (broker_address, broker) = broker(vec![0]);
+                let config = MqttConfig { broker: broker_address, version, qos, retain: true, ..MqttConfig::default() };
+                let mut sink = MqttSink::new(config, None);
+                assert!(publish_values(&mut sink, &[23.0, 26.0]).iter().all(Result::is_ok));
+                sink.close().unwrap();
+
+                let Seen { connects, received, .. } = broker.join().unwrap();
+                assert_eq!(connects.len(), 1, "{} {:?}", version, qos);
+                assert_eq!(received.len(), 2, "{} {:?}", version, qos);
+                assert_eq!((received[0].topic.as_str(), received[0].qos, received[0].retain), ("sensors/temperature", qos as u8, true));
+                let payload = String::from_utf8_lossy(&received[1].payload);
+                assert!(payload.starts_with("{\"seq\":2,\"sensor\":\"temperature\",\"quantity\":\"temperature\",\"value\":26.0"), "{}", payload);
+                assert_eq!(sink.to_string(), format!("mqtt {}: 2 published", sink.client.config.broker));
+            }
+        }
+    }
+
+    #[test]
+    fn publishes_the_sparkplug_lifecycle() {
+        let (address, broker) = broker(vec![0]);
+        let sparkplug = SparkplugConfig { group_id: "plant1".to_string(), edge_node_id: "lab1".to_string() };
+        // The configured QoS does not apply to Sparkplug messages.
+        let config = MqttConfig { broker: address, qos: QoS::ExactlyOnce, sparkplug: Some(sparkplug), ..MqttConfig::default() };
+        let mut sink = MqttSink::new(config, None);
+        assert!(publish_values(&mut sink, &[23.0, 26.0]).iter().all(Result::is_ok));
+        sink.close().unwrap();
+
+        let Seen { connects, received, .. } = broker.join().unwrap();
+        // A will at QoS 1, not retained: the NDEATH.
+        assert_eq!(connects[0][7] & 0x3c, 0x0c);
+        let will_topic = b"spBv1.0/plant1/NDEATH/lab1";
+        assert!(connects[0].windows(will_topic.len()).any(|window| window == will_topic));
+        // Topic, QoS and the last byte, which is seq for NBIRTH and NDATA
+        // and bdSeq for NDEATH.
+        let messages: Vec<_> = received.iter()
+            .map(|message| (message.topic.as_str(), message.qos, *message.payload.last().unwrap()))
+            .collect();
+        assert_eq!(messages, [
+            ("spBv1.0/plant1/NBIRTH/lab1", 0, 0),
+            ("spBv1.0/plant1/NDATA/lab1", 0, 1),
+            ("spBv1.0/plant1/NDATA/lab1", 0, 2),
+            ("spBv1.0/plant1/NDEATH/lab1", 1, 0),
+        ]);
+    }
+
+    #[test]
+    fn pings_an_idle_broker() {
+        let (address, broker) = broker(vec![0]);
+        let config = MqttConfig { broker: address, keep_alive_secs: 1, ..MqttConfig::default() };
+        let mut sink = MqttSink::new(config, None);
+        assert!(publish_values(&mut sink, &[23.0])[0].is_ok());
+        // Not idle for long enough yet.
+        sink.publish(&[]).unwrap();
+        thread::sleep(Duration::from_millis(600));
+        sink.publish(&[]).unwrap();
+        sink.publish(&[]).unwrap();
+        sink.close().unwrap();
+
+        let Seen { connects, received, pings } = broker.join().unwrap();
+        assert_eq!((connects.len(), received.len(), pings), (1, 1, 1));
+    }
+
+    #[test]
+    fn renders_topic_templates() {
+        let mut reading = Channel::new("bme280.humidity", "%").reading(41.0);
+        reading.tags.insert("site".to_string(), "lab/1".to_string());
+        let cases = [
+            ("sensors/{sensor}", "sensors/bme280.humidity"),
+            ("sites/{site}/{quantity}/{unit}", "sites/lab_1/humidity/%"),
+            ("{missing}/x", "/x"),
+            ("open/{sensor", "open/{sensor"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(render_topic(template, &reading), expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn spools_until_the_broker_accepts() {
+        let dir = tempfile::tempdir().unwrap();
+        // The first connection is refused as "server unavailable".
+        let (address, broker) = broker(vec![3, 0]);
+        let config = MqttConfig { broker: address, qos: QoS::AtLeastOnce, ..MqttConfig::default() };
+        let spool = Spool::open(dir.path(), 1024 * 1024, DropPolicy::Oldest).unwrap();
+        let mut sink = MqttSink::new(config, Some(spool));
+
+        let results = publish_values(&mut sink, &[23.0, 26.0]);
+        let err = results[0].as_ref().unwrap_err();
+        assert_eq!(err.to_string(), "broker refused the connection: server unavailable (0x03)");
+        assert!(results[1].is_ok());
+        sink.close().unwrap();
+
+        let received = broker.join().unwrap().received;
+        let seqs: Vec<_> = received.iter().map(|message| &message.payload[..8]).collect();
+        assert_eq!(seqs, [b"{\"seq\":1", b"{\"seq\":2"]);
+        assert!(sink.to_string().ends_with(": 2 published, 0 queued, 0 dropped"), "{}", sink);
+    }
+
+    #[test]
+    fn counts_messages_lost_without_a_spool() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let mut sink = MqttSink::new(MqttConfig { broker: address, ..MqttConfig::default() }, None);
+        assert!(publish_values(&mut sink, &[23.0])[0].is_err());
+        assert!(sink.to_string().ends_with(": 0 published, 1 lost"), "{}", sink);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let cases = [
+            ("qos = 3", "qos must be 0, 1 or 2, not 3"),
+            ("topic = \"sensors/#\"", "'sensors/#' is not a topic to publish to"),
+            ("will = { topic = \"+/status\", payload = \"offline\" }", "'+/status' is not a topic to publish to"),
+            ("version = \"4\"", "unknown variant `4`"),
+            ("port = 1883", "unknown field `port`"),
+        ];
+        for (toml, expected) in cases {
+            let err = toml::from_str::<MqttConfig>(toml).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", toml, err);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Payload encodings for constrained links: SenML packs (RFC 8428) as JSON
+//! or CBOR, and the JSON output's objects as CBOR (RFC 8949).
+//!
+//! A SenML pack holds one record per reading: the sensor name, the unit and
+//! the value, with the base name and base time on the first record. Units
+//! with a SenML name are renamed (`°C` is `Cel`, `%` humidity is `%RH`);
+//! others pass through as they are. SenML has no notion of quality, so it is
+//! not sent, and no way to write NaN or an infinity, so such readings are
+//! left out of the pack.
+
+use std::io;
+
+use serde::Serialize;
+
+use crate::clock::Timestamp;
+use crate::output::{self, Record};
+use crate::reading::Quantity;
+
+// SenML CBOR labels, RFC 8428 section 6.
+const BASE_NAME: i64 = -2;
+const BASE_TIME: i64 = -3;
+const NAME: i64 = 0;
+const UNIT: i64 = 1;
+const VALUE: i64 = 2;
+const TIME: i64 = 6;
+
+/// One SenML record; the JSON labels are the field names.
+#[derive(Serialize)]
+struct Senml<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bn: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bt: Option<f64>,
+    n: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    u: Option<&'a str>,
+    v: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    t: Option<f64>,
+}
+
+/// The pack for the finite readings in `records`. Times are seconds since
+/// the epoch, relative to the base time; readings without a wall clock have
+/// none, which SenML reads as "now".
+fn pack<'a>(base_name: Option<&'a str>, records: &[Record<'a>]) -> Vec<Senml<'a>> {
+    let seconds = |timestamp| match timestamp {
+        Timestamp::Wall(since_epoch) => Some(since_epoch.as_millis() as f64 / 1000.0),
+        Timestamp::Monotonic(_) | Timestamp::Unsynced => None,
+    };
+    let records: Vec<&Record> = records.iter().filter(|record| record.reading.value.is_finite()).collect();
+    let base_time = records.first().and_then(|record| seconds(record.timestamp));
+    records.iter().enumerate().map(|(i, record)| {
+        let reading = record.reading;
+        let unit = match (reading.unit.as_str(), reading.quantity) {
+            ("", _) => None,
+            ("°C", _) => Some("Cel"),
+            ("%", Quantity::Humidity) => Some("%RH"),
+            (unit, _) => Some(unit),
+        };
+        let time = match (seconds(record.timestamp), base_time) {
+            (Some(time), Some(base)) if time != base => Some(time - base),
+            (Some(time), None) => Some(time),
+            _ => None,
+        };
+        Senml {
+            bn: base_name.filter(|_| i == 0),
+            bt: base_time.filter(|_| i == 0),
+            n: &reading.sensor,
+            u: unit,
+            v: reading.value,
+            t: time,
+        }
+    }).collect()
+}
+
+/// `application/senml+json`
+pub fn senml_json(base_name: Option<&str>, records: &[Record]) -> io::Result<Vec<u8>> {
+    serde_json::to_vec(&pack(base_name, records)).map_err(io::Error::from)
+}
+
+/// `application/senml+cbor`
+pub fn senml_cbor(base_name: Option<&str>, records: &[Record]) -> Vec<u8> {
+    let pack = pack(base_name, records);
+    let mut cbor = Cbor::default();
+    cbor.head(4, pack.len() as u64);
+    for record in &pack {
+        let labels = [record.bn.is_some(), record.bt.is_some(), true, record.u.is_some(), true, record.t.is_some()];
+        cbor.head(5, labels.iter().filter(|&&present| present).count() as u64);
+        if let Some(base_name) = record.bn {
+            cbor.int(BASE_NAME);
+            cbor.text(base_name);
+        }
+        if let Some(base_time) = record.bt {
+            cbor.int(BASE_TIME);
+            cbor.float(base_time);
+        }
+        cbor.int(NAME);
+        cbor.text(record.n);
+        if let Some(unit) = record.u {
+            cbor.int(UNIT);
+            cbor.text(unit);
+        }
+        cbor.int(VALUE);
+        cbor.float(record.v);
+        if let Some(time) = record.t {
+            cbor.int(TIME);
+            cbor.float(time);
+        }
+    }
+    cbor.0
+}
+
+/// `application/cbor`: an array of the objects `--format json` writes.
+pub fn cbor(records: &[Record]) -> io::Result<Vec<u8>> {
+    let mut cbor = Cbor::default();
+    cbor.head(4, records.len() as u64);
+    for record in records {
+        cbor.value(&output::json_value(record)?);
+    }
+    Ok(cbor.0)
+}
+
+/// Just enough of a CBOR encoder: definite lengths, and floats in the
+/// shortest of single or double precision that keeps the value.
+#[derive(Default)]
+struct Cbor(Vec<u8>);
+
+impl Cbor {
+    /// Major type and argument in the fewest bytes.
+    fn head(&mut self, major: u8, argument: u64) {
+        let major = major << 5;
+        match argument {
+            0..=23 => self.0.push(major | argument as u8),
+            24..=0xff => self.0.extend_from_slice(&[major | 24, argument as u8]),
+            0x100..=0xffff => {
+                self.0.push(major | 25);
+                self.0.extend_from_slice(&(argument as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.0.push(major | 26);
+                self.0.extend_from_slice(&(argument as u32).to_be_bytes());
+            }
+            _ => {
+                self.0.push(major | 27);
+                self.0.extend_from_slice(&argument.to_be_bytes());
+            }
+        }
+    }
+
+    fn int(&mut self, value: i64) {
+        if value < 0 {
+            self.head(1, (-1 - value) as u64);
+        } else {
+            self.head(0, value as u64);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        self.head(3, text.len() as u64);
+        self.0.extend_from_slice(text.as_bytes());
+    }
+
+    fn float(&mut self, value: f64) {
+        let single = value as f32;
+        if f64::from(single) == value || value.is_nan() {
+            self.0.push(0xfa);
+            self.0.extend_from_slice(&single.to_be_bytes());
+        } else {
+            self.0.push(0xfb);
+            self.0.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    fn value(&mut self, value: &serde_json::Value) {
+        match value {
+            serde_json::Value::Null => self.0.push(0xf6),
+            serde_json::Value::Bool(value) => self.0.push(if *value { 0xf5 } else { 0xf4 }),
+            serde_json::Value::Number(number) => match (number.as_u64(), number.as_i64(), number.as_f64()) {
+                (Some(value), _, _) => self.head(0, value),
+                (None, Some(value), _) => self.int(value),
+                (None, None, value) => self.float(value.unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(text) => self.text(text),
+            serde_json::Value::Array(items) => {
+                self.head(4, items.len() as u64);
+                for item in items {
+                    self.value(item);
+                }
+            }
+            serde_json::Value::Object(entries) => {
+                self.head(5, entries.len() as u64);
+                for (key, value) in entries {
+                    self.text(key);
+                    self.value(value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::reading::{Channel, Reading};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn readings() -> Vec<Reading> {
+        vec![Channel::new("temperature", "°C").reading(21.5), Channel::new("humidity", "%").reading(40.1)]
+    }
+
+    fn records(readings: &[Reading]) -> Vec<Record<'_>> {
+        (0..).zip(readings)
+            .map(|(i, reading)| Record { seq: 1, reading, timestamp: Timestamp::Wall(Duration::from_millis(1_770_656_082_000 + 500 * i)) })
+            .collect()
+    }
+
+    #[test]
+    fn encodes_senml_packs() {
+        let readings = readings();
+        let records = records(&readings);
+        let json = senml_json(Some("urn:dev:lab1:"), &records).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), concat!(
+            r#"[{"bn":"urn:dev:lab1:","bt":1770656082.0,"n":"temperature","u":"Cel","v":21.5},"#,
+            r#"{"n":"humidity","u":"%RH","v":40.1,"t":0.5}]"#,
+        ));
+        // Checked against `serde_cbor` decoding the same pack.
+        assert_eq!(hex(&senml_cbor(Some("urn:dev:lab1:"), &records)), concat!(
+            "82",
+            "a5", "21", "6d75726e3a6465763a6c6162313a", "22", "fb41da628454800000",
+            "00", "6b74656d7065726174757265", "01", "6343656c", "02", "fa41ac0000",
+            "a4", "00", "6868756d6964697479", "01", "63255248", "02", "fb40440ccccccccccd", "06", "fa3f000000",
+        ));
+    }
+
+    #[test]
+    fn leaves_non_finite_values_out_of_packs() {
+        let mut readings = readings();
+        readings.insert(0, Channel::new("pressure", "hPa").reading(f64::NAN));
+        readings.push(Channel::new("voltage", "V").reading(f64::INFINITY));
+        let records = records(&readings);
+        let json = senml_json(Some("urn:dev:lab1:"), &records).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), concat!(
+            r#"[{"bn":"urn:dev:lab1:","bt":1770656082.5,"n":"temperature","u":"Cel","v":21.5},"#,
+            r#"{"n":"humidity","u":"%RH","v":40.1,"t":0.5}]"#,
+        ));
+        assert_eq!(&senml_cbor(None, &records)[..2], [0x82, 0xa4]);
+    }
+
+    #[test]
+    fn encodes_json_objects_as_cbor() {
+        let reading = Channel::new("battery", "V").reading(-3.0);
+        let record = Record { seq: 300, reading: &reading, timestamp: Timestamp::Monotonic(Duration::from_millis(70_000)) };
+        // Keys sorted, as serde_json orders them.
+        assert_eq!(hex(&cbor(&[record]).unwrap()), concat!(
+            "81", "a8",
+            "65636c6f636b", "696d6f6e6f746f6e6963",
+            "677175616c697479", "64676f6f64",
+            "687175616e74697479", "67766f6c74616765",
+            "6673656e736f72", "6762617474657279",
+            "63736571", "19012c",
+            "64756e6974", "6156",
+            "69757074696d655f6d73", "1a00011170",
+            "6576616c7565", "fac0400000",
+        ));
+    }
+}
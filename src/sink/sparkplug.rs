@@ -0,0 +1,395 @@
+//! Eclipse Sparkplug B payloads for the MQTT sink: protobuf encoding of the
+//! `Payload` message and the edge node lifecycle around it.
+//!
+//! Every MQTT session gets a new `bdSeq`, registered with the broker in the
+//! NDEATH will, and starts with an NBIRTH that lists every metric at `seq` 0.
+//! NDATA messages follow with `seq` counting up to 255 and wrapping. A metric
+//! not in the last NBIRTH forces a new one. Readings become Double metrics
+//! named after their sensor, with the unit as the `engUnit` property and, when
+//! not good, an OPC `Quality` code as Ignition reads it. Only the parts of the
+//! schema this needs are encoded, and NCMD rebirth requests are not
+//! subscribed to.
+
+use serde::{Deserialize, Deserializer};
+
+use crate::clock::Timestamp;
+use crate::output::Record;
+use crate::reading::Quality;
+
+const NAMESPACE: &str = "spBv1.0";
+
+// Metric and property data types from the Sparkplug B schema.
+const INT32: u64 = 3;
+const INT64: u64 = 4;
+const DOUBLE: u64 = 10;
+const BOOLEAN: u64 = 11;
+const STRING: u64 = 12;
+
+/// The `sparkplug` key of the `[mqtt]` table; its presence switches the sink
+/// from JSON to Sparkplug B.
+///
+/// ```toml
+/// [mqtt]
+/// sparkplug = { group_id = "plant1", edge_node_id = "lab1-gateway" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SparkplugConfig {
+    #[serde(deserialize_with = "id")]
+    pub group_id: String,
+    #[serde(deserialize_with = "id")]
+    pub edge_node_id: String,
+}
+
+/// A group or edge node ID, which becomes one level of the topic.
+fn id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    if id.is_empty() || id.contains(['/', '+', '#', '\0']) {
+        return Err(serde::de::Error::custom(format!("'{}' is not a valid Sparkplug ID", id)));
+    }
+    Ok(id)
+}
+
+/// The value of a metric or property, tagged with its data type.
+enum Value<'a> {
+    Int32(i32),
+    Int64(i64),
+    Double(f64),
+    Boolean(bool),
+    String(&'a str),
+}
+
+impl Value<'_> {
+    fn datatype(&self) -> u64 {
+        match self {
+            Value::Int32(_) => INT32,
+            Value::Int64(_) => INT64,
+            Value::Double(_) => DOUBLE,
+            Value::Boolean(_) => BOOLEAN,
+            Value::String(_) => STRING,
+        }
+    }
+
+    /// Write the value into its `oneof` field; `first` is the field number
+    /// of `int_value`, which the others follow in the same order in both
+    /// `Metric` and `PropertyValue`.
+    fn encode(&self, buf: &mut Vec<u8>, first: u32) {
+        match *self {
+            // Signed types travel as their two's complement bit pattern.
+            Value::Int32(value) => uint(buf, first, u64::from(value as u32)),
+            Value::Int64(value) => uint(buf, first + 1, value as u64),
+            Value::Double(value) => double(buf, first + 3, value),
+            Value::Boolean(value) => uint(buf, first + 4, u64::from(value)),
+            Value::String(value) => bytes(buf, first + 5, value.as_bytes()),
+        }
+    }
+}
+
+/// One entry of a payload's `metrics`.
+struct Metric<'a> {
+    name: &'a str,
+    timestamp: Option<u64>,
+    value: Value<'a>,
+    properties: Vec<(&'a str, Value<'a>)>,
+}
+
+impl Metric<'_> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mut metric = Vec::new();
+        bytes(&mut metric, 1, self.name.as_bytes());
+        if let Some(timestamp) = self.timestamp {
+            uint(&mut metric, 3, timestamp);
+        }
+        uint(&mut metric, 4, self.value.datatype());
+        if !self.properties.is_empty() {
+            // PropertySet: every key, then every value, in the same order.
+            let mut set = Vec::new();
+            for (key, _) in &self.properties {
+                bytes(&mut set, 1, key.as_bytes());
+            }
+            for (_, value) in &self.properties {
+                let mut property = Vec::new();
+                uint(&mut property, 1, value.datatype());
+                value.encode(&mut property, 3);
+                bytes(&mut set, 2, &property);
+            }
+            bytes(&mut metric, 9, &set);
+        }
+        self.value.encode(&mut metric, 10);
+        bytes(buf, 2, &metric);
+    }
+}
+
+/// A `Payload` with the given timestamp and metrics, without `seq`.
+fn payload(timestamp: Option<u64>, metrics: &[Metric]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(timestamp) = timestamp {
+        uint(&mut buf, 1, timestamp);
+    }
+    for metric in metrics {
+        metric.encode(&mut buf);
+    }
+    buf
+}
+
+fn key(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(buf, u64::from(field) << 3 | u64::from(wire_type));
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    key(buf, field, 0);
+    varint(buf, value);
+}
+
+fn double(buf: &mut Vec<u8>, field: u32, value: f64) {
+    key(buf, field, 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn bytes(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    key(buf, field, 2);
+    varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// OPC quality code of a reading.
+fn quality_code(quality: Quality) -> i32 {
+    match quality {
+        Quality::Good => 192,
+        Quality::Uncertain => 64,
+        Quality::Bad => 0,
+    }
+}
+
+fn millis(timestamp: Timestamp) -> Option<u64> {
+    match timestamp {
+        Timestamp::Wall(since_epoch) => Some(since_epoch.as_millis() as u64),
+        // Sparkplug timestamps are UTC; without a wall clock there is none.
+        Timestamp::Monotonic(_) | Timestamp::Unsynced => None,
+    }
+}
+
+/// The last value of a metric, for the next NBIRTH.
+struct Latest {
+    name: String,
+    unit: String,
+    value: f64,
+    quality: Quality,
+    timestamp: Option<u64>,
+}
+
+/// Lifecycle of one Sparkplug edge node across MQTT sessions.
+pub struct Node {
+    config: SparkplugConfig,
+    /// Birth/death sequence of the current session, once there is one.
+    bd_seq: Option<u8>,
+    seq: u8,
+    /// Whether the current session's NBIRTH lists every metric.
+    born: bool,
+    metrics: Vec<Latest>,
+}
+
+impl Node {
+    pub fn new(config: SparkplugConfig) -> Self {
+        Node { config, bd_seq: None, seq: 0, born: false, metrics: Vec::new() }
+    }
+
+    /// Topic of a message type, e.g. `spBv1.0/plant1/NDATA/lab1-gateway`.
+    pub fn topic(&self, message_type: &str) -> String {
+        format!("{}/{}/{}/{}", NAMESPACE, self.config.group_id, message_type, self.config.edge_node_id)
+    }
+
+    /// Start a new session and return the NDEATH to register as its will.
+    pub fn session(&mut self) -> Vec<u8> {
+        self.bd_seq = Some(self.bd_seq.map_or(0, |bd_seq| bd_seq.wrapping_add(1)));
+        self.born = false;
+        self.death()
+    }
+
+    /// NDEATH of the current session.
+    pub fn death(&self) -> Vec<u8> {
+        payload(None, &[self.bd_seq_metric(None)])
+    }
+
+    /// Whether an NBIRTH has to go out before the next NDATA.
+    pub fn needs_birth(&self) -> bool {
+        !self.born
+    }
+
+    /// NBIRTH with the last value of every metric, complete with `seq` 0.
+    pub fn birth(&mut self) -> Vec<u8> {
+        let timestamp = self.metrics.iter().filter_map(|latest| latest.timestamp).max();
+        let mut metrics = vec![
+            self.bd_seq_metric(timestamp),
+            Metric { name: "Node Control/Rebirth", timestamp, value: Value::Boolean(false), properties: Vec::new() },
+        ];
+        metrics.extend(self.metrics.iter().map(|latest| {
+            let mut properties = Vec::new();
+            if !latest.unit.is_empty() {
+                properties.push(("engUnit", Value::String(&latest.unit)));
+            }
+            if latest.quality != Quality::Good {
+                properties.push(("Quality", Value::Int32(quality_code(latest.quality))));
+            }
+            Metric { name: &latest.name, timestamp: latest.timestamp, value: Value::Double(latest.value), properties }
+        }));
+        let mut birth = payload(timestamp, &metrics);
+        uint(&mut birth, 3, 0);
+        self.seq = 0;
+        self.born = true;
+        birth
+    }
+
+    /// NDATA for one iteration's readings, without `seq`: that is added by
+    /// [`Node::stamp`] when it is sent, so spooled messages fit the session
+    /// they are eventually sent in.
+    pub fn data(&mut self, records: &[Record]) -> Vec<u8> {
+        let mut metrics = Vec::with_capacity(records.len());
+        for record in records {
+            let reading = record.reading;
+            let timestamp = millis(record.timestamp);
+            let latest = Latest {
+                name: reading.sensor.clone(),
+                unit: reading.unit.clone(),
+                value: reading.value,
+                quality: reading.quality,
+                timestamp,
+            };
+            match self.metrics.iter_mut().find(|known| known.name == reading.sensor) {
+                Some(known) => *known = latest,
+                None => {
+                    self.metrics.push(latest);
+                    self.born = false;
+                }
+            }
+            let mut properties = Vec::new();
+            if reading.quality != Quality::Good {
+                properties.push(("Quality", Value::Int32(quality_code(reading.quality))));
+            }
+            metrics.push(Metric { name: &reading.sensor, timestamp, value: Value::Double(reading.value), properties });
+        }
+        let timestamp = records.iter().filter_map(|record| millis(record.timestamp)).max();
+        payload(timestamp, &metrics)
+    }
+
+    /// An NDATA payload from [`Node::data`] with the session's next `seq`.
+    /// `seq` is field 3, after the metrics, so appending it keeps the fields
+    /// in order.
+    pub fn stamp(&mut self, data: &[u8]) -> Vec<u8> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut payload = Vec::with_capacity(data.len() + 2);
+        payload.extend_from_slice(data);
+        uint(&mut payload, 3, u64::from(self.seq));
+        payload
+    }
+
+    fn bd_seq_metric(&self, timestamp: Option<u64>) -> Metric<'static> {
+        let bd_seq = self.bd_seq.unwrap_or(0);
+        Metric { name: "bdSeq", timestamp, value: Value::Int64(i64::from(bd_seq)), properties: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::reading::{Channel, Reading};
+
+    /// Reference payloads, encoded by `prost` from the Eclipse Tahu
+    /// `sparkplug_b.proto` with the same field values; one line per field of
+    /// the `Payload`.
+    const NDEATH_BD_SEQ_0: &str = "120b0a05626453657120045800";
+    const NBIRTH: &str = concat!(
+        "08d0d0ce9ac433",
+        "12120a05626453657118d0d0ce9ac43320045800",
+        "12210a144e6f646520436f6e74726f6c2f5265626972746818d0d0ce9ac433200b7000",
+        "12330a0b74656d706572617475726518d0d0ce9ac433200a4a120a07656e67556e69741207080c4203c2b043690000000000803540",
+        "123d0a0868756d696469747918d0d0ce9ac433200a4a1f0a07656e67556e69740a075175616c6974791205080c4201251204080318",
+        "0069000000000000f0bf",
+        "1800",
+    );
+    const NDATA_SEQ_1: &str = concat!(
+        "08d0d0ce9ac433",
+        "121f0a0b74656d706572617475726518d0d0ce9ac433200a690000000000803540",
+        "122d0a0868756d696469747918d0d0ce9ac433200a4a0f0a075175616c69747912040803180069000000000000f0bf",
+        "1801",
+    );
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn node() -> Node {
+        Node::new(SparkplugConfig { group_id: "plant1".to_string(), edge_node_id: "lab1".to_string() })
+    }
+
+    fn records(readings: &[Reading]) -> Vec<Record<'_>> {
+        readings.iter()
+            .map(|reading| Record { seq: 1, reading, timestamp: Timestamp::Wall(Duration::from_millis(1_770_656_082_000)) })
+            .collect()
+    }
+
+    fn readings() -> Vec<Reading> {
+        let bad = Reading { quality: Quality::Bad, ..Channel::new("humidity", "%").reading(-1.0) };
+        vec![Channel::new("temperature", "°C").reading(21.5), bad]
+    }
+
+    #[test]
+    fn encodes_reference_payloads() {
+        let mut node = node();
+        assert_eq!(hex(&node.session()), NDEATH_BD_SEQ_0);
+        let readings = readings();
+        let data = node.data(&records(&readings));
+        assert_eq!(hex(&node.birth()), NBIRTH);
+        assert_eq!(hex(&node.stamp(&data)), NDATA_SEQ_1);
+    }
+
+    #[test]
+    fn counts_sessions_and_messages() {
+        let mut node = node();
+        let readings = readings();
+        let first = node.data(&records(&readings[..1]));
+        node.session();
+        assert!(node.needs_birth());
+        node.birth();
+        assert!(!node.needs_birth());
+        // seq runs 1..=255, then wraps to 0.
+        let seqs: Vec<_> = (0..257).map(|_| {
+            node.stamp(&first);
+            node.seq
+        }).collect();
+        assert_eq!((seqs[0], seqs[254], seqs[255], seqs[256]), (1, 255, 0, 1));
+        assert!(node.stamp(&first).ends_with(&[0x18, 2]));
+
+        // A metric the NBIRTH did not list calls for a new one, at seq 0.
+        node.data(&records(&readings));
+        assert!(node.needs_birth());
+        assert!(node.birth().ends_with(&[0x18, 0]));
+
+        // Every session takes the next bdSeq, wrapping after 255.
+        for _ in 0..255 {
+            node.session();
+        }
+        assert_eq!(node.bd_seq, Some(255));
+        assert_eq!(hex(&node.session()), NDEATH_BD_SEQ_0);
+        assert_eq!(node.topic("NDEATH"), "spBv1.0/plant1/NDEATH/lab1");
+    }
+
+    #[test]
+    fn rejects_ids_that_break_the_topic() {
+        for id in ["", "lab/1", "lab+", "#"] {
+            let toml = format!("group_id = \"plant1\"\nedge_node_id = \"{}\"", id);
+            let err = toml::from_str::<SparkplugConfig>(&toml).unwrap_err();
+            assert!(err.to_string().contains(&format!("'{}' is not a valid Sparkplug ID", id)), "{}", err);
+        }
+    }
+}
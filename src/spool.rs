@@ -0,0 +1,392 @@
+//! Store-and-forward: an append-only log of messages an upstream sink could
+//! not deliver, kept in a preopened directory so it survives restarts.
+//!
+//! The log is a run of segment files (`00000000000000000001.seg`, ...), each
+//! a sequence of length-prefixed messages. A `cursor` file records the oldest
+//! unsent message; segments behind it are deleted. Messages are replayed in
+//! the order they were queued, at least once: a crash between a send and the
+//! cursor update sends that message again.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+const CURSOR: &str = "cursor";
+const SEGMENT_EXTENSION: &str = "seg";
+/// Bytes of the length prefix in front of every message.
+const HEADER: u64 = 4;
+
+/// Which messages give way when the spool is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DropPolicy {
+    /// Discard the oldest unsent messages to make room.
+    #[default]
+    Oldest,
+    /// Keep the backlog and discard new messages.
+    Newest,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(DropPolicy::Oldest),
+            "newest" => Ok(DropPolicy::Newest),
+            _ => Err(format!("unknown drop policy '{}', expected one of: oldest, newest", s)),
+        }
+    }
+}
+
+impl fmt::Display for DropPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DropPolicy::Oldest => "oldest",
+            DropPolicy::Newest => "newest",
+        })
+    }
+}
+
+/// A bounded on-disk queue of unsent messages.
+pub struct Spool {
+    dir: PathBuf,
+    /// Most unsent bytes, length prefixes included, kept on disk.
+    capacity: u64,
+    /// Size at which a new segment is started.
+    segment_bytes: u64,
+    policy: DropPolicy,
+    /// Segment ids and sizes, oldest first.
+    segments: VecDeque<(u64, u64)>,
+    /// Id for the next segment.
+    next_id: u64,
+    /// Position of the oldest unsent message in the first segment.
+    offset: u64,
+    /// Cursor as last written to disk.
+    saved: (u64, u64),
+    pending: usize,
+    dropped: u64,
+}
+
+impl Spool {
+    /// Open the spool in `dir`, creating it if needed, and pick up whatever a
+    /// previous run left unsent. A message cut short by a crash is discarded.
+    pub fn open(dir: impl Into<PathBuf>, capacity: u64, policy: DropPolicy) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let (cursor_id, cursor_offset) = read_cursor(&dir.join(CURSOR))?;
+
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == SEGMENT_EXTENSION) {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+
+        let mut spool = Spool {
+            capacity,
+            segment_bytes: (capacity / 8).max(1),
+            policy,
+            segments: VecDeque::new(),
+            next_id: cursor_id.max(ids.last().map_or(1, |last| last + 1)),
+            offset: 0,
+            saved: (cursor_id, cursor_offset),
+            pending: 0,
+            dropped: 0,
+            dir,
+        };
+        for id in ids {
+            let path = spool.segment_path(id);
+            if id < cursor_id {
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let size = fs::metadata(&path)?.len();
+            let start = if id == cursor_id { cursor_offset.min(size) } else { 0 };
+            let (messages, end) = scan(&path, start)?;
+            if end < size {
+                OpenOptions::new().write(true).open(&path)?.set_len(end)?;
+            }
+            if spool.segments.is_empty() {
+                spool.offset = start;
+            }
+            spool.segments.push_back((id, end));
+            spool.pending += messages;
+        }
+        spool.skip_consumed()?;
+        Ok(spool)
+    }
+
+    /// Number of messages waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// Bytes waiting to be sent, length prefixes included.
+    pub fn bytes(&self) -> u64 {
+        self.segments.iter().map(|&(_, size)| size).sum::<u64>() - self.offset
+    }
+
+    /// Messages discarded by the drop policy since the spool was opened.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Queue `message` behind the backlog, dropping per the policy if full.
+    pub fn push(&mut self, message: &[u8]) -> io::Result<()> {
+        let size = HEADER + message.len() as u64;
+        if size > self.capacity {
+            self.dropped += 1;
+            return Ok(());
+        }
+        while self.bytes() + size > self.capacity {
+            match self.policy {
+                DropPolicy::Newest => {
+                    self.dropped += 1;
+                    return Ok(());
+                }
+                DropPolicy::Oldest => {
+                    let len = self.front_len()?;
+                    self.advance(len)?;
+                    self.dropped += 1;
+                }
+            }
+        }
+        let id = match self.segments.back() {
+            Some(&(id, size)) if size < self.segment_bytes => id,
+            _ => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.segments.push_back((id, 0));
+                id
+            }
+        };
+        let mut frame = Vec::with_capacity(size as usize);
+        frame.extend_from_slice(&(message.len() as u32).to_le_bytes());
+        frame.extend_from_slice(message);
+        OpenOptions::new().create(true).append(true).open(self.segment_path(id))?.write_all(&frame)?;
+        if let Some((_, end)) = self.segments.back_mut() {
+            *end += size;
+        }
+        self.pending += 1;
+        self.save_cursor()
+    }
+
+    /// Send queued messages oldest first until `send` fails or the spool is
+    /// empty; returns how many were sent. The first error is returned and the
+    /// message it failed on stays queued.
+    pub fn forward(&mut self, mut send: impl FnMut(&[u8]) -> io::Result<()>) -> io::Result<usize> {
+        let mut sent = 0;
+        let result = loop {
+            if self.pending == 0 {
+                break Ok(sent);
+            }
+            let message = match self.front() {
+                Ok(message) => message,
+                Err(err) => break Err(err),
+            };
+            if let Err(err) = send(&message).and_then(|()| self.advance(message.len() as u64)) {
+                break Err(err);
+            }
+            sent += 1;
+        };
+        self.save_cursor()?;
+        result
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+    }
+
+    fn open_front(&self) -> io::Result<File> {
+        let (id, _) = self.segments.front().ok_or_else(|| io::Error::other("spool is empty"))?;
+        let mut file = File::open(self.segment_path(*id))?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(file)
+    }
+
+    fn front_len(&self) -> io::Result<u64> {
+        let mut header = [0; HEADER as usize];
+        self.open_front()?.read_exact(&mut header)?;
+        Ok(u32::from_le_bytes(header).into())
+    }
+
+    fn front(&self) -> io::Result<Vec<u8>> {
+        let mut file = self.open_front()?;
+        let mut header = [0; HEADER as usize];
+        file.read_exact(&mut header)?;
+        let mut message = vec![0; u32::from_le_bytes(header) as usize];
+        file.read_exact(&mut message)?;
+        Ok(message)
+    }
+
+    /// Move past the oldest message, `len` bytes long.
+    fn advance(&mut self, len: u64) -> io::Result<()> {
+        self.offset += HEADER + len;
+        self.pending -= 1;
+        self.skip_consumed()
+    }
+
+    /// Delete the segments that have been read to the end.
+    fn skip_consumed(&mut self) -> io::Result<()> {
+        while let Some(&(id, size)) = self.segments.front() {
+            if self.offset < size {
+                break;
+            }
+            fs::remove_file(self.segment_path(id))?;
+            self.segments.pop_front();
+            self.offset = 0;
+        }
+        Ok(())
+    }
+
+    fn save_cursor(&mut self) -> io::Result<()> {
+        let cursor = match self.segments.front() {
+            Some(&(id, _)) => (id, self.offset),
+            None => (self.next_id, 0),
+        };
+        if cursor == self.saved {
+            return Ok(());
+        }
+        // Write then rename so a crash leaves the old cursor or the new one.
+        let temporary = self.dir.join(format!("{}.tmp", CURSOR));
+        fs::write(&temporary, format!("{} {}\n", cursor.0, cursor.1))?;
+        fs::rename(&temporary, self.dir.join(CURSOR))?;
+        self.saved = cursor;
+        Ok(())
+    }
+}
+
+/// `(segment, offset)` of the oldest unsent message; the start of segment 1
+/// for a new spool.
+fn read_cursor(path: &Path) -> io::Result<(u64, u64)> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((1, 0)),
+        Err(err) => return Err(err),
+    };
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("{}: invalid spool cursor", path.display()));
+    let (id, offset) = text.trim().split_once(' ').ok_or_else(invalid)?;
+    Ok((id.parse().map_err(|_| invalid())?, offset.parse().map_err(|_| invalid())?))
+}
+
+/// Count the whole messages in a segment from `start`; returns the count and
+/// where the last whole message ends.
+fn scan(path: &Path, start: u64) -> io::Result<(usize, u64)> {
+    let size = fs::metadata(path)?.len();
+    let mut file = File::open(path)?;
+    let (mut messages, mut end) = (0, start);
+    let mut header = [0; HEADER as usize];
+    while end + HEADER <= size {
+        file.seek(SeekFrom::Start(end))?;
+        file.read_exact(&mut header)?;
+        let next = end + HEADER + u64::from(u32::from_le_bytes(header));
+        if next > size {
+            break;
+        }
+        messages += 1;
+        end = next;
+    }
+    Ok((messages, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forward everything and return it as strings.
+    fn drain(spool: &mut Spool) -> Vec<String> {
+        let mut sent = Vec::new();
+        spool.forward(|message| {
+            sent.push(String::from_utf8(message.to_vec()).unwrap());
+            Ok(())
+        }).unwrap();
+        sent
+    }
+
+    fn push_all(spool: &mut Spool, messages: &[&str]) {
+        for message in messages {
+            spool.push(message.as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_in_order_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1024, DropPolicy::Oldest).unwrap();
+        push_all(&mut spool, &["a", "b", "c", "d", "e"]);
+
+        // The sink goes away after two messages.
+        let mut budget = 2;
+        let err = spool.forward(|_| if budget == 0 { Err(io::ErrorKind::ConnectionRefused.into()) } else { budget -= 1; Ok(()) });
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(spool.len(), 3);
+        drop(spool);
+
+        let mut spool = Spool::open(dir.path(), 1024, DropPolicy::Oldest).unwrap();
+        assert_eq!(spool.len(), 3);
+        push_all(&mut spool, &["f"]);
+        assert_eq!(drain(&mut spool), ["c", "d", "e", "f"]);
+        assert!(spool.is_empty());
+        // Only the cursor is left once everything is sent.
+        let names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(names, [CURSOR]);
+    }
+
+    #[test]
+    fn drop_policies_keep_one_end() {
+        // Five-byte frames, room for three.
+        let cases = [(DropPolicy::Oldest, ["c", "d", "e"]), (DropPolicy::Newest, ["a", "b", "c"])];
+        for (policy, expected) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            let mut spool = Spool::open(dir.path(), 15, policy).unwrap();
+            push_all(&mut spool, &["a", "b", "c", "d", "e"]);
+            assert_eq!((spool.len(), spool.bytes(), spool.dropped()), (3, 15, 2), "{}", policy);
+            assert_eq!(drain(&mut Spool::open(dir.path(), 15, policy).unwrap()), expected, "{}", policy);
+        }
+    }
+
+    #[test]
+    fn drops_messages_larger_than_the_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 8, DropPolicy::Oldest).unwrap();
+        push_all(&mut spool, &["ok", "too long"]);
+        assert_eq!((spool.len(), spool.dropped()), (1, 1));
+    }
+
+    #[test]
+    fn discards_a_torn_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1024, DropPolicy::Oldest).unwrap();
+        push_all(&mut spool, &["first", "second"]);
+        drop(spool);
+        // A crash mid-write leaves a header promising more than was written.
+        let segment = dir.path().join(format!("{:020}.seg", 1));
+        OpenOptions::new().append(true).open(&segment).unwrap().write_all(&[9, 0, 0, 0, b't']).unwrap();
+
+        let mut spool = Spool::open(dir.path(), 1024, DropPolicy::Oldest).unwrap();
+        assert_eq!(spool.len(), 2);
+        push_all(&mut spool, &["third"]);
+        assert_eq!(drain(&mut spool), ["first", "second", "third"]);
+    }
+
+    #[test]
+    fn parses_drop_policies() {
+        assert_eq!("oldest".parse(), Ok(DropPolicy::Oldest));
+        assert_eq!("newest".parse(), Ok(DropPolicy::Newest));
+        assert!("latest".parse::<DropPolicy>().unwrap_err().contains("expected one of: oldest, newest"));
+    }
+}
@@ -0,0 +1,444 @@
+//! Embedded wasmtime runner for `wasm-hello.wasm`.
+//!
+//! Runs the module the way crun+WasmEdge does in the Quadlet demo and
+//! measures it from the inside: compile and instantiation time, wall time,
+//! fuel consumed and the linear memory high-water mark.
+//!
+//! Both builds are supported: the `wasm32-wasip1` core module is started
+//! through `_start`, the `wasm32-wasip2` component through `wasi:cli/run`.
+//! Components can also be driven through the typed `margo:sensor-reporter`
+//! interface with [`sample`].
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use wasmtime::component::{Component, ResourceTable};
+use wasmtime::{Config, Engine, Linker, Module, ResourceLimiter, Store};
+use wasmtime_wasi::bindings::sync::Command;
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, IoView, WasiCtx, WasiCtxBuilder, WasiView};
+
+wasmtime::component::bindgen!({
+    path: "../wit",
+    world: "sensor-reporter",
+    additional_derives: [serde::Serialize],
+});
+
+pub use exports::margo::sensor_reporter::reporter::{Config as ReporterConfig, Reading};
+pub use margo::sensor_reporter::types::{
+    ClockPolicy, ClockSource, Error as ReporterError, ReplayPace, ReplaySensor, SensorConfig, Signal, SimulatedSensor, ThermalSensor,
+};
+
+/// What to run and with which WASI capabilities.
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+    pub module: PathBuf,
+    /// Arguments after the program name.
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Preopened directories as (host path, guest path).
+    pub dirs: Vec<(PathBuf, String)>,
+    /// Fuel available to the guest; `None` means effectively unlimited.
+    pub fuel: Option<u64>,
+    /// Let the guest open sockets and resolve names (preview 2 only).
+    pub network: bool,
+}
+
+/// Resource usage of one run, serialized as the runner's JSON report.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub module: PathBuf,
+    /// `module` for a preview 1 core module, `component` for preview 2.
+    pub kind: &'static str,
+    /// Guest exit code; `None` if it trapped.
+    pub exit_code: Option<i32>,
+    /// Trap message if the guest did not exit normally.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trap: Option<String>,
+    pub module_bytes: u64,
+    pub compile_ms: f64,
+    pub instantiate_ms: f64,
+    /// Time spent running the guest.
+    pub run_ms: f64,
+    /// Compile, instantiation and run together.
+    pub wall_ms: f64,
+    pub fuel_consumed: u64,
+    /// Largest size any linear memory reached, including the initial size.
+    pub memory_peak_bytes: usize,
+}
+
+/// Store data for both module kinds.
+struct Host {
+    p1: Option<WasiP1Ctx>,
+    p2: Option<WasiCtx>,
+    table: ResourceTable,
+    memory: MemoryTracker,
+}
+
+impl IoView for Host {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiView for Host {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        self.p2.as_mut().expect("preview 2 context")
+    }
+}
+
+/// Records the linear memory high-water mark as the guest grows its memory.
+#[derive(Default)]
+struct MemoryTracker {
+    peak: usize,
+}
+
+impl ResourceLimiter for MemoryTracker {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        self.peak = self.peak.max(desired);
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: usize, _desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Timing and fuel bookkeeping shared by [`run`] and [`sample`].
+struct Session {
+    options: RunOptions,
+    engine: Engine,
+    store: Store<Host>,
+    bytes: Vec<u8>,
+    started: Instant,
+    compile: Duration,
+    fuel: u64,
+}
+
+impl Session {
+    fn new(options: &RunOptions) -> Result<Self> {
+        let started = Instant::now();
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let bytes = std::fs::read(&options.module)
+            .with_context(|| format!("reading {}", options.module.display()))?;
+
+        let host = Host { p1: None, p2: None, table: ResourceTable::new(), memory: MemoryTracker::default() };
+        let mut store = Store::new(&engine, host);
+        store.limiter(|host| &mut host.memory);
+        let fuel = options.fuel.unwrap_or(u64::MAX);
+        store.set_fuel(fuel)?;
+
+        Ok(Session { options: options.clone(), engine, store, bytes, started, compile: Duration::ZERO, fuel })
+    }
+
+    fn is_component(&self) -> bool {
+        // Core modules are version 1; components use a different version/layer field.
+        self.bytes.get(4..8).is_some_and(|version| version != [1, 0, 0, 0])
+    }
+
+    fn component(&mut self) -> Result<Component> {
+        let compile_started = Instant::now();
+        let component = Component::new(&self.engine, &self.bytes)?;
+        self.compile = compile_started.elapsed();
+        self.store.data_mut().p2 = Some(wasi_builder(&self.options)?.build());
+        Ok(component)
+    }
+
+    fn report(&self, kind: &'static str, exit_code: Option<i32>, trap: Option<String>,
+              instantiate: Duration, run: Duration) -> Result<Report> {
+        Ok(Report {
+            module: self.options.module.clone(),
+            kind,
+            exit_code,
+            trap,
+            module_bytes: self.bytes.len() as u64,
+            compile_ms: millis(self.compile),
+            instantiate_ms: millis(instantiate),
+            run_ms: millis(run),
+            wall_ms: millis(self.started.elapsed()),
+            fuel_consumed: self.fuel - self.store.get_fuel()?,
+            memory_peak_bytes: self.store.data().memory.peak,
+        })
+    }
+}
+
+/// Compile, instantiate and run the module, then report what it used.
+///
+/// Guest stdio is inherited, so the module's output appears as it would
+/// under podman.
+pub fn run(options: &RunOptions) -> Result<Report> {
+    let session = Session::new(options)?;
+    if session.is_component() {
+        run_component(session)
+    } else {
+        run_module(session)
+    }
+}
+
+fn run_module(mut session: Session) -> Result<Report> {
+    let compile_started = Instant::now();
+    let module = Module::new(&session.engine, &session.bytes)?;
+    session.compile = compile_started.elapsed();
+
+    let mut linker: Linker<Host> = Linker::new(&session.engine);
+    preview1::add_to_linker_sync(&mut linker, |host| host.p1.as_mut().expect("preview 1 context"))?;
+    session.store.data_mut().p1 = Some(wasi_builder(&session.options)?.build_p1());
+
+    let instantiate_started = Instant::now();
+    let instance = linker.instantiate(&mut session.store, &module)?;
+    let instantiate = instantiate_started.elapsed();
+
+    let start = instance.get_typed_func::<(), ()>(&mut session.store, "_start")?;
+    let run_started = Instant::now();
+    let (exit_code, trap) = exit_status(start.call(&mut session.store, ()).map(|()| Ok(())));
+    session.report("module", exit_code, trap, instantiate, run_started.elapsed())
+}
+
+fn run_component(mut session: Session) -> Result<Report> {
+    let component = session.component()?;
+    let mut linker = wasmtime::component::Linker::new(&session.engine);
+    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+
+    let instantiate_started = Instant::now();
+    let command = Command::instantiate(&mut session.store, &component, &linker)?;
+    let instantiate = instantiate_started.elapsed();
+
+    let run_started = Instant::now();
+    let (exit_code, trap) = exit_status(command.wasi_cli_run().call_run(&mut session.store));
+    session.report("component", exit_code, trap, instantiate, run_started.elapsed())
+}
+
+/// Drive a `wasm32-wasip2` component through `margo:sensor-reporter`:
+/// configure it, then call `sample` `count` times without waiting.
+pub fn sample(options: &RunOptions, config: &ReporterConfig, count: u64) -> Result<(Vec<Reading>, Report)> {
+    let mut session = Session::new(options)?;
+    if !session.is_component() {
+        return Err(anyhow!("{} is not a component; build it for wasm32-wasip2", options.module.display()));
+    }
+    let component = session.component()?;
+    let mut linker = wasmtime::component::Linker::new(&session.engine);
+    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+
+    let instantiate_started = Instant::now();
+    let bindings = SensorReporter::instantiate(&mut session.store, &component, &linker)?;
+    let instantiate = instantiate_started.elapsed();
+
+    let run_started = Instant::now();
+    let reporter = bindings.margo_sensor_reporter_reporter();
+    // The guest's error stays downcastable to [`ReporterError`].
+    reporter.call_configure(&mut session.store, config)?
+        .map_err(|err| anyhow::Error::new(err).context("configure"))?;
+    let mut readings = Vec::new();
+    for _ in 0..count {
+        let batch = reporter.call_sample(&mut session.store)?
+            .map_err(|err| anyhow::Error::new(err).context("sample"))?;
+        readings.extend(batch);
+    }
+    let report = session.report("component", Some(0), None, instantiate, run_started.elapsed())?;
+    Ok((readings, report))
+}
+
+/// Map a guest's return into (exit code, trap message).
+fn exit_status(result: Result<std::result::Result<(), ()>>) -> (Option<i32>, Option<String>) {
+    match result {
+        Ok(Ok(())) => (Some(0), None),
+        Ok(Err(())) => (Some(1), None),
+        Err(err) => match err.downcast_ref::<I32Exit>() {
+            Some(exit) => (Some(exit.0), None),
+            None => (None, Some(format!("{:#}", err))),
+        },
+    }
+}
+
+fn wasi_builder(options: &RunOptions) -> Result<WasiCtxBuilder> {
+    let mut builder = WasiCtxBuilder::new();
+    builder.inherit_stdio();
+
+    let program = options.module.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    builder.arg(program).args(&options.args).envs(&options.env);
+    if options.network {
+        builder.inherit_network().allow_ip_name_lookup(true);
+    }
+    for (host, guest) in &options.dirs {
+        builder.preopened_dir(host, guest, DirPerms::all(), FilePerms::all())
+            .with_context(|| format!("preopening {}", Path::new(host).display()))?;
+    }
+    Ok(builder)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::process::Command as Process;
+    use std::thread;
+
+    /// Build the guest for `target` and return its path.
+    fn guest(target: &str) -> PathBuf {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("workspace root");
+        let status = Process::new(env!("CARGO"))
+            .current_dir(root)
+            .args(["build", "--quiet", "-p", "wasm-hello", "--bin", "wasm-hello", "--target", target])
+            .status()
+            .expect("running cargo");
+        assert!(status.success(), "building the guest for {} failed", target);
+        let target_dir = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| root.join("target"), PathBuf::from);
+        target_dir.join(target).join("debug/wasm-hello.wasm")
+    }
+
+    fn component() -> PathBuf {
+        guest("wasm32-wasip2")
+    }
+
+    fn module(args: &[&str]) -> RunOptions {
+        RunOptions { module: guest("wasm32-wasip1"), args: args.iter().map(|arg| arg.to_string()).collect(), ..RunOptions::default() }
+    }
+
+    /// Read one MQTT packet: its first byte and body.
+    fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).ok()?;
+        let first = byte[0];
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte).ok()?;
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).ok()?;
+        Some((first, body))
+    }
+
+    /// MQTT broker stand-in for one QoS 0 session; returns the (topic,
+    /// payload) of every PUBLISH until the client disconnects.
+    fn broker(listener: TcpListener) -> Vec<(String, String)> {
+        let (mut stream, _) = listener.accept().expect("guest connects");
+        let (first, _) = read_packet(&mut stream).expect("CONNECT");
+        assert_eq!(first, 0x10);
+        stream.write_all(&[0x20, 2, 0, 0]).expect("CONNACK");
+        let mut published = Vec::new();
+        while let Some((first, body)) = read_packet(&mut stream) {
+            match first {
+                0x30 => {
+                    let split = 2 + usize::from(u16::from_be_bytes([body[0], body[1]]));
+                    let topic = String::from_utf8(body[2..split].to_vec()).unwrap();
+                    published.push((topic, String::from_utf8(body[split..].to_vec()).unwrap()));
+                }
+                0xe0 => break,
+                other => panic!("unexpected packet 0x{:02x}", other),
+            }
+        }
+        published
+    }
+
+    #[test]
+    fn component_publishes_to_mqtt_over_wasi_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || broker(listener));
+
+        let options = RunOptions {
+            module: component(),
+            args: ["--mqtt-broker", &address, "-n", "3", "-i", "0", "-f", "json"].map(String::from).to_vec(),
+            network: true,
+            ..RunOptions::default()
+        };
+        let report = run(&options).unwrap();
+        assert_eq!((report.kind, report.exit_code, report.trap), ("component", Some(0), None));
+
+        let published = broker.join().unwrap();
+        assert_eq!(published.len(), 3);
+        for (seq, (topic, payload)) in (1..).zip(&published) {
+            assert_eq!(topic, "sensors/temperature");
+            assert!(payload.starts_with(&format!("{{\"seq\":{},\"sensor\":\"temperature\"", seq)), "{}", payload);
+        }
+    }
+
+    #[test]
+    fn runs_a_preview_1_module_and_reports_what_it_used() {
+        let report = run(&module(&["-n", "2", "-i", "0", "-f", "json"])).unwrap();
+        assert_eq!((report.kind, report.exit_code, report.trap.as_deref()), ("module", Some(0), None));
+        assert!(report.fuel_consumed > 0);
+        assert!(report.memory_peak_bytes > 0 && report.memory_peak_bytes % 65_536 == 0, "{}", report.memory_peak_bytes);
+        assert!(report.wall_ms >= report.run_ms);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["kind"], "module");
+        assert_eq!(json["exit_code"], 0);
+        assert_eq!(json["fuel_consumed"], report.fuel_consumed);
+        assert_eq!(json["memory_peak_bytes"], report.memory_peak_bytes);
+        assert_eq!(json["module_bytes"], std::fs::metadata(&report.module).unwrap().len());
+        assert!(json.get("trap").is_none(), "{}", json);
+    }
+
+    #[test]
+    fn reports_the_guest_exit_code_and_traps() {
+        let report = run(&module(&["--no-such-flag"])).unwrap();
+        assert_eq!((report.exit_code, report.trap), (Some(2), None));
+
+        let options = RunOptions { fuel: Some(10_000), ..module(&["-n", "2", "-i", "0"]) };
+        let report = run(&options).unwrap();
+        assert_eq!(report.exit_code, None);
+        assert!(report.trap.as_deref().is_some_and(|trap| trap.contains("fuel")), "{:?}", report.trap);
+        assert!(report.fuel_consumed <= 10_000);
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["exit_code"].is_null());
+        assert_eq!(json["trap"].as_str(), report.trap.as_deref());
+    }
+
+    #[test]
+    fn fails_on_a_missing_module() {
+        let options = RunOptions { module: PathBuf::from("no-such-module.wasm"), ..RunOptions::default() };
+        let err = run(&options).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("reading no-such-module.wasm: "), "{:#}", err);
+    }
+
+    #[test]
+    fn samples_a_configured_component() {
+        let options = RunOptions { module: component(), ..RunOptions::default() };
+        let sensor = SimulatedSensor { name: "boiler".to_string(), unit: "°C".to_string(), base: 60.0, step: 0.5, signal: None };
+        let config = ReporterConfig { sensors: vec![SensorConfig::Simulated(sensor)], clock: ClockPolicy::Flag };
+        let before = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+        let (readings, report) = sample(&options, &config, 3).unwrap();
+        assert_eq!((report.kind, report.exit_code), ("component", Some(0)));
+
+        let seen: Vec<_> = readings.iter().map(|reading| (reading.seq, reading.sensor.as_str(), reading.value)).collect();
+        assert_eq!(seen, [(1, "boiler", 60.5), (2, "boiler", 61.0), (3, "boiler", 61.5)]);
+        for reading in &readings {
+            assert!(matches!(reading.clock, ClockSource::Wall), "{:?}", reading.clock);
+            assert!(reading.time_ms.is_some_and(|time| time >= before), "{:?} before {}", reading.time_ms, before);
+        }
+        assert!(readings.windows(2).all(|pair| pair[0].time_ms <= pair[1].time_ms));
+    }
+
+    #[test]
+    fn configure_rejects_a_sensor_that_cannot_be_opened() {
+        let options = RunOptions { module: component(), ..RunOptions::default() };
+        let sensor = ReplaySensor {
+            name: "boiler".to_string(),
+            unit: "°C".to_string(),
+            path: "no-such-trace.csv".to_string(),
+            source: None,
+            pace: ReplayPace::Fast,
+        };
+        let config = ReporterConfig { sensors: vec![SensorConfig::Replay(sensor)], clock: ClockPolicy::Flag };
+        let err = sample(&options, &config, 1).unwrap_err();
+        match err.downcast_ref::<ReporterError>() {
+            Some(ReporterError::Config(reason)) => assert!(reason.contains("no-such-trace.csv"), "{}", reason),
+            other => panic!("expected a config error, got {:?}: {:#}", other, err),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result, bail};
+use wasm_hello_host::{ClockPolicy, ReporterConfig, RunOptions};
+
+const USAGE: &str = "\
+Usage: wasm-hello-host [OPTIONS] <MODULE> [-- <ARGS>...]
+
+Runs a WASI module in embedded wasmtime and prints a JSON resource report.
+With --sample, calls the component's margo:sensor-reporter interface instead
+of running it and prints the readings as JSON Lines on stdout.
+
+Options:
+      --dir <HOST::GUEST>   Preopen a host directory at a guest path (repeatable)
+      --env <KEY=VALUE>     Set a guest environment variable (repeatable)
+      --fuel <N>            Fuel available to the guest [default: unlimited]
+      --net                 Give the guest the host's network (wasm32-wasip2 components only)
+      --sample <N>          Call reporter.sample N times (wasm32-wasip2 components only)
+      --report <PATH>       Write the JSON report to a file instead of stderr
+  -h, --help                Print this help";
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(|args| run(&args)) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Args {
+    options: RunOptions,
+    report: Option<PathBuf>,
+    sample: Option<u64>,
+}
+
+fn run(args: &Args) -> Result<ExitCode> {
+    let report = match args.sample {
+        Some(count) => {
+            let config = ReporterConfig { sensors: Vec::new(), clock: ClockPolicy::Flag };
+            let (readings, report) = wasm_hello_host::sample(&args.options, &config, count)?;
+            let mut stdout = io::stdout().lock();
+            for reading in &readings {
+                serde_json::to_writer(&mut stdout, reading)?;
+                writeln!(stdout)?;
+            }
+            report
+        }
+        None => wasm_hello_host::run(&args.options)?,
+    };
+
+    let mut out: Box<dyn Write> = match &args.report {
+        Some(path) => Box::new(File::create(path).with_context(|| format!("creating {}", path.display()))?),
+        None => Box::new(io::stderr()),
+    };
+    serde_json::to_writer_pretty(&mut out, &report)?;
+    writeln!(out)?;
+
+    // Mirror the guest's exit status so scripts can check both.
+    Ok(match report.exit_code {
+        Some(code) => ExitCode::from(u8::try_from(code).unwrap_or(1)),
+        None => ExitCode::FAILURE,
+    })
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut options = RunOptions::default();
+    let mut report = None;
+    let mut sample = None;
+    let mut module = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--dir" => {
+                let spec = value()?;
+                let (host, guest) = spec.split_once("::").unwrap_or((&spec, &spec));
+                options.dirs.push((PathBuf::from(host), guest.to_string()));
+            }
+            "--env" => {
+                let spec = value()?;
+                let (key, val) = spec.split_once('=').with_context(|| format!("expected KEY=VALUE, got '{}'", spec))?;
+                options.env.push((key.to_string(), val.to_string()));
+            }
+            "--net" => options.network = true,
+            "--fuel" => options.fuel = Some(value()?.parse().context("invalid --fuel")?),
+            "--sample" => sample = Some(value()?.parse().context("invalid --sample")?),
+            "--report" => report = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--" => {
+                options.args.extend(args.by_ref());
+            }
+            flag if flag.starts_with('-') && module.is_none() => bail!("unexpected argument '{}'\n\n{}", flag, USAGE),
+            _ if module.is_none() => module = Some(PathBuf::from(arg)),
+            _ => options.args.push(arg),
+        }
+    }
+    options.module = module.with_context(|| format!("missing <MODULE>\n\n{}", USAGE))?;
+    Ok(Args { options, report, sample })
+}